@@ -32,17 +32,26 @@ actual object BlueManager {
     actual val errorSharedFlow = _errorSharedFlow.asSharedFlow()
 
     actual enum class BluetoothState {
-        Enabled,
-        Disabled,
+        BackendUnavailable,
+        NoAdapter,
+        SoftBlocked,
+        HardBlocked,
+        PoweredOff,
+        TurningOn,
+        On,
+        TurningOff,
+        Discovering,
     }
 
-    private val _isBluetoothEnabled = MutableStateFlow(BluetoothState.Disabled)
+    private val _isBluetoothEnabled = MutableStateFlow(BluetoothState.PoweredOff)
     actual val bluetoothState = _isBluetoothEnabled.asStateFlow()
 
     private fun updateBluetoothEnabled() = _isBluetoothEnabled.update {
-        when (bluetoothAdapter.isEnabled) {
-            true -> BluetoothState.Enabled
-            false -> BluetoothState.Disabled
+        when (bluetoothAdapter.state) {
+            BluetoothAdapter.STATE_TURNING_ON -> BluetoothState.TurningOn
+            BluetoothAdapter.STATE_TURNING_OFF -> BluetoothState.TurningOff
+            BluetoothAdapter.STATE_ON -> if (bluetoothAdapter.isDiscovering) BluetoothState.Discovering else BluetoothState.On
+            else -> BluetoothState.PoweredOff
         }
     }
 
@@ -99,9 +108,14 @@ actual object BlueManager {
             when (action) {
                 BluetoothAdapter.ACTION_STATE_CHANGED -> {
                     updateBluetoothEnabled()
-                    Logger.i { "onReceive(): Bluetooth state changed to enabled=${bluetoothAdapter.isEnabled}" }
+                    Logger.i { "onReceive(): Bluetooth state changed to ${bluetoothState.value}" }
+                }
+                BluetoothAdapter.ACTION_DISCOVERY_STARTED -> {
+                    updateBluetoothEnabled()
+                    Logger.i { "onReceive(): Bluetooth discovery started" }
                 }
                 BluetoothAdapter.ACTION_DISCOVERY_FINISHED -> {
+                    updateBluetoothEnabled()
                     onDiscoveryStopped()
                     Logger.i { "onReceive(): Bluetooth discovery finished" }
                 }
@@ -124,6 +138,7 @@ actual object BlueManager {
     fun registerBluetoothBroadcastReceiver(context: Context) {
         val intentFilter = IntentFilter().apply {
             addAction(BluetoothAdapter.ACTION_STATE_CHANGED)
+            addAction(BluetoothAdapter.ACTION_DISCOVERY_STARTED)
             addAction(BluetoothAdapter.ACTION_DISCOVERY_FINISHED)
             addAction(BluetoothDevice.ACTION_FOUND)
         }
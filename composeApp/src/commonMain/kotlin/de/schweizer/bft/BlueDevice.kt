@@ -1,3 +1,8 @@
 package de.schweizer.bft
 
 data class BlueDevice(val deviceName: String, val deviceAddress: String)
+
+/**
+ * Changed properties of a discovered device, `null` properties did not change.
+ */
+data class BlueDeviceUpdate(val deviceAddress: String, val deviceName: String?, val rssi: Int?)
@@ -4,5 +4,56 @@ sealed class BlueError(open val msg: String) {
     data class Generic(override val msg: String) : BlueError(msg)
     data object DiscoveryNotPossible : BlueError("Discovery not possible")
     data object AdapterNotAvailable : BlueError("No Bluetooth adapter available for this device")
+    data class FileTooLarge(val size: Long, val maxSize: Long) : BlueError("File of $size bytes exceeds the limit of $maxSize bytes")
+    data class InsufficientSpace(val required: Long, val available: Long) :
+        BlueError("Not enough space to receive file: $required bytes required, $available bytes available")
+    data class VerificationFailed(override val msg: String) : BlueError(msg)
+    data class FileQuarantined(val fileName: String, val reason: String) :
+        BlueError("$fileName was quarantined: $reason")
+    data class Internal(override val msg: String) : BlueError("Internal error: $msg")
     data object Unknown : BlueError("An unknown error occurred")
+
+    /**
+     * An operation on the adapter, a device, a connection or a file failed. [operation] is what was attempted,
+     * [device] the address of the remote device if one was involved and [detail] the message of BlueZ or the system.
+     */
+    sealed interface Failure {
+        val operation: String?
+        val device: String?
+        val detail: String
+    }
+
+    data class NotPowered(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Bluetooth adapter is powered off", operation, device, detail)), Failure
+    data class NotReady(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Bluetooth is not ready", operation, device, detail)), Failure
+    data class AuthenticationFailed(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Authentication failed", operation, device, detail)), Failure
+    data class AuthenticationRejected(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Authentication was rejected", operation, device, detail)), Failure
+    data class ConnectionRefused(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Connection was refused", operation, device, detail)), Failure
+    data class Timeout(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Operation timed out", operation, device, detail)), Failure
+    data class InProgress(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Operation is already in progress", operation, device, detail)), Failure
+    data class NotSupported(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Operation is not supported", operation, device, detail)), Failure
+    data class DoesNotExist(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Device or service does not exist", operation, device, detail)), Failure
+    data class Io(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Input/output error", operation, device, detail)), Failure
+    data class ProtocolViolation(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Unexpected data received", operation, device, detail)), Failure
+    data class PeerRejected(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Request was rejected by the other device", operation, device, detail)), Failure
+    data class NotPermitted(override val operation: String?, override val device: String?, override val detail: String) :
+        BlueError(describe("Operation is not permitted", operation, device, detail)), Failure
+}
+
+private fun describe(summary: String, operation: String?, device: String?, detail: String) = buildString {
+    append(summary)
+    operation?.let { append(" during $it") }
+    device?.let { append(" with $it") }
+    if (detail.isNotEmpty()) append(": $detail")
 }
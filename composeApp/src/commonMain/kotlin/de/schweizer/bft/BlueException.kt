@@ -0,0 +1,6 @@
+package de.schweizer.bft
+
+/**
+ * Thrown by suspending [BlueManager] calls when the native operation failed with [error].
+ */
+class BlueException(val error: BlueError) : Exception(error.msg)
@@ -9,8 +9,15 @@ expect object BlueManager {
     val errorSharedFlow: SharedFlow<BlueError>
 
     enum class BluetoothState {
-        Enabled,
-        Disabled,
+        BackendUnavailable,
+        NoAdapter,
+        SoftBlocked,
+        HardBlocked,
+        PoweredOff,
+        TurningOn,
+        On,
+        TurningOff,
+        Discovering,
     }
 
     val bluetoothState: StateFlow<BluetoothState>
@@ -25,3 +32,6 @@ expect object BlueManager {
     fun onDeviceDiscovered(deviceName: String, deviceAddress: String)
     fun onError(error: BlueError)
 }
+
+val BlueManager.BluetoothState.isEnabled: Boolean
+    get() = this == BlueManager.BluetoothState.On || this == BlueManager.BluetoothState.Discovering
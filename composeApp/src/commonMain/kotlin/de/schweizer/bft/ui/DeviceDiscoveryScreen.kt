@@ -29,6 +29,7 @@ import cafe.adriel.voyager.core.screen.Screen
 import cafe.adriel.voyager.navigator.LocalNavigator
 import cafe.adriel.voyager.navigator.currentOrThrow
 import de.schweizer.bft.BlueManager
+import de.schweizer.bft.isEnabled
 import de.schweizer.bft.ui.DeviceDiscoveryViewModel.DeviceDiscoveryState
 import de.schweizer.bft.ui.theme.Spacings
 import de.schweizer.bft.ui.theme.VerticalSpacerM
@@ -51,7 +52,7 @@ class DeviceDiscoveryScreen : Screen {
                 BlueManager.discoveryStoppedSharedFlow.onEach { viewModel.onDiscoveryStopped() }.launchIn(this)
                 BlueManager.errorSharedFlow.onEach { viewModel.onError(it) }.launchIn(this)
                 BlueManager.bluetoothState.onEach {
-                    if (it == BlueManager.BluetoothState.Disabled) {
+                    if (!it.isEnabled) {
                         // TODO: Cancel any ongoing file transfers
                         viewModel.cancelDiscovery()
                         navigator.pop()
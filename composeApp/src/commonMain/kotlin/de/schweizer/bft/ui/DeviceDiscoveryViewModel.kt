@@ -4,6 +4,7 @@ import androidx.lifecycle.ViewModel
 import co.touchlab.kermit.Logger
 import de.schweizer.bft.BlueDevice
 import de.schweizer.bft.BlueError
+import de.schweizer.bft.BlueException
 import de.schweizer.bft.BlueManager
 import de.schweizer.bft.PermissionManager
 import kotlinx.coroutines.flow.MutableStateFlow
@@ -32,7 +33,11 @@ class DeviceDiscoveryViewModel : ViewModel() {
     suspend fun discoverDevices() {
         _uiState.update { DeviceDiscoveryState.Loading }
         _discoveredDevices.update { linkedSetOf() }
-        BlueManager.discover()
+        try {
+            BlueManager.discover()
+        } catch (e: BlueException) {
+            onError(e.error)
+        }
     }
 
     fun cancelDiscovery() {
@@ -20,6 +20,7 @@ import cafe.adriel.voyager.navigator.LocalNavigator
 import cafe.adriel.voyager.navigator.currentOrThrow
 import de.schweizer.bft.BlueManager
 import de.schweizer.bft.PermissionManager
+import de.schweizer.bft.isEnabled
 import de.schweizer.bft.ui.theme.Spacings
 import de.schweizer.bft.ui.theme.VerticalSpacerL
 import de.schweizer.bft.ui.theme.VerticalSpacerS
@@ -28,7 +29,7 @@ class HomeScreen : Screen {
     @Composable
     override fun Content() {
         val bluetoothState by BlueManager.bluetoothState.collectAsState()
-        val isBluetoothEnabled = bluetoothState == BlueManager.BluetoothState.Enabled
+        val isBluetoothEnabled = bluetoothState.isEnabled
         val deniedPermissions by PermissionManager.deniedPermissions.collectAsState()
         val areAllPermissionsGranted = deniedPermissions.isEmpty()
 
@@ -44,7 +45,10 @@ class HomeScreen : Screen {
             VerticalSpacerL()
             when {
                 !areAllPermissionsGranted -> GrantPermissionsNotice()
-                !isBluetoothEnabled -> EnableBluetoothNotice()
+                bluetoothState == BlueManager.BluetoothState.BackendUnavailable -> BackendUnavailableNotice()
+                bluetoothState == BlueManager.BluetoothState.HardBlocked -> HardBlockedNotice()
+                bluetoothState == BlueManager.BluetoothState.NoAdapter -> NoAdapterNotice()
+                !isBluetoothEnabled -> EnableBluetoothNotice(bluetoothState)
                 else -> StartDiscoveryButton()
             }
         }
@@ -93,7 +97,39 @@ class HomeScreen : Screen {
     }
 
     @Composable
-    private fun ColumnScope.EnableBluetoothNotice() {
+    private fun ColumnScope.BackendUnavailableNotice() {
+        Text(
+            modifier = Modifier.align(Alignment.CenterHorizontally),
+            text = "The Bluetooth service is not running.\n" +
+                "Please start bluetoothd, the app connects automatically once it is available",
+            style = MaterialTheme.typography.bodyMedium,
+            textAlign = TextAlign.Center,
+        )
+    }
+
+    @Composable
+    private fun ColumnScope.HardBlockedNotice() {
+        Text(
+            modifier = Modifier.align(Alignment.CenterHorizontally),
+            text = "Bluetooth is turned off by a hardware switch.\n" +
+                "Please use the wireless switch or key combination of your device to turn it on",
+            style = MaterialTheme.typography.bodyMedium,
+            textAlign = TextAlign.Center,
+        )
+    }
+
+    @Composable
+    private fun ColumnScope.NoAdapterNotice() {
+        Text(
+            modifier = Modifier.align(Alignment.CenterHorizontally),
+            text = "No Bluetooth adapter found.\nPlease plug in a Bluetooth adapter",
+            style = MaterialTheme.typography.bodyMedium,
+            textAlign = TextAlign.Center,
+        )
+    }
+
+    @Composable
+    private fun ColumnScope.EnableBluetoothNotice(bluetoothState: BlueManager.BluetoothState) {
         Text(
             modifier = Modifier.align(Alignment.CenterHorizontally),
             text = "Bluetooth must be enabled for this app to work.\nPlease enable Bluetooth",
@@ -103,6 +139,7 @@ class HomeScreen : Screen {
         VerticalSpacerS()
         Button(
             modifier = Modifier.align(Alignment.CenterHorizontally),
+            enabled = bluetoothState != BlueManager.BluetoothState.TurningOn,
             onClick = { BlueManager.requestEnableBluetooth() },
         ) {
             Text("Enable Bluetooth")
@@ -7,10 +7,17 @@ import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.asSharedFlow
 import kotlinx.coroutines.flow.asStateFlow
 import kotlinx.coroutines.flow.update
+import kotlinx.coroutines.future.await
+import java.util.concurrent.CompletableFuture
+import kotlin.coroutines.cancellation.CancellationException
 
 actual object BlueManager {
     private val _deviceDiscoveredSharedFlow = MutableSharedFlow<BlueDevice>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
     actual val deviceDiscoveredSharedFlow = _deviceDiscoveredSharedFlow.asSharedFlow()
+    private val _deviceUpdatedSharedFlow = MutableSharedFlow<BlueDeviceUpdate>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val deviceUpdatedSharedFlow = _deviceUpdatedSharedFlow.asSharedFlow()
+    private val _deviceLostSharedFlow = MutableSharedFlow<String>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val deviceLostSharedFlow = _deviceLostSharedFlow.asSharedFlow()
     private val _discoveryStoppedSharedFlow = MutableSharedFlow<Unit>(extraBufferCapacity = 1, onBufferOverflow = BufferOverflow.DROP_OLDEST)
     actual val discoveryStoppedSharedFlow = _discoveryStoppedSharedFlow.asSharedFlow()
 
@@ -18,18 +25,98 @@ actual object BlueManager {
     actual val errorSharedFlow = _errorSharedFlow.asSharedFlow()
 
     actual enum class BluetoothState {
-        Enabled,
-        Disabled,
+        BackendUnavailable,
+        NoAdapter,
+        SoftBlocked,
+        HardBlocked,
+        PoweredOff,
+        TurningOn,
+        On,
+        TurningOff,
+        Discovering,
     }
 
-    private val _isBluetoothEnabled = MutableStateFlow(BluetoothState.Disabled)
+    private val _isBluetoothEnabled = MutableStateFlow(BluetoothState.NoAdapter)
     actual val bluetoothState = _isBluetoothEnabled.asStateFlow()
 
+    /**
+     * Seconds until the adapter stops being discoverable, `null` if it is not discoverable.
+     */
+    private val _discoverableSecondsLeft = MutableStateFlow<Int?>(null)
+    val discoverableSecondsLeft = _discoverableSecondsLeft.asStateFlow()
+
     actual external fun init()
-    actual external suspend fun discover()
+
+    /**
+     * Discovers devices until the discovery times out or is cancelled.
+     *
+     * @throws BlueException if the discovery could not be started
+     */
+    actual suspend fun discover() {
+        val result = CompletableFuture<Unit?>()
+        discoverAsync(result)
+        try {
+            result.await()
+        } catch (e: CancellationException) {
+            cancelDiscovery()
+            throw e
+        }
+    }
+    private external fun discoverAsync(result: CompletableFuture<Unit?>)
+
+    /**
+     * Returns the UUIDs of the services the device with [deviceAddr] announced, without connecting to it.
+     *
+     * @throws BlueException if the device is unknown
+     */
+    suspend fun getServices(deviceAddr: String): List<String> {
+        val result = CompletableFuture<Array<String>>()
+        getServicesAsync(deviceAddr, result)
+        return result.await().toList()
+    }
+    private external fun getServicesAsync(deviceAddr: String, result: CompletableFuture<Array<String>>)
+
+    /**
+     * Writes a zip to [path] for a problem report: the recent native logs, adapter properties, paired devices,
+     * BlueZ and kernel version, rfkill state, the app config with secrets redacted and the latest transfers.
+     * Parts that cannot be collected contain the error instead.
+     *
+     * @throws BlueException if the zip could not be written
+     */
+    suspend fun exportDiagnostics(path: String) {
+        val result = CompletableFuture<Unit?>()
+        exportDiagnosticsAsync(path, result)
+        result.await()
+    }
+    private external fun exportDiagnosticsAsync(path: String, result: CompletableFuture<Unit?>)
+
     actual external fun connectToDevice(deviceAddr: String)
     actual external fun cancelDiscovery()
     actual external fun requestEnableBluetooth()
+    external fun requestDisableBluetooth()
+
+    /**
+     * Returns all Bluetooth adapters as JSON array with `name`, `address`, `alias`, `powered`, `discoverable`,
+     * `modalias` and `selected` of every adapter.
+     */
+    external fun listAdapters(): String
+
+    /**
+     * Uses the adapter with the given BlueZ name (e.g. `hci0`) and prefers it whenever it is plugged in.
+     */
+    external fun selectAdapter(name: String)
+
+    /**
+     * Makes the selected adapter discoverable and pairable for [durationSecs] seconds (at most 30 minutes),
+     * so new devices can find it and send files.
+     */
+    external fun makeDiscoverable(durationSecs: Int)
+    external fun stopDiscoverable()
+
+    /**
+     * Sets the name other devices see for the selected adapter.
+     */
+    external fun setAdapterAlias(alias: String)
 
     @JvmStatic
     actual fun onDiscoveryStopped() {
@@ -43,19 +130,41 @@ actual object BlueManager {
         Logger.i { "BlueManager::onDeviceDiscovered(): deviceName=$deviceName, deviceAddress=$deviceAddress" }
     }
 
+    /**
+     * Called when the name or signal strength of a discovered device changed, [rssi] is `Int.MIN_VALUE` if unchanged.
+     */
+    @JvmStatic
+    fun onDeviceUpdated(deviceAddress: String, deviceName: String?, rssi: Int) {
+        _deviceUpdatedSharedFlow.tryEmit(BlueDeviceUpdate(deviceAddress, deviceName, rssi.takeIf { it != Int.MIN_VALUE }))
+    }
+
+    @JvmStatic
+    fun onDeviceLost(deviceAddress: String) {
+        _deviceLostSharedFlow.tryEmit(deviceAddress)
+        Logger.i { "BlueManager::onDeviceLost(): deviceAddress=$deviceAddress" }
+    }
+
     @JvmStatic
     actual fun onError(error: BlueError) {
         _errorSharedFlow.tryEmit(error)
         Logger.i { "BlueManager::onError: $error" }
     }
 
+    @JvmStatic
+    fun onDiscoverableCountdown(remainingSecs: Int) {
+        _discoverableSecondsLeft.update { remainingSecs }
+    }
+
+    @JvmStatic
+    fun onDiscoverableEnded() {
+        _discoverableSecondsLeft.update { null }
+        Logger.i { "BlueManager::onDiscoverableEnded()" }
+    }
+
     // TODO: Try to make private and see if still callable from native code
     @JvmStatic
-    fun updateBluetoothEnabled(enabled: Boolean) = _isBluetoothEnabled.update {
-        when (enabled) {
-            true -> BluetoothState.Enabled
-            false -> BluetoothState.Disabled
-        }
+    fun updateBluetoothState(state: String) = _isBluetoothEnabled.update {
+        BluetoothState.valueOf(state)
     }
 
     init {
@@ -1,10 +1,40 @@
 package de.schweizer.bft
 
+import co.touchlab.kermit.Logger
+import co.touchlab.kermit.Severity
 import kotlin.jvm.JvmStatic
 
 object NativeLogger {
+    /**
+     * Installs the native logger. Besides forwarding to [onLog], records are written to `logs/bft.log` in the app
+     * data directory, which is rotated at 5 MiB with 4 old files kept. Records carry the `adapter`, `device` and
+     * `transfer_id` they belong to, so e.g. `grep transfer_id=42` extracts the full history of one transfer.
+     * Setting the environment variable `BFT_LOG_FORMAT=json` writes one JSON object per line instead.
+     */
     @JvmStatic
-    external fun init(string: String)
+    external fun init(level: String)
+
+    /**
+     * Changes the level of the native library while the app is running, e.g. to [LogLevel.DEBUG] to reproduce a bug.
+     */
+    @JvmStatic
+    external fun setLevel(level: String)
+
+    /**
+     * Receives the log records of the native library in batches from a background thread.
+     * [target] is the Rust module and [location] the `file:line` of the log statement if known.
+     */
+    @JvmStatic
+    fun onLog(level: String, target: String, message: String, location: String?) {
+        val severity = when (LogLevel.valueOf(level)) {
+            LogLevel.ERROR -> Severity.Error
+            LogLevel.WARN -> Severity.Warn
+            LogLevel.INFO -> Severity.Info
+            LogLevel.DEBUG -> Severity.Debug
+            LogLevel.TRACE -> Severity.Verbose
+        }
+        Logger.log(severity, target, null, if (location == null) message else "$message ($location)")
+    }
 }
 
 enum class LogLevel {
@@ -0,0 +1,31 @@
+package de.schweizer.bft
+
+import kotlin.jvm.JvmStatic
+
+object TransferHistory {
+    /**
+     * Returns the recorded transfers as a JSON array, most recent first.
+     * [peerAddress] restricts the result to a single device, [limit] <= 0 returns all entries.
+     */
+    @JvmStatic
+    external fun query(peerAddress: String?, limit: Int): String?
+
+    @JvmStatic
+    external fun delete(id: Long): Boolean
+
+    @JvmStatic
+    external fun clear()
+
+    @JvmStatic
+    private external fun export(path: String, format: String)
+
+    /**
+     * Writes all recorded transfers to [path], most recent first.
+     */
+    fun export(path: String, format: HistoryExportFormat) = export(path, format.name)
+}
+
+enum class HistoryExportFormat {
+    CSV,
+    JSON,
+}
@@ -0,0 +1,223 @@
+package de.schweizer.bft
+
+import co.touchlab.kermit.Logger
+import kotlinx.coroutines.channels.BufferOverflow
+import kotlinx.coroutines.flow.MutableSharedFlow
+import kotlinx.coroutines.flow.asSharedFlow
+import kotlinx.coroutines.future.await
+import java.util.concurrent.CompletableFuture
+import kotlin.jvm.JvmStatic
+
+object TransferManager {
+    private val _watchFolderSharedFlow = MutableSharedFlow<WatchFolderEvent>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val watchFolderSharedFlow = _watchFolderSharedFlow.asSharedFlow()
+    private val _textReceivedSharedFlow = MutableSharedFlow<TextMessage>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val textReceivedSharedFlow = _textReceivedSharedFlow.asSharedFlow()
+    private val _transferProgressSharedFlow = MutableSharedFlow<TransferProgress>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val transferProgressSharedFlow = _transferProgressSharedFlow.asSharedFlow()
+    private val _transferOfferedSharedFlow = MutableSharedFlow<TransferOffer>(extraBufferCapacity = 10, onBufferOverflow = BufferOverflow.DROP_OLDEST)
+    val transferOfferedSharedFlow = _transferOfferedSharedFlow.asSharedFlow()
+
+    /**
+     * Sends the file at [path] to the paired device [targetAddress], which has to run the app as well.
+     * Returns once the device stored the file, progress is reported via [transferProgressSharedFlow].
+     *
+     * @throws BlueException if the device could not be reached, rejected the file or the connection failed
+     */
+    suspend fun sendFile(targetAddress: String, path: String) {
+        val result = CompletableFuture<Unit?>()
+        sendFileAsync(targetAddress, path, result)
+        result.await()
+    }
+
+    @JvmStatic
+    private external fun sendFileAsync(targetAddress: String, path: String, result: CompletableFuture<Unit?>)
+
+    /**
+     * Sends a text message to the paired device [targetAddress], which reports it via [textReceivedSharedFlow].
+     * The text is limited to 64 KiB.
+     *
+     * @throws BlueException if the device could not be reached, the text is too long or the connection failed
+     */
+    suspend fun sendText(targetAddress: String, text: String, kind: MessageKind) {
+        val result = CompletableFuture<Unit?>()
+        sendTextAsync(targetAddress, text, kind.name, result)
+        result.await()
+    }
+
+    @JvmStatic
+    private external fun sendTextAsync(targetAddress: String, text: String, kind: String, result: CompletableFuture<Unit?>)
+
+    /**
+     * Limits the bandwidth of all transfers combined and of every single transfer.
+     * Active transfers share the global limit fairly. A limit of 0 means unlimited.
+     */
+    @JvmStatic
+    external fun setBandwidthLimit(globalBytesPerSec: Long, perTransferBytesPerSec: Long)
+
+    /**
+     * Overrides the per-transfer limit of a running transfer, a negative value restores the default limit.
+     */
+    @JvmStatic
+    external fun setTransferBandwidthLimit(transferId: Long, bytesPerSec: Long)
+
+    /**
+     * Configures where received files are stored and is persisted in the app config.
+     * [maxFileSize] and [quota] are in bytes, 0 means no limit.
+     */
+    @JvmStatic
+    external fun configureReceiving(
+        downloadRoot: String,
+        perSenderSubfolders: Boolean,
+        conflictMode: String,
+        maxFileSize: Long,
+        quota: Long,
+    )
+
+    /**
+     * Configures which of the file attributes sent along with a file are applied to the received file.
+     * Setuid, setgid and sticky bit are dropped unless [allowUnsafeModeBits] is set.
+     */
+    @JvmStatic
+    external fun configureMetadata(
+        preserveMtime: Boolean,
+        preservePermissions: Boolean,
+        preserveXattrs: Boolean,
+        allowUnsafeModeBits: Boolean,
+    )
+
+    /**
+     * Auto-accept rules as JSON array, evaluated in order for every incoming offer. Example:
+     * `[{"name": "My phone", "sender": "AA:BB:CC:DD:EE:FF", "extensions": ["jpg"], "max_size": 10000000,
+     *   "time_window": {"start": "08:00", "end": "18:00"}, "action": "accept"}]`
+     * Possible actions are `accept`, `reject` and `ask`. `sender` is matched against the Bluetooth address only.
+     * A time window whose start equals its end covers the whole day.
+     */
+    @JvmStatic
+    external fun getAutoAcceptRules(): String
+
+    @JvmStatic
+    external fun setAutoAcceptRules(rules: String)
+
+    /**
+     * Answers an offer reported via [transferOfferedSharedFlow]. Offers not answered within 60 seconds are rejected.
+     */
+    @JvmStatic
+    external fun respondToOffer(transferId: Long, accept: Boolean)
+
+    /**
+     * Command run after a file has been received and verified, `null` removes the hook.
+     * The placeholders `{path}`, `{sender}`, `{sender_name}` and `{sha256}` in [args] are replaced and also
+     * passed as `BFT_FILE_PATH`, `BFT_SENDER`, `BFT_SENDER_NAME` and `BFT_SHA256` environment variables.
+     * The command is killed after [timeoutSecs], its output ends up in the log.
+     * File and sender name are chosen by the peer, so the hook must not hand them to `sh -c`.
+     */
+    @JvmStatic
+    external fun setPostReceiveHook(command: String?, args: Array<String>, timeoutSecs: Long)
+
+    /**
+     * Scanner received files have to pass before they leave quarantine, `null` disables scanning. Examples:
+     * `{"type": "clamd", "socket_path": "/run/clamav/clamd.ctl"}` or
+     * `{"type": "command", "command": "my-scanner", "args": ["--quiet"]}`, where exit code 0 means clean
+     * and 1 means infected. Flagged files are reported as [BlueError.FileQuarantined].
+     */
+    @JvmStatic
+    external fun setContentScanner(scanner: String?)
+
+    /**
+     * Sends new files in [path] to the paired device [targetAddress] once they stopped changing.
+     * Sent files are moved to the `sent` subfolder, progress is reported via [watchFolderSharedFlow].
+     * `null` stops watching.
+     */
+    @JvmStatic
+    external fun setWatchFolder(path: String?, targetAddress: String?)
+
+    /**
+     * Folders kept in sync with the same folder on a paired device as JSON array, e.g.
+     * `[{"path": "/home/me/Shared", "peer_address": "AA:BB:CC:DD:EE:FF"}]`.
+     * Changes and deletions are exchanged in both directions. If both sides changed a file, both versions are kept
+     * and the older one gets a `(conflict <device>)` suffix.
+     * Both devices match the folders by their name. The folders are synced every few minutes while the device is in
+     * range and its app is running.
+     */
+    @JvmStatic
+    external fun getSyncFolders(): String
+
+    @JvmStatic
+    external fun setSyncFolders(syncFolders: String)
+
+    /**
+     * Writes every protocol frame of a transfer with direction, timestamp, type, length and payload to
+     * `captures/transfer-<id>.jsonl` in the app data directory. Of file data only the first [maxPayloadBytes]
+     * bytes of every frame are kept.
+     * Off by default, as payloads contain file data and text messages. A capture can be inspected and replayed
+     * against the receiver with `cargo run --bin replay -- <capture file>`.
+     */
+    @JvmStatic
+    external fun setFrameCapture(enabled: Boolean, maxPayloadBytes: Int)
+
+    @JvmStatic
+    fun onWatchFolderStatus(path: String, status: String, message: String?) {
+        _watchFolderSharedFlow.tryEmit(WatchFolderEvent(path, WatchFolderStatus.valueOf(status), message))
+        Logger.i { "TransferManager::onWatchFolderStatus(): path=$path, status=$status, message=$message" }
+    }
+
+    /**
+     * Called for text messages sent by a peer. Nothing is written to disk, the UI offers to copy or open the text.
+     */
+    @JvmStatic
+    fun onTextReceived(sender: String, text: String, kind: String) {
+        _textReceivedSharedFlow.tryEmit(TextMessage(sender, text, MessageKind.valueOf(kind)))
+        Logger.i { "TransferManager::onTextReceived(): sender=$sender, kind=$kind, length=${text.length}" }
+    }
+
+    /**
+     * Called for offers no auto-accept rule decided, the user has to answer them with [respondToOffer].
+     */
+    @JvmStatic
+    fun onTransferOffered(transferId: Long, sender: String, senderName: String?, fileName: String, size: Long) {
+        _transferOfferedSharedFlow.tryEmit(TransferOffer(transferId, sender, senderName, fileName, size))
+        Logger.i { "TransferManager::onTransferOffered(): transferId=$transferId, sender=$sender, fileName=$fileName, size=$size" }
+    }
+
+    @JvmStatic
+    fun onTransferProgress(transferId: Long, transferred: Long, total: Long) {
+        _transferProgressSharedFlow.tryEmit(TransferProgress(transferId, transferred, total))
+    }
+
+    fun configureReceiving(
+        downloadRoot: String,
+        perSenderSubfolders: Boolean = false,
+        conflictMode: ConflictMode = ConflictMode.RenameWithSuffix,
+        maxFileSize: Long = 0,
+        quota: Long = 0,
+    ) = configureReceiving(downloadRoot, perSenderSubfolders, conflictMode.name, maxFileSize, quota)
+}
+
+enum class ConflictMode {
+    RenameWithSuffix,
+    Overwrite,
+    Skip,
+    KeepBoth,
+}
+
+enum class WatchFolderStatus {
+    Detected,
+    Queued,
+    Sent,
+    Failed,
+}
+
+data class WatchFolderEvent(val path: String, val status: WatchFolderStatus, val message: String?)
+
+enum class MessageKind {
+    Text,
+    Url,
+    Clipboard,
+}
+
+data class TextMessage(val sender: String, val text: String, val kind: MessageKind)
+
+data class TransferOffer(val transferId: Long, val sender: String, val senderName: String?, val fileName: String, val size: Long)
+
+data class TransferProgress(val transferId: Long, val transferred: Long, val total: Long)
@@ -2,6 +2,7 @@ package de.schweizer.bft.ui
 
 import androidx.compose.runtime.Composable
 import cafe.adriel.voyager.navigator.Navigator
+import de.schweizer.bft.BlueManager
 import de.schweizer.bft.LogLevel
 import de.schweizer.bft.NativeLogger
 import java.nio.file.Paths
@@ -18,6 +19,13 @@ class BftApp {
         @JvmStatic
         private external fun init()
 
+        /**
+         * Cancels discovery, stops watching the watch folder and all background tasks of the native backend
+         * and releases the BlueZ session. [start] brings the backend back, e.g. when a window is created again.
+         */
+        @JvmStatic
+        external fun shutdown()
+
         init {
             loadLibraries()
 
@@ -25,6 +33,14 @@ class BftApp {
             NativeLogger.init(LogLevel.INFO.name)
         }
 
+        /**
+         * Starts the native backend again after [shutdown], does nothing while it is running.
+         */
+        fun start() {
+            init()
+            BlueManager.init()
+        }
+
         @Suppress("UnsafeDynamicallyLoadedCode")
         private fun loadLibraries() {
             L.i { "Loading libraries" }
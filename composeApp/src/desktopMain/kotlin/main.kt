@@ -1,10 +1,20 @@
+import androidx.compose.runtime.LaunchedEffect
 import androidx.compose.ui.window.Window
 import androidx.compose.ui.window.application
 import de.schweizer.bft.ui.BftApp
 import de.schweizer.bft.ui.theme.BftAppTheme
 
 fun main() = application {
-    Window(onCloseRequest = ::exitApplication) {
+    Window(
+        onCloseRequest = {
+            BftApp.shutdown()
+            exitApplication()
+        },
+    ) {
+        // Brings the backend back if an earlier window shut it down
+        LaunchedEffect(Unit) {
+            BftApp.start()
+        }
         BftAppTheme {
             BftApp().run()
         }
@@ -10,7 +10,6 @@ use std::path::MAIN_SEPARATOR_STR;
 use phf::phf_map;
 
 use serde::Serialize;
-use toml;
 use util::{run_command, CommandConfig};
 
 static ANDROID_NDK_VERSION: &str = "26.1.10909125";
@@ -139,7 +138,7 @@ fn create_targets_config_file() {
     }
 
     config_file
-        .write(b"\n")
+        .write_all(b"\n")
         .expect("Writting newline should not fail");
 
     let desktop_targets = desktop_targets();
@@ -48,7 +48,7 @@ fn publish_jni_libs_to_project() {
     for target in build::DESKTOP_TARGET_ABI_CONIG.keys() {
         let kmp_target_dir = build::DESKTOP_TARGET_ABI_CONIG
             .get(target)
-            .expect(format!("Target: {} not available", target).as_str());
+            .unwrap_or_else(|| panic!("Target: {} not available", target));
         let crate_lib_file = crate_lib_file(&rust_target_dir, target);
         let jni_libs_file = jni_libs_file(project_dir, kmp_target_dir, "desktopMain");
 
@@ -20,7 +20,10 @@ fn release_targets() {
             command: "cargo",
             args: vec!["build", "--target", target, "--release"],
         };
-        util::run_command(&command);
+        let status = util::run_command(&command);
+        if !status.success() {
+            panic!("Building target: {} failed with {}", target, status);
+        }
     }
 }
 
@@ -0,0 +1,264 @@
+//! Pretty-prints a frame capture and replays it against the receiver state machine,
+//! to reproduce transfer bugs without the device.
+//!
+//! Usage: `cargo run --bin replay -- [--peer] <capture file>`
+//!
+//! Received frames are fed into the receiver, sent Accept, Reject, Stored and Cancel frames are replayed as its decisions.
+//! With `--peer` the directions are swapped, which replays a capture of the sending side as the peer saw it.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+#[path = "../desktop/transfer/protocol.rs"]
+mod protocol;
+
+#[path = "../desktop/transfer/receiver.rs"]
+mod receiver;
+
+#[path = "../desktop/transfer/capture.rs"]
+mod capture;
+
+use capture::{read_capture, CaptureRecord, Direction};
+use protocol::{Frame, FrameType, ProtocolError};
+use receiver::{Action, Receiver, ReceiverState};
+
+/// Payload bytes shown per frame
+const PREVIEW_LEN: usize = 48;
+
+/// Feeds the records of a capture into a [Receiver] one by one
+#[derive(Default)]
+struct Replay {
+    receiver: Receiver,
+    /// Swaps sent and received frames
+    as_peer: bool,
+    errors: usize,
+}
+
+impl Replay {
+    /// Replays the record and describes what the receiver did
+    fn step(&mut self, record: &CaptureRecord) -> String {
+        match self.apply(record) {
+            Ok(Some(outcome)) => {
+                format!("{outcome}, state {}", describe_state(self.receiver.state()))
+            }
+            Ok(None) => "not replayed".to_string(),
+            Err(err) => {
+                self.errors += 1;
+                format!(
+                    "ERROR {err}, state {}",
+                    describe_state(self.receiver.state())
+                )
+            }
+        }
+    }
+
+    fn apply(&mut self, record: &CaptureRecord) -> Result<Option<String>, ProtocolError> {
+        let received = (record.direction == Direction::Received) != self.as_peer;
+        if received {
+            let action = self.receiver.handle(record.frame()?)?;
+            return Ok(Some(action.map_or("no action".to_string(), |action| {
+                describe_action(&action)
+            })));
+        }
+        // The decisions of the receiver, the other frames it sends are not part of the state machine
+        match record.frame_type {
+            FrameType::Accept => self.receiver.accept().map(|_| Some("accepted".to_string())),
+            FrameType::Stored => self.receiver.stored().map(|_| Some("stored".to_string())),
+            FrameType::Reject => {
+                let Frame::Reject(reason) = record.frame()? else {
+                    unreachable!("A reject record is rebuilt as reject frame");
+                };
+                self.receiver
+                    .reject(&reason)
+                    .map(|_| Some("rejected".to_string()))
+            }
+            FrameType::Cancel => Ok(Some(
+                self.receiver
+                    .cancel()
+                    .map_or("nothing to cancel", |_| "cancelled")
+                    .to_string(),
+            )),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn describe_action(action: &Action) -> String {
+    match action {
+        Action::Decide(offer) => format!("decide on {:?} ({} bytes)", offer.name, offer.size),
+        Action::Write(data) => format!("write {} bytes", data.len()),
+        Action::Finish(offer) => format!("finish {:?}", offer.name),
+        Action::Abort => "abort".to_string(),
+        Action::TextReceived(message) => format!(
+            "{} message of {} bytes",
+            message.kind.name(),
+            message.text.len()
+        ),
+        Action::SyncRequested(index) => format!(
+            "sync {:?} ({} entries)",
+            index.folder,
+            index.index.entries.len()
+        ),
+    }
+}
+
+fn describe_state(state: &ReceiverState) -> String {
+    match state {
+        ReceiverState::Idle => "Idle".to_string(),
+        ReceiverState::AwaitingDecision(offer) => format!("AwaitingDecision({:?})", offer.name),
+        ReceiverState::Receiving { offer, received } => {
+            format!("Receiving({:?}, {received}/{})", offer.name, offer.size)
+        }
+        ReceiverState::Storing(offer) => format!("Storing({:?})", offer.name),
+    }
+}
+
+/// Control payloads are JSON or text, data is shown as hex
+fn preview(record: &CaptureRecord) -> String {
+    let Ok(payload) = record.payload_bytes() else {
+        return format!("invalid payload {:?}", record.payload);
+    };
+    let (text, shortened) = match std::str::from_utf8(&payload) {
+        Ok(text) if record.frame_type != FrameType::Data => (
+            text.chars().take(PREVIEW_LEN * 2).collect(),
+            text.chars().count() > PREVIEW_LEN * 2,
+        ),
+        _ => (
+            payload
+                .iter()
+                .take(PREVIEW_LEN)
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            payload.len() > PREVIEW_LEN,
+        ),
+    };
+    if shortened || record.is_truncated() {
+        format!("{text}...")
+    } else {
+        text
+    }
+}
+
+fn print_record(index: usize, start_ms: u64, record: &CaptureRecord) {
+    let arrow = match record.direction {
+        Direction::Sent => "->",
+        Direction::Received => "<-",
+    };
+    let truncated = if record.is_truncated() {
+        " (truncated)"
+    } else {
+        ""
+    };
+    println!(
+        "#{index:<4} +{:>7} ms {arrow} {:<8} {:>7} bytes{truncated}  {}",
+        record.timestamp_ms.saturating_sub(start_ms),
+        format!("{:?}", record.frame_type),
+        record.length,
+        preview(record)
+    );
+}
+
+fn main() -> ExitCode {
+    let mut as_peer = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--peer" => as_peer = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => path = None,
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("Usage: replay [--peer] <capture file>");
+        return ExitCode::from(2);
+    };
+
+    let records = match File::open(&path).and_then(|file| read_capture(BufReader::new(file))) {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("Could not read {path}: {err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let start_ms = records.first().map_or(0, |record| record.timestamp_ms);
+    let mut replay = Replay {
+        as_peer,
+        ..Default::default()
+    };
+    for (index, record) in records.iter().enumerate() {
+        print_record(index, start_ms, record);
+        println!("{:>19}{}", "", replay.step(record));
+    }
+
+    println!(
+        "\n{} frames, {} protocol errors, final state {}",
+        records.len(),
+        replay.errors,
+        describe_state(replay.receiver.state())
+    );
+    if replay.errors > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capture::Capture;
+    use protocol::FileOffer;
+
+    #[test]
+    fn replays_decisions_and_reports_errors() {
+        let offer = FileOffer {
+            name: "notes.txt".to_string(),
+            size: 4,
+            sha256: String::new(),
+            metadata: Default::default(),
+        };
+        let mut buf = Vec::new();
+        let mut capture = Capture::new(&mut buf, 512);
+        capture
+            .record(Direction::Received, &Frame::Offer(offer))
+            .unwrap();
+        capture.record(Direction::Sent, &Frame::Accept).unwrap();
+        capture
+            .record(Direction::Received, &Frame::Data(b"not".to_vec()))
+            .unwrap();
+        capture
+            .record(Direction::Received, &Frame::Data(b"es".to_vec()))
+            .unwrap();
+        capture.record(Direction::Sent, &Frame::Cancel).unwrap();
+        let records = read_capture(buf.as_slice()).unwrap();
+
+        let mut replay = Replay::default();
+        let steps: Vec<String> = records.iter().map(|record| replay.step(record)).collect();
+        assert_eq!(
+            steps,
+            vec![
+                r#"decide on "notes.txt" (4 bytes), state AwaitingDecision("notes.txt")"#,
+                r#"accepted, state Receiving("notes.txt", 0/4)"#,
+                r#"write 3 bytes, state Receiving("notes.txt", 3/4)"#,
+                r#"ERROR Expected 4 bytes but received 5, state Receiving("notes.txt", 3/4)"#,
+                "cancelled, state Idle",
+            ]
+        );
+        assert_eq!(replay.errors, 1);
+
+        // The peer did not receive the offer, so it never accepted
+        let mut replay = Replay {
+            as_peer: true,
+            ..Default::default()
+        };
+        assert_eq!(replay.step(&records[0]), "not replayed");
+        assert_eq!(
+            replay.step(&records[1]),
+            "ERROR Unexpected Accept frame in state Idle, state Idle"
+        );
+    }
+}
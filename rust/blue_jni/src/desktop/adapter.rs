@@ -0,0 +1,210 @@
+use std::ptr;
+
+use bluer::{Adapter, Address};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use jni::objects::{JObject, JString};
+use jni::sys::jstring;
+use jni::JNIEnv;
+
+use crate::desktop::config::{app_config, update_config};
+use crate::desktop::error::{on_error, Error, FailureKind, Result, ResultExt};
+use crate::desktop::guard::{jni_entry, spawn_guarded};
+
+use super::blue_manager::selected_adapter_changed;
+use super::{bt_manager, rt_handle};
+
+/// Properties of a Bluetooth adapter as shown to the user
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct AdapterInfo {
+    /// Name of the adapter in BlueZ, e.g. `hci0`
+    pub(crate) name: String,
+    pub(crate) address: String,
+    pub(crate) alias: String,
+    pub(crate) powered: bool,
+    pub(crate) discoverable: bool,
+    pub(crate) modalias: Option<String>,
+    pub(crate) selected: bool,
+}
+
+impl AdapterInfo {
+    pub(crate) async fn read(adapter: &Adapter, selected: bool) -> Result<Self> {
+        let modalias = adapter.modalias().await?.map(|modalias| {
+            format!(
+                "{}:v{:04X}p{:04X}d{:04X}",
+                modalias.source, modalias.vendor, modalias.product, modalias.device
+            )
+        });
+        Ok(Self {
+            name: adapter.name().to_string(),
+            address: adapter.address().await?.to_string(),
+            alias: adapter.alias().await?,
+            powered: adapter.is_powered().await?,
+            discoverable: adapter.is_discoverable().await?,
+            modalias,
+            selected,
+        })
+    }
+}
+
+/// Adapter chosen by the user. Adapter names like `hci0` can change when an adapter is plugged in again,
+/// so the address is matched first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PreferredAdapter {
+    pub(crate) name: String,
+    pub(crate) address: String,
+}
+
+/// Picks the adapter to use from the available `(name, address)` pairs:
+/// the preferred adapter, else the currently selected one, else the BlueZ default adapter, else the first one.
+pub(crate) fn choose_adapter(
+    adapters: &[(String, Address)],
+    preferred: Option<&PreferredAdapter>,
+    current: Option<&str>,
+    default: Option<&str>,
+) -> Option<String> {
+    let by_name = |name: &str| adapters.iter().find(|(candidate, _)| candidate == name);
+    preferred
+        .and_then(|preferred| {
+            adapters
+                .iter()
+                .find(|(_, address)| address.to_string() == preferred.address)
+                .or_else(|| by_name(&preferred.name))
+        })
+        .or_else(|| current.and_then(by_name))
+        .or_else(|| default.and_then(by_name))
+        .or_else(|| adapters.first())
+        .map(|(name, _)| name.clone())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_listAdapters<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+) -> jstring {
+    jni_entry(
+        &mut env,
+        "BlueManager::listAdapters",
+        ptr::null_mut(),
+        |env| {
+            info!("BlueManager::listAdapters()");
+
+            let adapters = rt_handle().block_on(async {
+                let manager = bt_manager().lock().await;
+                let selected = manager
+                    .adapter
+                    .as_ref()
+                    .map(|adapter| adapter.name().to_string());
+                let mut adapters = Vec::with_capacity(manager.adapters.len());
+                for (name, adapter) in &manager.adapters {
+                    match AdapterInfo::read(adapter, selected.as_ref() == Some(name)).await {
+                        Ok(info) => adapters.push(info),
+                        Err(err) => warn!("Could not read properties of adapter {name}: {:?}", err),
+                    }
+                }
+                adapters
+            });
+
+            let json =
+                serde_json::to_string(&adapters).expect("Serializing adapters should not fail");
+            env.new_string(json)
+                .map(|json| json.into_raw())
+                .unwrap_or(ptr::null_mut())
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_selectAdapter<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    name: JString<'local>,
+) {
+    jni_entry(&mut env, "BlueManager::selectAdapter", (), |env| {
+        info!("BlueManager::selectAdapter()");
+
+        let name: String = env
+            .get_string(&name)
+            .expect("Getting String from env should not fail")
+            .into();
+        spawn_guarded(async move {
+            select_adapter(name).await.map_err(on_error).ok();
+        });
+    })
+}
+
+async fn select_adapter(name: String) -> Result<()> {
+    let mut manager = bt_manager().lock().await;
+    let adapter = manager
+        .adapters
+        .get(&name)
+        .ok_or_else(|| {
+            Error::failed(
+                FailureKind::DoesNotExist,
+                format!("Adapter {name} is not available"),
+            )
+        })
+        .during("select adapter")?;
+    let preferred = PreferredAdapter {
+        address: adapter
+            .address()
+            .await
+            .during("select adapter")?
+            .to_string(),
+        name,
+    };
+    info!(
+        "Preferring adapter {} ({})",
+        preferred.name, preferred.address
+    );
+    update_config(|config| config.preferred_adapter = Some(preferred))?;
+
+    let changed = manager.update_selected_adapter().await;
+    drop(manager);
+    if changed {
+        selected_adapter_changed().await;
+    }
+    Ok(())
+}
+
+pub(crate) fn preferred_adapter() -> Option<PreferredAdapter> {
+    app_config().lock().unwrap().preferred_adapter.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chooses_preferred_adapter_first() {
+        let address = |last: u8| Address::new([0, 0, 0, 0, 0, last]);
+        let adapters = vec![
+            ("hci0".to_string(), address(1)),
+            ("hci1".to_string(), address(2)),
+        ];
+        let preferred = PreferredAdapter {
+            name: "hci5".to_string(),
+            address: address(2).to_string(),
+        };
+
+        assert_eq!(
+            choose_adapter(&adapters, Some(&preferred), Some("hci0"), Some("hci0")).as_deref(),
+            Some("hci1")
+        );
+        // The preferred adapter is unplugged
+        assert_eq!(
+            choose_adapter(&adapters[..1], Some(&preferred), Some("hci0"), None).as_deref(),
+            Some("hci0")
+        );
+        assert_eq!(
+            choose_adapter(&adapters, None, Some("hci3"), Some("hci1")).as_deref(),
+            Some("hci1")
+        );
+        assert_eq!(
+            choose_adapter(&adapters, None, None, None).as_deref(),
+            Some("hci0")
+        );
+        assert_eq!(choose_adapter(&[], Some(&preferred), None, None), None);
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use tracing::info;
+
+use crate::desktop::events::{self, Event};
+use crate::desktop::rfkill::BlockState;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum AdapterState {
+    /// bluetoothd or the system D-Bus is not reachable
+    BackendUnavailable,
+    #[default]
+    NoAdapter,
+    SoftBlocked,
+    /// Blocked by a hardware switch, only the user can lift the block
+    HardBlocked,
+    PoweredOff,
+    TurningOn,
+    On,
+    TurningOff,
+    Discovering,
+}
+
+impl AdapterState {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::BackendUnavailable => "BackendUnavailable",
+            Self::NoAdapter => "NoAdapter",
+            Self::SoftBlocked => "SoftBlocked",
+            Self::HardBlocked => "HardBlocked",
+            Self::PoweredOff => "PoweredOff",
+            Self::TurningOn => "TurningOn",
+            Self::On => "On",
+            Self::TurningOff => "TurningOff",
+            Self::Discovering => "Discovering",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AdapterInput {
+    /// BlueZ became reachable or went away
+    Backend {
+        available: bool,
+    },
+    /// An adapter has been selected, e.g. at startup or after it was plugged in
+    AdapterSelected {
+        powered: bool,
+        discovering: bool,
+    },
+    /// No adapter is available anymore
+    AdapterRemoved,
+    Rfkill(BlockState),
+    Powered(bool),
+    Discovering(bool),
+    PowerOnStarted,
+    PowerOffStarted,
+    /// Powering on or off finished, successful or not
+    PowerChangeFinished,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transition {
+    TurningOn,
+    TurningOff,
+}
+
+/// Lifecycle of the selected adapter, driven by adapter properties, rfkill events and power requests
+#[derive(Debug, Default)]
+pub(crate) struct AdapterStateMachine {
+    backend_unavailable: bool,
+    has_adapter: bool,
+    block: BlockState,
+    powered: bool,
+    discovering: bool,
+    transition: Option<Transition>,
+    state: AdapterState,
+}
+
+impl AdapterStateMachine {
+    pub(crate) fn state(&self) -> AdapterState {
+        self.state
+    }
+
+    /// Applies an input and returns the new state if it changed
+    pub(crate) fn handle(&mut self, input: AdapterInput) -> Option<AdapterState> {
+        match input {
+            AdapterInput::Backend { available } => self.backend_unavailable = !available,
+            AdapterInput::AdapterSelected {
+                powered,
+                discovering,
+            } => {
+                self.has_adapter = true;
+                self.powered = powered;
+                self.discovering = discovering;
+            }
+            AdapterInput::AdapterRemoved => {
+                self.has_adapter = false;
+                self.powered = false;
+                self.discovering = false;
+            }
+            AdapterInput::Rfkill(block) => self.block = block,
+            AdapterInput::Powered(powered) => {
+                self.powered = powered;
+                if !powered {
+                    self.discovering = false;
+                }
+            }
+            AdapterInput::Discovering(discovering) => self.discovering = discovering,
+            AdapterInput::PowerOnStarted => self.transition = Some(Transition::TurningOn),
+            AdapterInput::PowerOffStarted => self.transition = Some(Transition::TurningOff),
+            AdapterInput::PowerChangeFinished => self.transition = None,
+        }
+
+        // A transition ends as soon as the adapter reached the target
+        match (self.transition, self.powered) {
+            (Some(Transition::TurningOn), true) | (Some(Transition::TurningOff), false) => {
+                self.transition = None
+            }
+            _ => (),
+        }
+
+        let state = self.derive();
+        if state == self.state {
+            return None;
+        }
+        info!("Adapter state: {:?} -> {:?}", self.state, state);
+        self.state = state;
+        Some(state)
+    }
+
+    fn derive(&self) -> AdapterState {
+        if self.backend_unavailable {
+            return AdapterState::BackendUnavailable;
+        }
+        if self.block.hard_blocked {
+            return AdapterState::HardBlocked;
+        }
+        match self.transition {
+            Some(Transition::TurningOn) => return AdapterState::TurningOn,
+            Some(Transition::TurningOff) => return AdapterState::TurningOff,
+            None => (),
+        }
+        if self.block.soft_blocked {
+            AdapterState::SoftBlocked
+        } else if !self.has_adapter {
+            AdapterState::NoAdapter
+        } else if !self.powered {
+            AdapterState::PoweredOff
+        } else if self.discovering {
+            AdapterState::Discovering
+        } else {
+            AdapterState::On
+        }
+    }
+}
+
+lazy_static! {
+    static ref ADAPTER_STATE: Mutex<AdapterStateMachine> =
+        Mutex::new(AdapterStateMachine::default());
+}
+
+pub(crate) fn adapter_state() -> AdapterState {
+    ADAPTER_STATE.lock().unwrap().state()
+}
+
+/// Forgets all inputs, so a restarted backend does not start from stale state
+pub(crate) fn reset_adapter_state() {
+    *ADAPTER_STATE.lock().unwrap() = AdapterStateMachine::default();
+}
+
+/// Feeds an input into the state machine and publishes the new state to Kotlin
+pub(crate) fn update_adapter_state(input: AdapterInput) {
+    let changed = ADAPTER_STATE.lock().unwrap().handle(input);
+    if let Some(state) = changed {
+        publish_adapter_state(state);
+    }
+}
+
+/// Starts powering on or off unless a transition is already running
+pub(crate) fn begin_power_change(input: AdapterInput) -> bool {
+    let mut machine = ADAPTER_STATE.lock().unwrap();
+    if matches!(
+        machine.state(),
+        AdapterState::TurningOn | AdapterState::TurningOff
+    ) {
+        return false;
+    }
+    let changed = machine.handle(input);
+    drop(machine);
+    if let Some(state) = changed {
+        publish_adapter_state(state);
+    }
+    true
+}
+
+pub(crate) fn publish_adapter_state(state: AdapterState) {
+    events::emit(Event::StateChanged(state));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_adapter_lifecycle() {
+        let mut machine = AdapterStateMachine::default();
+        let soft_blocked = BlockState {
+            soft_blocked: true,
+            hard_blocked: false,
+        };
+
+        assert_eq!(
+            machine.handle(AdapterInput::Rfkill(soft_blocked)),
+            Some(AdapterState::SoftBlocked)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::AdapterSelected {
+                powered: false,
+                discovering: false
+            }),
+            None
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::PowerOnStarted),
+            Some(AdapterState::TurningOn)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Rfkill(BlockState::default())),
+            None
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Powered(true)),
+            Some(AdapterState::On)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Discovering(true)),
+            Some(AdapterState::Discovering)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::PowerOffStarted),
+            Some(AdapterState::TurningOff)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Powered(false)),
+            Some(AdapterState::PoweredOff)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::AdapterRemoved),
+            Some(AdapterState::NoAdapter)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Backend { available: false }),
+            Some(AdapterState::BackendUnavailable)
+        );
+        assert_eq!(
+            machine.handle(AdapterInput::Backend { available: true }),
+            Some(AdapterState::NoAdapter)
+        );
+    }
+
+    #[test]
+    fn failed_power_on_does_not_get_stuck() {
+        let mut machine = AdapterStateMachine::default();
+        machine.handle(AdapterInput::AdapterSelected {
+            powered: false,
+            discovering: false,
+        });
+        machine.handle(AdapterInput::PowerOnStarted);
+        assert_eq!(machine.state(), AdapterState::TurningOn);
+        assert_eq!(
+            machine.handle(AdapterInput::PowerChangeFinished),
+            Some(AdapterState::PoweredOff)
+        );
+
+        // A hard block wins over everything else
+        machine.handle(AdapterInput::PowerOnStarted);
+        assert_eq!(
+            machine.handle(AdapterInput::Rfkill(BlockState {
+                soft_blocked: false,
+                hard_blocked: true
+            })),
+            Some(AdapterState::HardBlocked)
+        );
+    }
+}
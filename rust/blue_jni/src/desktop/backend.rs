@@ -0,0 +1,182 @@
+use std::sync::Mutex;
+
+use bluer::Session;
+use dbus::message::MatchRule;
+use dbus::Message;
+use lazy_static::lazy_static;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::desktop::adapter_state::{update_adapter_state, AdapterInput};
+use crate::desktop::blue_manager::{bluetooth_adapter_events, selected_adapter_changed};
+use crate::desktop::guard::spawn_guarded;
+use crate::desktop::transfer::rfcomm;
+
+use super::{bt_manager, rt_handle};
+
+/// Bus name of bluetoothd
+const BLUEZ_SERVICE: &str = "org.bluez";
+/// Time between attempts to reach BlueZ when no appearance of `org.bluez` can be observed
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Tasks that run while attached to BlueZ: following adapters being added and removed and serving transfers
+    static ref SESSION_TASKS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+}
+
+async fn connect() -> bluer::Result<Session> {
+    let session = Session::new().await?;
+    // Creating the session only connects to D-Bus, listing adapters fails if bluetoothd is not running
+    session.adapter_names().await?;
+    Ok(session)
+}
+
+/// Aborts the tasks of the previous session and keeps `tasks` instead
+fn replace_session_tasks(tasks: Vec<JoinHandle<()>>) {
+    let previous = std::mem::replace(&mut *SESSION_TASKS.lock().unwrap(), tasks);
+    for task in previous {
+        task.abort();
+    }
+}
+
+/// Attaches to BlueZ unless already attached, returns whether BlueZ is available
+pub(crate) async fn attach() -> bool {
+    let mut manager = bt_manager().lock().await;
+    if manager.is_attached() {
+        return true;
+    }
+    let session = match connect().await {
+        Ok(session) => session,
+        Err(err) => {
+            drop(manager);
+            info!("BlueZ is not available: {err}");
+            update_adapter_state(AdapterInput::Backend { available: false });
+            return false;
+        }
+    };
+    info!("Attached to BlueZ");
+    manager.attach(session.clone()).await;
+    drop(manager);
+
+    let tasks = vec![
+        spawn_guarded(bluetooth_adapter_events(session.clone())),
+        spawn_guarded(rfcomm::serve(session)),
+        spawn_guarded(rfcomm::sync_periodically()),
+    ];
+    replace_session_tasks(tasks);
+    update_adapter_state(AdapterInput::Backend { available: true });
+    selected_adapter_changed().await;
+    true
+}
+
+/// Stops following adapters and serving transfers and drops the session, which releases everything registered with BlueZ through it.
+/// Unlike [`detach`] nothing is reported, the backend is going away.
+pub(crate) async fn shutdown() {
+    replace_session_tasks(Vec::new());
+    let mut manager = bt_manager().lock().await;
+    if manager.is_attached() {
+        info!("Detached from BlueZ");
+        manager.detach();
+    }
+}
+
+async fn detach() {
+    let mut manager = bt_manager().lock().await;
+    if !manager.is_attached() {
+        return;
+    }
+    info!("Detached from BlueZ");
+    manager.detach();
+    drop(manager);
+
+    replace_session_tasks(Vec::new());
+    update_adapter_state(AdapterInput::Backend { available: false });
+    selected_adapter_changed().await;
+}
+
+/// Reports `true` whenever `org.bluez` appears on the system bus and `false` when it disappears.
+/// The channel closes when the connection to the bus is lost.
+async fn watch_bluez_owner() -> Result<mpsc::UnboundedReceiver<bool>, dbus::Error> {
+    let (resource, connection) = dbus_tokio::connection::new_system_sync()?;
+    let mut resource = rt_handle().spawn(resource);
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let receiver_dropped = tx.clone();
+    let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+        .with_sender("org.freedesktop.DBus");
+    let owner_changes = match connection.add_match(rule).await {
+        Ok(owner_changes) => owner_changes,
+        Err(err) => {
+            resource.abort();
+            return Err(err);
+        }
+    };
+    let owner_changes = owner_changes.cb(
+        move |_: Message, (name, _old_owner, new_owner): (String, String, String)| {
+            if name == BLUEZ_SERVICE {
+                let _ = tx.send(!new_owner.is_empty());
+            }
+            true
+        },
+    );
+
+    spawn_guarded(async move {
+        tokio::select! {
+            result = &mut resource => {
+                if let Ok(err) = result {
+                    warn!("Lost connection to the system D-Bus: {err}");
+                }
+            }
+            // Nobody is interested anymore, e.g. after a shutdown
+            _ = receiver_dropped.closed() => {
+                info!("Stopped watching the system D-Bus");
+                resource.abort();
+            }
+        }
+        // Dropping the match and the connection drops the senders, which closes the channel
+        drop(owner_changes);
+        drop(connection);
+    });
+    Ok(rx)
+}
+
+/// Keeps the backend attached while BlueZ is available. Runs for the lifetime of the app.
+pub(crate) async fn supervise() {
+    loop {
+        // The bus is watched before attaching, so BlueZ starting in between is not missed
+        let mut owner_changes = match watch_bluez_owner().await {
+            Ok(owner_changes) => owner_changes,
+            Err(err) => {
+                warn!("System D-Bus is not available: {err}");
+                detach().await;
+                update_adapter_state(AdapterInput::Backend { available: false });
+                sleep(RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        let mut attached = attach().await;
+
+        loop {
+            tokio::select! {
+                change = owner_changes.recv() => match change {
+                    Some(true) => {
+                        info!("{BLUEZ_SERVICE} appeared on the system bus");
+                        attached = attach().await;
+                    }
+                    Some(false) => {
+                        info!("{BLUEZ_SERVICE} disappeared from the system bus");
+                        detach().await;
+                        attached = false;
+                    }
+                    None => break,
+                },
+                _ = sleep(RETRY_INTERVAL), if !attached => attached = attach().await,
+            }
+        }
+
+        detach().await;
+        sleep(RETRY_INTERVAL).await;
+    }
+}
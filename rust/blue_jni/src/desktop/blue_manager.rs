@@ -1,29 +1,129 @@
+use std::collections::{BTreeMap, HashSet};
 use std::str::FromStr;
+use std::sync::Mutex;
 
 use bluer::DiscoveryFilter;
-use bluer::{Adapter, AdapterEvent, Address, DeviceEvent, Session, SessionEvent};
+use bluer::{
+    Adapter, AdapterEvent, AdapterProperty, Address, DeviceEvent, DeviceProperty, Session,
+    SessionEvent,
+};
 use futures::{pin_mut, stream::SelectAll, StreamExt};
 use lazy_static::lazy_static;
-use log::{error, info, warn};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc;
 use tokio::time::{sleep, Duration};
-
-use jni::objects::{JObject, JString, JValue};
-use jni::{Executor, JNIEnv};
-use util::CommandConfig;
-
-use crate::desktop::error::{on_error, Error, Result};
-use crate::desktop::GLOBAL_JVM;
+use tracing::field::Empty;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+
+use jni::objects::{JObject, JString};
+use jni::JNIEnv;
+
+use crate::desktop::adapter::{choose_adapter, preferred_adapter};
+use crate::desktop::adapter_state::{
+    adapter_state, begin_power_change, publish_adapter_state, reset_adapter_state,
+    update_adapter_state, AdapterInput,
+};
+use crate::desktop::backend;
+use crate::desktop::completion::complete_with;
+use crate::desktop::discoverable::{stop_countdown, stop_discoverable};
+use crate::desktop::error::{on_error, Error, FailureKind, Result, ResultExt};
+use crate::desktop::events::{app_sink, Event, EventSink};
+use crate::desktop::guard::{jni_entry, spawn_guarded};
+use crate::desktop::rfkill;
+use crate::desktop::transfer::cancel;
 
 use super::{bt_manager, rt_handle};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub(crate) struct BlueManager {
-    pub(crate) session: Session,
+    /// Session with BlueZ, `None` while bluetoothd or the system D-Bus is not available
+    pub(crate) session: Option<Session>,
+    /// All adapters known to BlueZ by name
+    pub(crate) adapters: BTreeMap<String, Adapter>,
+    /// The adapter used for discovery and transfers
     pub(crate) adapter: Option<Adapter>,
+    /// Index of the rfkill switch of the selected adapter
+    pub(crate) rfkill_switch: Option<u32>,
 }
 
 impl BlueManager {
+    pub(crate) fn is_attached(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Uses the session with BlueZ and selects one of its adapters
+    pub(crate) async fn attach(&mut self, session: Session) {
+        let mut adapters = BTreeMap::new();
+        match session.adapter_names().await {
+            Ok(names) => {
+                for name in names {
+                    match session.adapter(&name) {
+                        Ok(adapter) => {
+                            adapters.insert(name, adapter);
+                        }
+                        Err(err) => warn!("Error: {err}. Adapter {name} could not be retrieved"),
+                    }
+                }
+            }
+            Err(err) => warn!("Error: {err}. Adapters could not be listed"),
+        }
+
+        self.session = Some(session);
+        self.adapters = adapters;
+        self.update_selected_adapter().await;
+    }
+
+    /// Forgets the session and all adapters after BlueZ went away
+    pub(crate) fn detach(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Selects the adapter to use after adapters were added or removed or the preferred adapter changed.
+    /// Returns whether the selection changed.
+    pub(crate) async fn update_selected_adapter(&mut self) -> bool {
+        let mut candidates = Vec::with_capacity(self.adapters.len());
+        for (name, adapter) in &self.adapters {
+            match adapter.address().await {
+                Ok(address) => candidates.push((name.clone(), address)),
+                Err(err) => warn!("Error: {err}. Address of adapter {name} could not be read"),
+            }
+        }
+        let default = match &self.session {
+            Some(session) => session
+                .default_adapter()
+                .await
+                .ok()
+                .map(|adapter| adapter.name().to_string()),
+            None => None,
+        };
+        let current = self
+            .adapter
+            .as_ref()
+            .map(|adapter| adapter.name().to_string());
+        let selected = choose_adapter(
+            &candidates,
+            preferred_adapter().as_ref(),
+            current.as_deref(),
+            default.as_deref(),
+        );
+        if selected == current {
+            return false;
+        }
+
+        self.adapter = selected.and_then(|name| self.adapters.get(&name).cloned());
+        self.rfkill_switch = self
+            .adapter
+            .as_ref()
+            .and_then(|adapter| rfkill::adapter_switch(adapter.name()));
+        match &self.adapter {
+            Some(adapter) => info!("Using Bluetooth adapter {}", adapter.name()),
+            None => info!("No Bluetooth adapter available"),
+        }
+        self.set_discovery_filter()
+            .await
+            .unwrap_or_else(|_| error!("Could not set discovery filter"));
+        true
+    }
+
     pub(crate) async fn set_discovery_filter(&self) -> Result<()> {
         if let Some(adapter) = &self.adapter {
             info!(
@@ -45,6 +145,59 @@ impl BlueManager {
     }
 }
 
+/// Turns the device events of a discovery into UI events. Each device is reported as discovered once.
+pub(crate) struct DiscoveryReporter<'a> {
+    sink: &'a dyn EventSink,
+    devices: HashSet<Address>,
+}
+
+impl<'a> DiscoveryReporter<'a> {
+    pub(crate) fn new(sink: &'a dyn EventSink) -> Self {
+        Self {
+            sink,
+            devices: HashSet::new(),
+        }
+    }
+
+    /// Devices without a name are shown with their address
+    pub(crate) fn added(&mut self, address: Address, name: Option<String>) {
+        if self.devices.insert(address) {
+            self.sink.emit(Event::DeviceDiscovered {
+                name: name.unwrap_or_else(|| address.to_string()),
+                address: address.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn changed(&mut self, address: Address, property: &DeviceProperty) {
+        if !self.devices.contains(&address) {
+            return;
+        }
+        let (name, rssi) = match property {
+            DeviceProperty::Name(name) => (Some(name.clone()), None),
+            DeviceProperty::Rssi(rssi) => (None, Some(*rssi)),
+            _ => return,
+        };
+        self.sink.emit(Event::DeviceUpdated {
+            address: address.to_string(),
+            name,
+            rssi,
+        });
+    }
+
+    pub(crate) fn removed(&mut self, address: Address) {
+        if self.devices.remove(&address) {
+            self.sink.emit(Event::DeviceLost {
+                address: address.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn stopped(&self) {
+        self.sink.emit(Event::DiscoveryStopped);
+    }
+}
+
 #[derive(Debug)]
 struct BlueState {
     timeout: Option<mpsc::Sender<()>>,
@@ -68,302 +221,623 @@ impl BlueState {
 }
 
 lazy_static! {
+    /// Only locked briefly and never across an await, so the discovery slot can be released on drop
     static ref BLUE_STATE: Mutex<BlueState> = Mutex::new(BlueState::new());
 }
 
 lazy_static! {
-    static ref IS_BLUETOOTH_ENABLING: Mutex<bool> = Mutex::new(false);
+    /// Supervisor of the BlueZ connection and rfkill watcher, running between [`start`] and [`shutdown`]
+    static ref BACKGROUND_TASKS: std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>> =
+        std::sync::Mutex::new(Vec::new());
+}
+
+lazy_static! {
+    /// Forwards the property changes of the selected adapter to the state machine
+    static ref ADAPTER_PROPERTIES_TASK: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>> =
+        std::sync::Mutex::new(None);
 }
 
+/// Time BlueZ gets to register the adapter and lift its power block after an rfkill unblock
+const POWER_ON_TIMEOUT: Duration = Duration::from_secs(5);
+const POWER_ON_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+/// Time running transfers get to tell their peers they are cancelled before they are aborted
+const TRANSFER_CANCEL_GRACE: Duration = Duration::from_secs(3);
+
 #[no_mangle]
 pub extern "system" fn Java_de_schweizer_bft_BlueManager_init<'local>(
-    _env: JNIEnv<'local>,
+    mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
 ) {
-    info!("Initializing BluetoothManager");
-    // Ensure BlueManager is initialized synchronously
-    bt_manager();
+    jni_entry(&mut env, "BlueManager::init", (), |_env| {
+        info!("Initializing BluetoothManager");
+
+        start();
+    })
+}
+
+/// Starts following rfkill and BlueZ. While running, only the current state is published again.
+fn start() {
+    let mut tasks = BACKGROUND_TASKS.lock().unwrap();
+    if !tasks.is_empty() {
+        info!("BluetoothManager is already running");
+        publish_adapter_state(adapter_state());
+        return;
+    }
 
     rt_handle().block_on(async {
-        let manager = bt_manager().lock().await;
-        update_bluetooth_enabled(manager.adapter.is_some());
+        // The first attempt is synchronous, so Kotlin starts with the actual state
+        backend::attach().await;
+        update_rfkill_state().await;
+        // The initial state is published even if it matches the default
+        publish_adapter_state(adapter_state());
     });
 
-    rt_handle().spawn(bluetooth_adapter_events());
+    tasks.push(spawn_guarded(backend::supervise()));
+    tasks.push(spawn_guarded(rfkill_events()));
 }
 
-async fn bluetooth_adapter_events() {
-    let manager = bt_manager().lock().await;
-    let session_events = manager
-        .session
-        .events()
-        .await
-        .expect("Getting bluettooth session events should not fail");
+/// Cancels the running transfers, stops discovery and everything [`start`] set up,
+/// ends discoverable mode and drops the BlueZ session. [`start`] brings it all back.
+pub(crate) async fn shutdown() {
+    cancel::cancel_all(TRANSFER_CANCEL_GRACE).await;
+    let tasks: Vec<_> = BACKGROUND_TASKS.lock().unwrap().drain(..).collect();
+    for task in tasks {
+        task.abort();
+        // Waiting for the abort makes sure the supervisor does not attach again
+        let _ = task.await;
+    }
+    cancel_disocovery().await;
+    // The adapter would stay visible and pairable after the app is gone
+    if let Err(err) = stop_discoverable().await {
+        info!("Could not end discoverable mode: {err}");
+    }
+    if let Some(task) = ADAPTER_PROPERTIES_TASK.lock().unwrap().take() {
+        task.abort();
+    }
+    backend::shutdown().await;
+    reset_adapter_state();
+}
+
+async fn rfkill_events() {
+    let mut watch = match rfkill::watch_switches() {
+        Ok(watch) => watch,
+        Err(err) => {
+            warn!("Could not watch rfkill events: {err}");
+            return;
+        }
+    };
+    while let Some(switches) = watch.recv().await {
+        let switch = bt_manager().lock().await.rfkill_switch;
+        update_adapter_state(AdapterInput::Rfkill(switches.state(switch)));
+    }
+}
+
+/// Feeds the block state of the rfkill switch of the selected adapter into the state machine
+async fn update_rfkill_state() {
+    let switch = bt_manager().lock().await.rfkill_switch;
+    match rfkill::block_state(switch) {
+        Ok(block_state) => update_adapter_state(AdapterInput::Rfkill(block_state)),
+        Err(err) => warn!("Could not read rfkill state: {err}"),
+    }
+}
+
+/// Feeds the state of the newly selected adapter into the state machine and follows its property changes
+async fn adapter_selected() {
+    let adapter = bt_manager().lock().await.adapter.clone();
+    if let Some(task) = ADAPTER_PROPERTIES_TASK.lock().unwrap().take() {
+        task.abort();
+    }
+
+    update_rfkill_state().await;
+    let Some(adapter) = adapter else {
+        update_adapter_state(AdapterInput::AdapterRemoved);
+        return;
+    };
+    update_adapter_state(AdapterInput::AdapterSelected {
+        powered: adapter.is_powered().await.unwrap_or(false),
+        discovering: adapter.is_discovering().await.unwrap_or(false),
+    });
+    let span = info_span!("adapter", adapter = adapter.name());
+    let task = spawn_guarded(adapter_property_events(adapter).instrument(span));
+    *ADAPTER_PROPERTIES_TASK.lock().unwrap() = Some(task);
+}
+
+async fn adapter_property_events(adapter: Adapter) {
+    let events = match adapter.events().await {
+        Ok(events) => events,
+        Err(err) => {
+            warn!(
+                "Error: {err}. Events of adapter {} are not available",
+                adapter.name()
+            );
+            return;
+        }
+    };
+    pin_mut!(events);
+
+    while let Some(event) = events.next().await {
+        match event {
+            AdapterEvent::PropertyChanged(AdapterProperty::Powered(powered)) => {
+                update_adapter_state(AdapterInput::Powered(powered));
+            }
+            AdapterEvent::PropertyChanged(AdapterProperty::Discovering(discovering)) => {
+                update_adapter_state(AdapterInput::Discovering(discovering));
+            }
+            _ => (),
+        }
+    }
+}
+
+pub(crate) async fn bluetooth_adapter_events(session: Session) {
+    let session_events = match session.events().await {
+        Ok(session_events) => session_events,
+        Err(err) => {
+            warn!("Error: {err}. Bluetooth session events are not available");
+            return;
+        }
+    };
     pin_mut!(session_events);
-    drop(manager);
 
-    loop {
-        tokio::select! {
-            Some(session_event) = session_events.next() => {
-                let mut manager = bt_manager().lock().await;
-                match session_event {
-                    SessionEvent::AdapterAdded(adapter_name) => {
-                        info!("Adapter added: {adapter_name}");
-                        match manager.session.adapter(&adapter_name) {
-                            Ok(adapter) => {
-                                manager.adapter = Some(adapter);
-                                manager.set_discovery_filter()
-                                .await
-                                .unwrap_or_else(|_| error!("Could not set discovery filter"));
-                                update_bluetooth_enabled(true);
-                            }
-                            Err(err) => {
-                                warn!("Error: {err}. Adapter {adapter_name} could not be retrieved");
-                                manager.adapter = None;
-                                update_bluetooth_enabled(false);
-                            }
-                        }
+    while let Some(session_event) = session_events.next().await {
+        let mut manager = bt_manager().lock().await;
+        match session_event {
+            SessionEvent::AdapterAdded(adapter_name) => {
+                info!("Adapter added: {adapter_name}");
+                match session.adapter(&adapter_name) {
+                    Ok(adapter) => {
+                        manager.adapters.insert(adapter_name, adapter);
                     }
-                    SessionEvent::AdapterRemoved(adapter) => {
-                        info!("Adapter removed: {adapter}");
-                        manager.adapter = None;
-                        update_bluetooth_enabled(false);
-                        cancel_disocovery().await;
+                    Err(err) => {
+                        warn!("Error: {err}. Adapter {adapter_name} could not be retrieved");
                     }
                 }
-                drop(manager);
             }
+            SessionEvent::AdapterRemoved(adapter_name) => {
+                info!("Adapter removed: {adapter_name}");
+                manager.adapters.remove(&adapter_name);
+            }
+        }
+        let changed = manager.update_selected_adapter().await;
+        drop(manager);
+        if changed {
+            selected_adapter_changed().await;
         }
     }
 }
 
+/// Completes `future` when the discovery has ended
 #[no_mangle]
-pub extern "system" fn Java_de_schweizer_bft_BlueManager_discover<'local>(
-    _env: JNIEnv<'local>,
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_discoverAsync<'local>(
+    mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
+    future: JObject<'local>,
 ) {
-    info!("BlueManager::discover()");
+    jni_entry(&mut env, "BlueManager::discover", (), |env| {
+        info!("BlueManager::discover()");
 
-    rt_handle().spawn(async {
-        discover_devices().await.map_err(|err| on_error(err)).ok();
-    });
+        let span = info_span!("discovery", adapter = Empty);
+        complete_with(env, &future, discover_devices().instrument(span));
+    })
+}
+
+/// The running discovery, the slot is released when it is dropped, even if the discovery panicked
+struct DiscoverySlot {
+    timeout_rx: mpsc::Receiver<()>,
+    cancel_rx: mpsc::Receiver<()>,
+}
+
+impl Drop for DiscoverySlot {
+    fn drop(&mut self) {
+        *BLUE_STATE.lock().unwrap() = BlueState::new();
+    }
+}
+
+/// Takes the discovery slot, a second discovery is refused until the running one has ended
+fn reserve_discovery() -> Result<DiscoverySlot> {
+    let mut state = BLUE_STATE.lock().unwrap();
+    if state.cancel.is_some() {
+        return Err(Error::failed(
+            FailureKind::InProgress,
+            "Discovery is already running",
+        ))
+        .during("start discovery");
+    }
+    let (timeout_tx, timeout_rx) = mpsc::channel(1);
+    let (cancel_tx, cancel_rx) = mpsc::channel(1);
+    *state = BlueState::set(timeout_tx, cancel_tx);
+    Ok(DiscoverySlot {
+        timeout_rx,
+        cancel_rx,
+    })
 }
 
 async fn discover_devices() -> Result<()> {
+    let slot = reserve_discovery()?;
+    run_discovery(slot).await
+}
+
+async fn run_discovery(mut slot: DiscoverySlot) -> Result<()> {
     let manager = bt_manager().lock().await;
     let adapter = manager.adapter.as_ref().ok_or(Error::AdapterNotAvailable)?;
+    Span::current().record("adapter", adapter.name());
+    if !adapter.is_powered().await.during("start discovery")? {
+        return Err(Error::failed(
+            FailureKind::NotPowered,
+            format!("Adapter {} is powered off", adapter.name()),
+        ))
+        .during("start discovery");
+    }
 
-    let device_events = adapter
-        .discover_devices()
-        .await
-        .map_err(|_| Error::DiscoveryNotPossible)?;
+    let device_events = adapter.discover_devices().await.during("start discovery")?;
     pin_mut!(device_events);
     drop(manager);
 
     let mut all_change_events = SelectAll::new();
-
-    let (timeout_tx, mut timeout_rx) = mpsc::channel(1);
-    let (cancel_tx, mut cancel_rx) = mpsc::channel(1);
-    *BLUE_STATE.lock().await = BlueState::set(timeout_tx, cancel_tx);
+    let mut reporter = DiscoveryReporter::new(app_sink());
 
     let duration = Duration::from_secs(12);
-    let timeout_task = tokio::spawn(sleep_and_notify(duration));
+    let timeout_task = spawn_guarded(sleep_and_notify(duration));
 
     loop {
         tokio::select! {
             Some(device_event) = device_events.next() => {
                     match device_event {
                         AdapterEvent::DeviceAdded(addr) => {
-                            let manager = bt_manager().lock().await;
-                            let adapter = manager.adapter.as_ref().unwrap();
-                            let device = adapter.device(addr).expect("Getting device should not fail");
-                            drop(manager);
-
-                            let device_name = device.name().await.expect("Getting device name should not fail").unwrap_or(addr.to_string());
-                            let addr_str = addr.to_string();
-                            info!("Device ({}) with address: {} added", device_name, addr_str);
-
-                            device_discovered(&device_name, &addr_str);
-
-                            let change_event = device.events().await.expect("Getting events from device should not fail").map(move |event| (addr, event));
-                            all_change_events.push(change_event);
+                            let adapter = bt_manager().lock().await.adapter.clone();
+                            let Some(adapter) = adapter else {
+                                warn!(device = %addr, "Adapter went away, skipping device");
+                                continue;
+                            };
+                            let device = match adapter.device(addr) {
+                                Ok(device) => device,
+                                Err(err) => {
+                                    warn!(device = %addr, "Skipping device: {err}");
+                                    continue;
+                                }
+                            };
+                            let device_name = match device.name().await {
+                                Ok(device_name) => device_name,
+                                Err(err) => {
+                                    warn!(device = %addr, "Skipping device, its name is not available: {err}");
+                                    continue;
+                                }
+                            };
+                            let change_events = match device.events().await {
+                                Ok(events) => events,
+                                Err(err) => {
+                                    warn!(device = %addr, "Skipping device, its changes are not available: {err}");
+                                    continue;
+                                }
+                            };
+                            info!(device = %addr, "Device ({:?}) added", device_name);
+
+                            reporter.added(addr, device_name);
+                            all_change_events.push(change_events.map(move |event| (addr, event)));
                         }
                         AdapterEvent::DeviceRemoved(addr) => {
-                            info!("Device removed: {addr}");
+                            info!(device = %addr, "Device removed");
+                            reporter.removed(addr);
                         }
                         _ => (),
                     }
             }
             Some((addr, DeviceEvent::PropertyChanged(prop))) = all_change_events.next() => {
-                info!("Device changed: {}", addr);
-                info!("    {:?}", prop);
+                info!(device = %addr, "Device changed: {:?}", prop);
+                reporter.changed(addr, &prop);
             }
-            Some(()) = timeout_rx.recv() => {
+            Some(()) = slot.timeout_rx.recv() => {
                 info!("Timeout reached, ending discovery");
-                discovery_stopped().await;
-                return Ok(());
+                break;
             }
-            Some(()) = cancel_rx.recv() => {
+            Some(()) = slot.cancel_rx.recv() => {
                 info!("Canceling Discovery");
-                discovery_stopped().await;
-                timeout_task.abort();
-                return Ok(());
+                break;
             }
             else => {
-                info!("tokio::select! => else");
-                return Ok(());
+                info!("Device events ended, ending discovery");
+                break;
             }
         }
     }
+    timeout_task.abort();
+    reporter.stopped();
+    Ok(())
 }
 
+/// Completes `future` with the service UUIDs of the device
 #[no_mangle]
-pub extern "system" fn Java_de_schweizer_bft_BlueManager_connectToDevice<'local>(
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_getServicesAsync<'local>(
     mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
     device_addr: JString<'local>,
+    future: JObject<'local>,
 ) {
-    info!("BlueManager::connectToDevice()");
+    jni_entry(&mut env, "BlueManager::getServices", (), |env| {
+        info!("BlueManager::getServices()");
+
+        let device_addr: String = env
+            .get_string(&device_addr)
+            .expect("Getting String from env should not fail")
+            .into();
+        let span = info_span!("device", device = %device_addr, adapter = Empty);
+        complete_with(env, &future, device_services(device_addr).instrument(span));
+    })
+}
 
-    let device_addr: String = env
-        .get_string(&device_addr)
-        .expect("Getting String from env should not fail")
-        .into();
-    let device_addr = Address::from_str(&device_addr).unwrap();
-    rt_handle().spawn(connect_to_device(device_addr));
+/// Services the device announced during discovery or pairing, the device is not connected for this
+async fn device_services(device_addr: String) -> Result<Vec<String>> {
+    let address = Address::from_str(&device_addr)
+        .map_err(|_| Error::Generic(format!("Invalid device address: {device_addr}")))?;
+    let adapter = bt_manager()
+        .lock()
+        .await
+        .adapter
+        .clone()
+        .ok_or(Error::AdapterNotAvailable)?;
+    Span::current().record("adapter", adapter.name());
+    let device = adapter
+        .device(address)
+        .on_device("get services", &address)?;
+    let mut services: Vec<String> = device
+        .uuids()
+        .await
+        .on_device("get services", &address)?
+        .unwrap_or_default()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    services.sort();
+    Ok(services)
 }
 
-async fn connect_to_device(device_address: Address) {
-    let manager = bt_manager().lock().await;
-    let adapter = if let Some(adapter) = &manager.adapter {
-        adapter
-    } else {
-        warn!("Cannot connect to device because no bluetooth adapter is available");
-        return;
-    };
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_connectToDevice<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    device_addr: JString<'local>,
+) {
+    jni_entry(&mut env, "BlueManager::connectToDevice", (), |env| {
+        info!("BlueManager::connectToDevice()");
+
+        let device_addr: String = env
+            .get_string(&device_addr)
+            .expect("Getting String from env should not fail")
+            .into();
+        let span = info_span!("device", device = %device_addr, adapter = Empty);
+        spawn_guarded(
+            async move {
+                connect_to_device(device_addr).await.map_err(on_error).ok();
+            }
+            .instrument(span),
+        );
+    })
+}
 
-    let device = adapter
-        .device(device_address)
-        .expect("Device should still be available from adapter");
-    drop(manager);
+async fn connect_to_device(device_addr: String) -> Result<()> {
+    let address = Address::from_str(&device_addr)
+        .map_err(|_| Error::Generic(format!("Invalid device address: {device_addr}")))?;
+    let adapter = bt_manager()
+        .lock()
+        .await
+        .adapter
+        .clone()
+        .ok_or(Error::AdapterNotAvailable)?;
+    Span::current().record("adapter", adapter.name());
 
-    let block = async {
-        let props = device.all_properties().await.unwrap();
-        props
-    };
-    let handle = rt_handle();
-    let props = handle.block_on(block);
+    let device = adapter
+        .device(address)
+        .on_device("connect to device", &address)?;
+    let props = device
+        .all_properties()
+        .await
+        .on_device("connect to device", &address)?;
     for prop in props {
         info!("    {:?}", &prop);
     }
+    Ok(())
 }
 
 #[no_mangle]
 pub extern "system" fn Java_de_schweizer_bft_BlueManager_cancelDiscovery<'local>(
-    _env: JNIEnv<'local>,
+    mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
 ) {
-    info!("BlueManager::cancelDiscovery()");
+    jni_entry(&mut env, "BlueManager::cancelDiscovery", (), |_env| {
+        info!("BlueManager::cancelDiscovery()");
 
-    rt_handle().spawn(cancel_disocovery());
+        spawn_guarded(cancel_disocovery());
+    })
 }
 
 async fn cancel_disocovery() {
-    let state = BLUE_STATE.lock().await;
+    let state = BLUE_STATE.lock().unwrap();
     if let Some(cancel) = &state.cancel {
-        let _ = cancel.send(()).await;
+        // A cancel that is already pending is enough
+        let _ = cancel.try_send(());
     }
 }
 
 #[no_mangle]
 pub extern "system" fn Java_de_schweizer_bft_BlueManager_requestEnableBluetooth<'local>(
-    _env: JNIEnv<'local>,
+    mut env: JNIEnv<'local>,
     _obj: JObject<'local>,
 ) {
-    info!("BlueManager::requestEnableBluetooth()");
+    jni_entry(
+        &mut env,
+        "BlueManager::requestEnableBluetooth",
+        (),
+        |_env| {
+            info!("BlueManager::requestEnableBluetooth()");
+
+            if begin_power_change(AdapterInput::PowerOnStarted) {
+                spawn_guarded(async {
+                    let result = power_on().await;
+                    update_adapter_state(AdapterInput::PowerChangeFinished);
+                    result.map_err(on_error).ok();
+                });
+            }
+        },
+    )
+}
 
-    let mut is_bluetooth_enabling = rt_handle().block_on(async {
-        return IS_BLUETOOTH_ENABLING.lock().await;
-    });
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_requestDisableBluetooth<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+) {
+    jni_entry(
+        &mut env,
+        "BlueManager::requestDisableBluetooth",
+        (),
+        |_env| {
+            info!("BlueManager::requestDisableBluetooth()");
+
+            if begin_power_change(AdapterInput::PowerOffStarted) {
+                spawn_guarded(async {
+                    let adapter = bt_manager().lock().await.adapter.clone();
+                    let result = match adapter {
+                        Some(adapter) => adapter.set_powered(false).await.during("power off"),
+                        None => Err(Error::AdapterNotAvailable),
+                    };
+                    update_adapter_state(AdapterInput::PowerChangeFinished);
+                    result.map_err(on_error).ok();
+                });
+            }
+        },
+    )
+}
 
-    if !*is_bluetooth_enabling {
-        *is_bluetooth_enabling = true;
-        request_enable_bluetooth();
+/// Lifts a soft block and powers the selected adapter. A hard block is reported by the state machine instead.
+/// Without rfkill the adapter is powered right away.
+async fn power_on() -> Result<()> {
+    let switch = bt_manager().lock().await.rfkill_switch;
+    match rfkill::block_state(switch) {
+        Ok(block_state) if block_state.hard_blocked => {
+            warn!("Bluetooth is blocked by a hardware switch");
+            return Ok(());
+        }
+        Ok(block_state) if block_state.soft_blocked => {
+            rfkill::unblock_bluetooth(switch).during("unblock Bluetooth")?;
+        }
+        Ok(_) => (),
+        Err(err) => warn!("Could not read rfkill state: {err}"),
     }
-}
 
-fn request_enable_bluetooth() {
-    let command = CommandConfig {
-        command: "rfkill",
-        args: vec!["toggle", "bluetooth"],
-    };
-    util::run_command(&command);
+    // After unblocking, the adapter may appear late and powering it fails until BlueZ noticed the unblock
+    let deadline = tokio::time::Instant::now() + POWER_ON_TIMEOUT;
+    loop {
+        let adapter = bt_manager().lock().await.adapter.clone();
+        let result = match &adapter {
+            Some(adapter) => adapter.set_powered(true).await.during("power on"),
+            None => Err(Error::AdapterNotAvailable),
+        };
+        match result {
+            Ok(()) => {
+                info!("Powered on adapter {}", adapter.unwrap().name());
+                return Ok(());
+            }
+            Err(err) if tokio::time::Instant::now() >= deadline => return Err(err),
+            Err(_) => sleep(POWER_ON_RETRY_INTERVAL).await,
+        }
+    }
 }
 
 async fn sleep_and_notify(duration: Duration) {
     sleep(duration).await;
 
-    let state = BLUE_STATE.lock().await;
+    let state = BLUE_STATE.lock().unwrap();
 
     if let Some(timeout) = &state.timeout {
-        let _ = timeout.send(()).await;
+        // The discovery only waits for a single timeout
+        let _ = timeout.try_send(());
     }
 }
 
-fn device_discovered(device: &str, addr: &str) {
-    let exec = Executor::new(GLOBAL_JVM.get().unwrap().clone());
-    let _ = exec.with_attached(|env| {
-        let device_name = env.new_string(&device).unwrap();
-        let device_addr = env.new_string(&addr).unwrap();
-
-        let blue_manager_cls = env
-            .find_class("de/schweizer/bft/BlueManager")
-            .expect("BlueManger could not be found");
-
-        env.call_static_method(
-            blue_manager_cls,
-            "onDeviceDiscovered",
-            "(Ljava/lang/String;Ljava/lang/String;)V",
-            &[JValue::from(&device_name), JValue::from(&device_addr)],
-        )
-        .unwrap()
-        .v()
-    });
+/// Discovery and the discoverable countdown run on the previously selected adapter, so they are stopped
+pub(crate) async fn selected_adapter_changed() {
+    cancel_disocovery().await;
+    stop_countdown();
+    adapter_selected().await;
 }
 
-async fn discovery_stopped() {
-    let exec = Executor::new(GLOBAL_JVM.get().unwrap().clone());
-    let _ = exec.with_attached(|env| {
-        let blue_manager_cls = env
-            .find_class("de/schweizer/bft/BlueManager")
-            .expect("BlueManger could not be found");
-
-        env.call_static_method(blue_manager_cls, "onDiscoveryStopped", "()V", &[])
-            .unwrap()
-            .v()
-    });
-
-    *BLUE_STATE.lock().await = BlueState::new();
-}
-
-fn update_bluetooth_enabled(enabled: bool) {
-    let exec = Executor::new(GLOBAL_JVM.get().unwrap().clone());
-    let _ = exec.with_attached(|env| {
-        let blue_manager_cls = env
-            .find_class("de/schweizer/bft/BlueManager")
-            .expect("BlueManger could not be found");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::desktop::events::RecordingSink;
+
+    #[test]
+    fn refuses_a_second_discovery() {
+        rt_handle().block_on(async {
+            let reserved = reserve_discovery();
+            assert!(reserved.is_ok());
+            match reserve_discovery() {
+                Err(Error::Failed { kind, .. }) => assert_eq!(kind, FailureKind::InProgress),
+                Err(err) => panic!("Expected InProgress, got {err:?}"),
+                Ok(_) => panic!("Expected InProgress, got a second slot"),
+            }
+            drop(reserved);
+            assert!(reserve_discovery().is_ok());
+
+            // Released when the discovery panics as well
+            let discovery = spawn_guarded(async {
+                let _slot = reserve_discovery().unwrap();
+                panic!("Discovery failed");
+            });
+            discovery.await.unwrap();
+            assert!(reserve_discovery().is_ok());
+        });
+    }
 
-        env.call_static_method(
-            blue_manager_cls,
-            "updateBluetoothEnabled",
-            "(Z)V",
-            &[JValue::from(enabled)],
-        )
-        .unwrap()
-        .v()
-    });
+    #[test]
+    fn starts_again_after_shutdown() {
+        // Without BlueZ and rfkill, as in the sandbox the tests run in
+        for _ in 0..2 {
+            start();
+            assert_eq!(BACKGROUND_TASKS.lock().unwrap().len(), 2);
+            rt_handle().block_on(shutdown());
+            assert!(BACKGROUND_TASKS.lock().unwrap().is_empty());
+            assert!(ADAPTER_PROPERTIES_TASK.lock().unwrap().is_none());
+        }
+    }
 
-    rt_handle().spawn(async {
-        *IS_BLUETOOTH_ENABLING.lock().await = false;
-    });
+    #[test]
+    fn reports_discovered_devices() {
+        let (sink, events) = RecordingSink::new();
+        let mut reporter = DiscoveryReporter::new(&sink);
+        let phone = Address::new([0, 0, 0, 0, 0, 1]);
+        let unknown = Address::new([0, 0, 0, 0, 0, 2]);
+
+        reporter.added(phone, Some("Phone".to_string()));
+        reporter.added(phone, Some("Phone".to_string()));
+        reporter.added(unknown, None);
+        reporter.changed(phone, &DeviceProperty::Rssi(-70));
+        reporter.changed(phone, &DeviceProperty::Paired(true));
+        reporter.removed(unknown);
+        // Devices that were never reported are not reported as lost
+        reporter.removed(Address::new([0, 0, 0, 0, 0, 3]));
+        reporter.stopped();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![
+                Event::DeviceDiscovered {
+                    name: "Phone".to_string(),
+                    address: phone.to_string(),
+                },
+                Event::DeviceDiscovered {
+                    name: unknown.to_string(),
+                    address: unknown.to_string(),
+                },
+                Event::DeviceUpdated {
+                    address: phone.to_string(),
+                    name: None,
+                    rssi: Some(-70),
+                },
+                Event::DeviceLost {
+                    address: unknown.to_string(),
+                },
+                Event::DiscoveryStopped,
+            ]
+        );
+    }
 }
@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+
+use futures::FutureExt;
+use tracing::{error, warn};
+
+use jni::errors::Result as JniResult;
+use jni::objects::{GlobalRef, JObject, JValue};
+use jni::sys::jsize;
+use jni::{Executor, JNIEnv};
+
+use crate::desktop::error::{blue_error_object, Error, Result};
+use crate::desktop::guard::{panic_message, spawn_guarded};
+use crate::desktop::upcall::{self, BLUE_EXCEPTION, FALLBACK_EXCEPTION};
+
+use super::GLOBAL_JVM;
+
+/// Value a native async operation completes its future with
+#[derive(Debug, PartialEq)]
+pub(crate) enum Completion {
+    /// Completes a `CompletableFuture<Unit?>` with `null`
+    Unit,
+    /// Completes a `CompletableFuture<Array<String>>`
+    Strings(Vec<String>),
+}
+
+impl From<()> for Completion {
+    fn from(_: ()) -> Self {
+        Self::Unit
+    }
+}
+
+impl From<Vec<String>> for Completion {
+    fn from(values: Vec<String>) -> Self {
+        Self::Strings(values)
+    }
+}
+
+impl Completion {
+    fn into_object<'local>(self, env: &mut JNIEnv<'local>) -> JniResult<JObject<'local>> {
+        match self {
+            Self::Unit => Ok(JObject::null()),
+            Self::Strings(values) => {
+                let array = env.new_object_array(
+                    values.len() as jsize,
+                    "java/lang/String",
+                    JObject::null(),
+                )?;
+                for (index, value) in values.into_iter().enumerate() {
+                    let value = env.new_string(value)?;
+                    env.set_object_array_element(&array, index as jsize, value)?;
+                }
+                Ok(array.into())
+            }
+        }
+    }
+}
+
+/// `CompletableFuture` passed in from Kotlin, which awaits it in a suspending function
+pub(crate) struct CompletionHandle(GlobalRef);
+
+impl CompletionHandle {
+    pub(crate) fn new(env: &mut JNIEnv, future: &JObject) -> JniResult<Self> {
+        Ok(Self(env.new_global_ref(future)?))
+    }
+
+    /// Completes the future with the value, or exceptionally with a `BlueException` carrying the error
+    pub(crate) fn complete(self, result: Result<Completion>) {
+        let Some(jvm) = GLOBAL_JVM.get() else {
+            return;
+        };
+        let exec = Executor::new(jvm.clone());
+        let completed = exec.with_attached(|env| {
+            let completed = match result {
+                Ok(value) => {
+                    let value = value.into_object(env)?;
+                    upcall::complete_future(env, self.0.as_obj(), &value)
+                }
+                Err(error) => {
+                    let exception = blue_exception(env, error)?;
+                    upcall::complete_future_exceptionally(env, self.0.as_obj(), &exception)
+                }
+            };
+            clear_exception(env)?;
+            completed.map(|_| ())
+        });
+
+        if let Err(err) = completed {
+            warn!("Completing future failed: {err}");
+            // The coroutine would otherwise be suspended forever
+            let failed = exec.with_attached(|env| {
+                let msg = env.new_string(format!("Completing future failed: {err}"))?;
+                let exception = upcall::new_object(env, FALLBACK_EXCEPTION, &[JValue::from(&msg)])?;
+                upcall::complete_future_exceptionally(env, self.0.as_obj(), &exception)?;
+                clear_exception(env)
+            });
+            if let Err(err) = failed {
+                error!("Failing future failed: {err}");
+            }
+        }
+    }
+}
+
+fn blue_exception<'local>(env: &mut JNIEnv<'local>, error: Error) -> JniResult<JObject<'local>> {
+    let blue_error = blue_error_object(env, error)?;
+    upcall::new_object(env, BLUE_EXCEPTION, &[JValue::from(&blue_error)])
+}
+
+/// An exception thrown by a callback of the future must not stay pending on a native thread
+fn clear_exception(env: &mut JNIEnv) -> JniResult<()> {
+    if env.exception_check()? {
+        env.exception_describe()?;
+        env.exception_clear()?;
+    }
+    Ok(())
+}
+
+/// Runs the operation and turns a panic into `Error::Internal`
+async fn catch_panic_async<T>(operation: impl Future<Output = Result<T>>) -> Result<T> {
+    AssertUnwindSafe(operation)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|payload| {
+            let msg = panic_message(&*payload);
+            error!("Async operation panicked: {msg}");
+            Err(Error::Internal(msg))
+        })
+}
+
+/// Runs the operation on the runtime and completes the Kotlin future with its result.
+/// Errors are not reported through `onError`, the awaiting caller receives them as `BlueException`.
+pub(crate) fn complete_with<T, F>(env: &mut JNIEnv, future: &JObject, operation: F)
+where
+    T: Into<Completion>,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let handle =
+        CompletionHandle::new(env, future).expect("Referencing the future should not fail");
+    spawn_guarded(async move {
+        let result = catch_panic_async(operation).await;
+        handle.complete(result.map(Into::into));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::desktop::rt_handle;
+
+    #[test]
+    fn completes_with_panic_as_internal_error() {
+        let result = rt_handle().block_on(catch_panic_async(async {
+            Ok::<_, Error>(vec!["0000110a-0000-1000-8000-00805f9b34fb".to_string()])
+        }));
+        assert_eq!(
+            result.map(Completion::from),
+            Ok(Completion::Strings(vec![
+                "0000110a-0000-1000-8000-00805f9b34fb".to_string()
+            ]))
+        );
+
+        let result: Result<()> =
+            rt_handle().block_on(catch_panic_async(async { panic!("Device should exist") }));
+        assert_eq!(
+            result,
+            Err(Error::Internal("Device should exist".to_string()))
+        );
+    }
+}
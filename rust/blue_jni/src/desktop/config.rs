@@ -0,0 +1,121 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::desktop::adapter::PreferredAdapter;
+use crate::desktop::dirs;
+use crate::desktop::error::{Error, Result};
+use crate::desktop::transfer::capture::CaptureConfig;
+use crate::desktop::transfer::hook::PostReceiveHook;
+use crate::desktop::transfer::metadata::MetadataPolicy;
+use crate::desktop::transfer::placement::PlacementPolicy;
+use crate::desktop::transfer::rules::Rule;
+use crate::desktop::transfer::scanner::ScannerConfig;
+use crate::desktop::transfer::sync::SyncFolderConfig;
+use crate::desktop::transfer::watch::WatchFolderConfig;
+
+static CONFIG_FILE_NAME: &str = "config.toml";
+
+/// User configuration of the native backend, persisted as TOML in the config directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct AppConfig {
+    /// Adapter selected by the user, used whenever it is plugged in
+    pub(crate) preferred_adapter: Option<PreferredAdapter>,
+    pub(crate) receive: PlacementPolicy,
+    pub(crate) metadata: MetadataPolicy,
+    /// Auto-accept rules for incoming offers, evaluated in order
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) post_receive_hook: Option<PostReceiveHook>,
+    /// Scanner received files have to pass before they leave quarantine
+    pub(crate) scanner: Option<ScannerConfig>,
+    /// Folder whose new files are sent to a paired device automatically
+    pub(crate) watch_folder: Option<WatchFolderConfig>,
+    /// Folders kept in sync with the same folder on a paired device
+    pub(crate) sync_folders: Vec<SyncFolderConfig>,
+    /// Capture of the protocol frames of every transfer
+    pub(crate) capture: CaptureConfig,
+}
+
+impl AppConfig {
+    fn path() -> PathBuf {
+        dirs::config_dir().join(CONFIG_FILE_NAME)
+    }
+
+    fn load() -> Self {
+        let path = Self::path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                warn!("Could not read config {:?}: {err}. Using defaults", path);
+                return Self::default();
+            }
+        };
+        toml::from_str(&content).unwrap_or_else(|err| {
+            warn!("Could not parse config {:?}: {err}. Using defaults", path);
+            Self::default()
+        })
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = toml::to_string_pretty(self)
+            .map_err(|err| Error::Generic(format!("Could not serialize config: {err}")))?;
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &path)?;
+        info!("Saved config to {:?}", path);
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref APP_CONFIG: Mutex<AppConfig> = Mutex::new(AppConfig::load());
+}
+
+pub(crate) fn app_config() -> &'static Mutex<AppConfig> {
+    &APP_CONFIG
+}
+
+/// Applies `update` to the global config and persists the result
+pub(crate) fn update_config(update: impl FnOnce(&mut AppConfig)) -> Result<()> {
+    let mut config = APP_CONFIG.lock().unwrap();
+    update(&mut config);
+    config.save()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_roundtrips_through_toml() {
+        let config = AppConfig {
+            rules: serde_json::from_str(
+                r#"[{"name": "phone", "sender": "AA:BB:CC:DD:EE:FF", "action": "accept"}]"#,
+            )
+            .unwrap(),
+            post_receive_hook: Some(PostReceiveHook {
+                command: "sha256sum".to_string(),
+                args: vec!["{path}".to_string()],
+                timeout_secs: 10,
+            }),
+            ..Default::default()
+        };
+
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let parsed: AppConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.rules, config.rules);
+        assert_eq!(parsed.post_receive_hook, config.post_receive_hook);
+        assert_eq!(parsed.receive, config.receive);
+    }
+}
@@ -0,0 +1,352 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use bluer::Adapter;
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use jni::objects::{JObject, JString};
+use jni::JNIEnv;
+
+use crate::desktop::adapter_state::adapter_state;
+use crate::desktop::completion::complete_with;
+use crate::desktop::config::app_config;
+use crate::desktop::error::{Error, Result, ResultExt};
+use crate::desktop::guard::jni_entry;
+use crate::desktop::logger::log_files;
+use crate::desktop::rfkill;
+use crate::desktop::transfer::history::{transfer_history, HistoryQuery};
+use crate::desktop::transfer::now_millis;
+
+use super::bt_manager;
+
+/// Most recent transfer history entries included in a bundle
+const HISTORY_ENTRIES: usize = 100;
+/// Config values under keys containing one of these are replaced with [REDACTED]
+const SECRET_KEYS: [&str; 5] = ["password", "secret", "token", "credential", "api_key"];
+/// Command lines of hooks and scanners often carry credentials, so their arguments are always redacted
+const ARGS_KEY: &str = "args";
+const REDACTED: &str = "<redacted>";
+/// Locations of bluetoothd on the common distributions, `bluetoothctl` is asked if none exists
+const BLUETOOTHD_PATHS: [&str; 3] = [
+    "/usr/libexec/bluetooth/bluetoothd",
+    "/usr/lib/bluetooth/bluetoothd",
+    "/usr/sbin/bluetoothd",
+];
+
+/// Text file of the bundle. A section that could not be collected contains the error instead,
+/// the bundle is most needed when something is broken.
+struct Section {
+    name: &'static str,
+    content: String,
+}
+
+impl Section {
+    fn new(name: &'static str, content: Result<String>) -> Self {
+        let content = content.unwrap_or_else(|err| {
+            warn!("Could not collect {name} for the diagnostics bundle: {err:?}");
+            format!("Could not collect {name}: {err:?}\n")
+        });
+        Self { name, content }
+    }
+}
+
+/// Writes a zip with logs, system and Bluetooth state, the redacted config and recent transfers to `path`
+pub(crate) async fn export(path: PathBuf) -> Result<()> {
+    let bluetooth = Section::new("bluetooth.txt", Ok(bluetooth_info().await));
+    let rfkill_switch = bt_manager().lock().await.rfkill_switch;
+
+    // Running commands and reading files and the history database would block the runtime
+    tokio::task::spawn_blocking(move || {
+        let sections = [
+            Section::new("system.txt", Ok(system_info(rfkill_switch))),
+            bluetooth,
+            Section::new("config.toml", redacted_config()),
+            Section::new("history.json", recent_history()),
+        ];
+        write_bundle(&path, &sections, &log_files())
+    })
+    .await
+    .map_err(|err| Error::Internal(format!("Writing the diagnostics bundle failed: {err}")))??;
+    info!("Exported diagnostics bundle");
+    Ok(())
+}
+
+fn write_bundle(path: &Path, sections: &[Section], logs: &[PathBuf]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for section in sections {
+        zip.start_file(section.name, options)?;
+        zip.write_all(section.content.as_bytes())?;
+    }
+    for log in logs {
+        let Some(file_name) = log.file_name() else {
+            continue;
+        };
+        // Rotated away while the bundle is written
+        let Ok(mut file) = File::open(log) else {
+            continue;
+        };
+        zip.start_file(format!("logs/{}", file_name.to_string_lossy()), options)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn system_info(rfkill_switch: Option<u32>) -> String {
+    let mut info = String::new();
+    let _ = writeln!(info, "App version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        info,
+        "Exported at: {} ms since the Unix epoch",
+        now_millis()
+    );
+    let _ = writeln!(info, "Kernel: {}", kernel_version());
+    let _ = writeln!(info, "BlueZ: {}", bluez_version());
+    match rfkill::block_state(rfkill_switch) {
+        Ok(state) => {
+            let _ = writeln!(
+                info,
+                "Rfkill: soft blocked {}, hard blocked {}",
+                state.soft_blocked, state.hard_blocked
+            );
+        }
+        Err(err) => {
+            let _ = writeln!(info, "Rfkill: not available ({err})");
+        }
+    }
+    let _ = writeln!(info, "Adapter state: {}", adapter_state().name());
+    info
+}
+
+fn kernel_version() -> String {
+    fs::read_to_string("/proc/version")
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|err| format!("unknown ({err})"))
+}
+
+fn bluez_version() -> String {
+    let bluetoothd = BLUETOOTHD_PATHS
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .map(|path| Command::new(path).arg("--version").output());
+    let output =
+        bluetoothd.unwrap_or_else(|| Command::new("bluetoothctl").arg("--version").output());
+    match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        Ok(output) => format!("unknown ({})", output.status),
+        Err(err) => format!("unknown ({err})"),
+    }
+}
+
+/// Properties of every adapter and the devices paired with it
+async fn bluetooth_info() -> String {
+    let manager = bt_manager().lock().await.clone();
+    let mut info = String::new();
+    if !manager.is_attached() {
+        info.push_str("BlueZ is not available\n");
+    }
+    let selected = manager.adapter.as_ref().map(Adapter::name);
+    let _ = writeln!(info, "Selected adapter: {selected:?}");
+
+    for (name, adapter) in &manager.adapters {
+        let _ = writeln!(info, "\nAdapter {name}");
+        match adapter.all_properties().await {
+            Ok(properties) => {
+                for property in properties {
+                    let _ = writeln!(info, "    {property:?}");
+                }
+            }
+            Err(err) => {
+                let _ = writeln!(info, "    Properties not available: {err}");
+            }
+        }
+        let _ = writeln!(info, "Paired devices of {name}");
+        if let Err(err) = paired_devices(adapter, &mut info).await {
+            let _ = writeln!(info, "    Not available: {err}");
+        }
+    }
+    info
+}
+
+async fn paired_devices(adapter: &Adapter, info: &mut String) -> bluer::Result<()> {
+    for address in adapter.device_addresses().await? {
+        let device = adapter.device(address)?;
+        if !device.is_paired().await? {
+            continue;
+        }
+        let _ = writeln!(
+            info,
+            "    {address} {:?} connected {}, trusted {}, services {:?}",
+            device.alias().await?,
+            device.is_connected().await?,
+            device.is_trusted().await?,
+            device.uuids().await?.unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn redacted_config() -> Result<String> {
+    let serialize = || -> io::Result<String> {
+        let mut config = toml::Value::try_from(&*app_config().lock().unwrap())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        redact(&mut config);
+        toml::to_string_pretty(&config)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    };
+    serialize().during("serialize config")
+}
+
+fn redact(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if key == ARGS_KEY || SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+                    redact_all(value);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        toml::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Replaces every string, keeps the structure so the bundle still shows what was configured
+fn redact_all(value: &mut toml::Value) {
+    match value {
+        toml::Value::String(string) => *string = REDACTED.to_string(),
+        toml::Value::Table(table) => table.iter_mut().for_each(|(_, value)| redact_all(value)),
+        toml::Value::Array(values) => values.iter_mut().for_each(redact_all),
+        _ => {}
+    }
+}
+
+fn recent_history() -> Result<String> {
+    let query = HistoryQuery {
+        limit: Some(HISTORY_ENTRIES),
+        ..Default::default()
+    };
+    let records = transfer_history().lock().unwrap().query(&query)?;
+    Ok(serde_json::to_string_pretty(&records)?)
+}
+
+/// Completes `future` once the bundle is written to `path`
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_exportDiagnosticsAsync<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    path: JString<'local>,
+    future: JObject<'local>,
+) {
+    jni_entry(&mut env, "BlueManager::exportDiagnostics", (), |env| {
+        let path: String = env
+            .get_string(&path)
+            .expect("Getting String from env should not fail")
+            .into();
+        info!("BlueManager::exportDiagnostics({path})");
+
+        complete_with(env, &future, export(PathBuf::from(path)));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_in_config() {
+        let mut config: toml::Value = toml::from_str(
+            r#"
+            [post_receive_hook]
+            command = "upload"
+            args = ["--token", "abc123", "{path}"]
+            timeout_secs = 30
+
+            [scanner]
+            type = "command"
+            command = "scan"
+            api_key = "xyz"
+
+            [[sync_folders]]
+            path = "/home/me/Shared"
+            peer_address = "AA:BB:CC:DD:EE:FF"
+            "#,
+        )
+        .unwrap();
+
+        redact(&mut config);
+
+        let expected: toml::Value = toml::from_str(
+            r#"
+            [post_receive_hook]
+            command = "upload"
+            args = ["<redacted>", "<redacted>", "<redacted>"]
+            timeout_secs = 30
+
+            [scanner]
+            type = "command"
+            command = "scan"
+            api_key = "<redacted>"
+
+            [[sync_folders]]
+            path = "/home/me/Shared"
+            peer_address = "AA:BB:CC:DD:EE:FF"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn writes_sections_and_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("bft.log");
+        fs::write(&log, "INFO Adapter state: NoAdapter -> PoweredOff\n").unwrap();
+        let sections = [
+            Section::new("system.txt", Ok("Kernel: 6.8.0\n".to_string())),
+            Section::new("history.json", Err(Error::Generic("locked".to_string()))),
+        ];
+        let bundle = dir.path().join("reports").join("bft-diagnostics.zip");
+
+        write_bundle(&bundle, &sections, &[log, dir.path().join("bft.log.1")]).unwrap();
+
+        let mut zip = ZipArchive::new(File::open(&bundle).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            zip.by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        assert_eq!(read("system.txt"), "Kernel: 6.8.0\n");
+        assert_eq!(
+            read("history.json"),
+            "Could not collect history.json: Generic(\"locked\")\n"
+        );
+        assert_eq!(
+            read("logs/bft.log"),
+            "INFO Adapter state: NoAdapter -> PoweredOff\n"
+        );
+        assert_eq!(zip.len(), 3);
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static APP_DIR_NAME: &str = "bft";
+/// Locations of the well known user directories, written by xdg-user-dirs-update
+static USER_DIRS_FILE_NAME: &str = "user-dirs.dirs";
+
+/// Directory for persistent application data, following the XDG base directory spec
+/// (e.g. ~/.local/share/bft)
+pub(crate) fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", &[".local", "share"]).join(APP_DIR_NAME)
+}
+
+/// Directory for the application configuration (e.g. ~/.config/bft)
+pub(crate) fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", &[".config"]).join(APP_DIR_NAME)
+}
+
+/// Default root for received files (e.g. ~/Downloads/bft). The download directory is taken from
+/// `user-dirs.dirs`, since it is localized on many systems.
+pub(crate) fn download_dir() -> PathBuf {
+    let user_dirs = xdg_dir("XDG_CONFIG_HOME", &[".config"]).join(USER_DIRS_FILE_NAME);
+    fs::read_to_string(user_dirs)
+        .ok()
+        .and_then(|content| user_dir(&content, "XDG_DOWNLOAD_DIR", &home_dir()))
+        .unwrap_or_else(|| home_dir().join("Downloads"))
+        .join(APP_DIR_NAME)
+}
+
+fn home_dir() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+}
+
+fn xdg_dir(var: &str, home_fallback: &[&str]) -> PathBuf {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut home = home_dir();
+            for segment in home_fallback {
+                home.push(segment);
+            }
+            home
+        }
+    }
+}
+
+/// Looks up `key` in the shell-like `user-dirs.dirs` format, e.g. `XDG_DOWNLOAD_DIR="$HOME/Downloads"`.
+/// Values are either absolute or relative to `$HOME`, a value of just `$HOME` means the directory is disabled.
+fn user_dir(content: &str, key: &str, home: &Path) -> Option<PathBuf> {
+    let value = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .next_back()?;
+
+    let path = match value.strip_prefix("$HOME") {
+        Some(relative) => home.join(relative.trim_start_matches('/')),
+        None if value.starts_with('/') => PathBuf::from(value),
+        None => return None,
+    };
+    (path != home).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_user_dirs() {
+        let content = r#"
+# This file is written by xdg-user-dirs-update
+XDG_DESKTOP_DIR="$HOME/Schreibtisch"
+XDG_DOWNLOAD_DIR="$HOME/Downloads/Heruntergeladen"
+XDG_MUSIC_DIR="/data/music"
+XDG_VIDEOS_DIR="$HOME/"
+XDG_TEMPLATES_DIR="templates"
+"#;
+        let home = Path::new("/home/me");
+        assert_eq!(
+            user_dir(content, "XDG_DOWNLOAD_DIR", home),
+            Some(PathBuf::from("/home/me/Downloads/Heruntergeladen"))
+        );
+        assert_eq!(
+            user_dir(content, "XDG_MUSIC_DIR", home),
+            Some(PathBuf::from("/data/music"))
+        );
+        assert_eq!(user_dir(content, "XDG_VIDEOS_DIR", home), None);
+        assert_eq!(user_dir(content, "XDG_TEMPLATES_DIR", home), None);
+        assert_eq!(user_dir(content, "XDG_PICTURES_DIR", home), None);
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::Mutex;
+
+use bluer::{Adapter, AdapterEvent, AdapterProperty};
+use futures::{pin_mut, StreamExt};
+use lazy_static::lazy_static;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant};
+use tracing::{info, info_span, warn, Instrument};
+
+use jni::objects::{JObject, JString};
+use jni::sys::jint;
+use jni::JNIEnv;
+
+use crate::desktop::error::{on_error, Error, Result, ResultExt};
+use crate::desktop::events::{self, Event};
+use crate::desktop::guard::{jni_entry, spawn_guarded};
+
+use super::bt_manager;
+
+/// Longest time the adapter can be made discoverable. BlueZ treats a timeout of 0 as forever, which is not offered.
+pub(crate) const MAX_DISCOVERABLE_SECS: u32 = 30 * 60;
+/// Bluetooth device names are limited to 248 bytes of UTF-8
+const MAX_ALIAS_LEN: usize = 248;
+
+lazy_static! {
+    /// Reports the remaining time to Kotlin while the adapter is discoverable
+    static ref COUNTDOWN_TASK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+
+pub(crate) fn discoverable_timeout(duration_secs: i32) -> Result<u32> {
+    match u32::try_from(duration_secs) {
+        Ok(secs) if (1..=MAX_DISCOVERABLE_SECS).contains(&secs) => Ok(secs),
+        _ => Err(Error::Generic(format!(
+            "Discoverable duration must be between 1 and {MAX_DISCOVERABLE_SECS} seconds"
+        ))),
+    }
+}
+
+pub(crate) fn validate_alias(alias: &str) -> Result<String> {
+    let alias = alias.trim();
+    if alias.is_empty() {
+        return Err(Error::Generic("Adapter name must not be empty".to_string()));
+    }
+    if alias.len() > MAX_ALIAS_LEN {
+        return Err(Error::Generic(format!(
+            "Adapter name must not be longer than {MAX_ALIAS_LEN} bytes"
+        )));
+    }
+    Ok(alias.to_string())
+}
+
+/// Whole seconds left, rounded up so the countdown ends on 1 instead of 0
+fn remaining_secs(remaining: Duration) -> u32 {
+    remaining.as_millis().div_ceil(1000) as u32
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_makeDiscoverable<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    duration_secs: jint,
+) {
+    jni_entry(&mut env, "BlueManager::makeDiscoverable", (), |_env| {
+        info!("BlueManager::makeDiscoverable()");
+
+        spawn_guarded(async move {
+            make_discoverable(duration_secs)
+                .await
+                .map_err(on_error)
+                .ok();
+        });
+    })
+}
+
+async fn make_discoverable(duration_secs: i32) -> Result<()> {
+    let timeout = discoverable_timeout(duration_secs)?;
+    let adapter = bt_manager()
+        .lock()
+        .await
+        .adapter
+        .clone()
+        .ok_or(Error::AdapterNotAvailable)?;
+
+    // The timeouts have to be set first, BlueZ starts them when the mode is switched on
+    let operation = "make discoverable";
+    adapter
+        .set_discoverable_timeout(timeout)
+        .await
+        .during(operation)?;
+    adapter
+        .set_pairable_timeout(timeout)
+        .await
+        .during(operation)?;
+    adapter.set_pairable(true).await.during(operation)?;
+    adapter.set_discoverable(true).await.during(operation)?;
+    info!(
+        "Adapter {} is discoverable for {timeout} seconds",
+        adapter.name()
+    );
+
+    let span = info_span!("adapter", adapter = adapter.name());
+    let task = spawn_guarded(
+        count_down(adapter, Duration::from_secs(u64::from(timeout))).instrument(span),
+    );
+    if let Some(previous) = COUNTDOWN_TASK.lock().unwrap().replace(task) {
+        previous.abort();
+    }
+    Ok(())
+}
+
+async fn count_down(adapter: Adapter, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let events = match adapter.events().await {
+        Ok(events) => events,
+        Err(err) => {
+            warn!(
+                "Error: {err}. Events of adapter {} are not available",
+                adapter.name()
+            );
+            return;
+        }
+    };
+    pin_mut!(events);
+    let mut ticks = interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                discoverable_countdown(remaining_secs(remaining));
+            }
+            Some(event) = events.next() => {
+                // Switched off by the BlueZ timeout or another application
+                if let AdapterEvent::PropertyChanged(AdapterProperty::Discoverable(false)) = event {
+                    break;
+                }
+            }
+        }
+    }
+
+    info!("Adapter {} is not discoverable anymore", adapter.name());
+    // The adapter may have been made discoverable again in the meantime
+    let mut task = COUNTDOWN_TASK.lock().unwrap();
+    if task.as_ref().map(JoinHandle::id) == Some(tokio::task::id()) {
+        task.take();
+    }
+    drop(task);
+    discoverable_ended();
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_stopDiscoverable<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+) {
+    jni_entry(&mut env, "BlueManager::stopDiscoverable", (), |_env| {
+        info!("BlueManager::stopDiscoverable()");
+
+        spawn_guarded(async {
+            stop_discoverable().await.map_err(on_error).ok();
+        });
+    })
+}
+
+/// Ends discoverable mode and the countdown, the adapter is not pairable anymore either
+pub(crate) async fn stop_discoverable() -> Result<()> {
+    stop_countdown();
+    let adapter = bt_manager()
+        .lock()
+        .await
+        .adapter
+        .clone()
+        .ok_or(Error::AdapterNotAvailable)?;
+    adapter
+        .set_discoverable(false)
+        .await
+        .during("stop discoverable")?;
+    adapter
+        .set_pairable(false)
+        .await
+        .during("stop discoverable")?;
+    Ok(())
+}
+
+/// Stops reporting the remaining time, e.g. because another adapter was selected
+pub(crate) fn stop_countdown() {
+    let task = COUNTDOWN_TASK.lock().unwrap().take();
+    if let Some(task) = task {
+        task.abort();
+        discoverable_ended();
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_BlueManager_setAdapterAlias<'local>(
+    mut env: JNIEnv<'local>,
+    _obj: JObject<'local>,
+    alias: JString<'local>,
+) {
+    jni_entry(&mut env, "BlueManager::setAdapterAlias", (), |env| {
+        info!("BlueManager::setAdapterAlias()");
+
+        let alias: String = env
+            .get_string(&alias)
+            .expect("Getting String from env should not fail")
+            .into();
+        spawn_guarded(async move {
+            set_adapter_alias(&alias).await.map_err(on_error).ok();
+        });
+    })
+}
+
+/// BlueZ stores the alias per adapter, so it does not need to be kept in the app config
+async fn set_adapter_alias(alias: &str) -> Result<()> {
+    let alias = validate_alias(alias)?;
+    let adapter = bt_manager()
+        .lock()
+        .await
+        .adapter
+        .clone()
+        .ok_or(Error::AdapterNotAvailable)?;
+    adapter
+        .set_alias(alias.clone())
+        .await
+        .during("set adapter name")?;
+    info!("Adapter {} is now called {alias}", adapter.name());
+    Ok(())
+}
+
+fn discoverable_countdown(remaining_secs: u32) {
+    events::emit(Event::DiscoverableCountdown { remaining_secs });
+}
+
+fn discoverable_ended() {
+    events::emit(Event::DiscoverableEnded);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_discoverable_settings() {
+        assert_eq!(discoverable_timeout(120).unwrap(), 120);
+        assert!(discoverable_timeout(0).is_err());
+        assert!(discoverable_timeout(-5).is_err());
+        assert!(discoverable_timeout(MAX_DISCOVERABLE_SECS as i32 + 1).is_err());
+
+        assert_eq!(validate_alias("  Office PC ").unwrap(), "Office PC");
+        assert!(validate_alias(" ").is_err());
+        assert!(validate_alias(&"ä".repeat(125)).is_err());
+
+        assert_eq!(remaining_secs(Duration::from_millis(119_001)), 120);
+        assert_eq!(remaining_secs(Duration::from_millis(400)), 1);
+    }
+}
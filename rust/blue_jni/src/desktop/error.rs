@@ -1,82 +1,447 @@
-use jni::{
-    objects::{JObject, JValue},
-    Executor,
-};
+use std::fmt;
+use std::io;
 
-use super::GLOBAL_JVM;
+use bluer::Address;
+
+use jni::objects::{JObject, JValue};
+use jni::JNIEnv;
+
+use crate::desktop::events::{self, Event};
+use crate::desktop::transfer::protocol::ProtocolError;
+use crate::desktop::upcall::{self, BLUE_ERROR};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     Generic(String),
     DiscoveryNotPossible,
     AdapterNotAvailable,
+    FileTooLarge {
+        size: u64,
+        max: u64,
+    },
+    InsufficientSpace {
+        required: u64,
+        available: u64,
+    },
+    VerificationFailed(String),
+    FileQuarantined {
+        file_name: String,
+        reason: String,
+    },
+    /// A bug in the native library, e.g. a panic
+    Internal(String),
+    /// An operation on the adapter, a device, a connection or a file failed
+    Failed {
+        kind: FailureKind,
+        context: ErrorContext,
+    },
 }
 
-impl From<bluer::Error> for Error {
-    fn from(err: bluer::Error) -> Self {
-        match err.kind {
-            bluer::ErrorKind::DiscoveryActive => Self::DiscoveryNotPossible,
-            _ => Self::Generic("An error occured".to_string()),
+/// Reasons an operation fails, each one is a `BlueError` subclass of the same name in Kotlin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    NotPowered,
+    /// The adapter or the device is busy or not set up yet
+    NotReady,
+    AuthenticationFailed,
+    AuthenticationRejected,
+    ConnectionRefused,
+    Timeout,
+    /// The same operation is already running
+    InProgress,
+    NotSupported,
+    /// The adapter, device or service is unknown
+    DoesNotExist,
+    /// Reading or writing a connection or a file failed
+    Io,
+    /// BlueZ or the peer sent something that does not follow the protocol
+    ProtocolViolation,
+    /// The peer refused the request, e.g. an authorization or a transfer
+    PeerRejected,
+    /// BlueZ refuses the operation in the current state, e.g. pairing while the adapter is not pairable
+    NotPermitted,
+}
+
+impl FailureKind {
+    pub(crate) const ALL: [Self; 13] = [
+        Self::NotPowered,
+        Self::NotReady,
+        Self::AuthenticationFailed,
+        Self::AuthenticationRejected,
+        Self::ConnectionRefused,
+        Self::Timeout,
+        Self::InProgress,
+        Self::NotSupported,
+        Self::DoesNotExist,
+        Self::Io,
+        Self::ProtocolViolation,
+        Self::PeerRejected,
+        Self::NotPermitted,
+    ];
+
+    /// Name of the `BlueError` subclass
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::NotPowered => "NotPowered",
+            Self::NotReady => "NotReady",
+            Self::AuthenticationFailed => "AuthenticationFailed",
+            Self::AuthenticationRejected => "AuthenticationRejected",
+            Self::ConnectionRefused => "ConnectionRefused",
+            Self::Timeout => "Timeout",
+            Self::InProgress => "InProgress",
+            Self::NotSupported => "NotSupported",
+            Self::DoesNotExist => "DoesNotExist",
+            Self::Io => "Io",
+            Self::ProtocolViolation => "ProtocolViolation",
+            Self::PeerRejected => "PeerRejected",
+            Self::NotPermitted => "NotPermitted",
         }
     }
 }
-pub(crate) fn on_error(error: Error) {
-    let blue_error_class_name = "de/schweizer/bft/BlueError";
-
-    let exec = Executor::new(GLOBAL_JVM.get().unwrap().clone());
-    let _ = exec.with_attached(|env| {
-        let error_object = match error {
-            Error::Generic(msg) => {
-                let blue_error_class_name = format!("{}$Generic", blue_error_class_name);
-                let blue_error_class = env.find_class(&blue_error_class_name).unwrap();
-                let msg = env.new_string(msg).unwrap();
-                env.new_object(
-                    blue_error_class,
-                    "(Ljava/lang/String;)V",
-                    &[JValue::from(&msg)],
-                )
-                .unwrap()
+
+/// What failed and why
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The attempted operation, e.g. `start discovery`
+    pub operation: Option<String>,
+    /// Address of the remote device the operation was performed on
+    pub device: Option<String>,
+    /// Message of BlueZ or the operating system
+    pub message: String,
+}
+
+impl Error {
+    pub(crate) fn failed(kind: FailureKind, message: impl Into<String>) -> Self {
+        Self::Failed {
+            kind,
+            context: ErrorContext {
+                message: message.into(),
+                ..ErrorContext::default()
+            },
+        }
+    }
+
+    /// Records the operation and the device in a failure, other errors stay unchanged
+    fn with_context(mut self, operation: &str, device: Option<&Address>) -> Self {
+        if let Self::Failed { context, .. } = &mut self {
+            context.operation = Some(operation.to_string());
+            if let Some(device) = device {
+                context.device = Some(device.to_string());
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Generic(msg) => write!(f, "{msg}"),
+            Self::DiscoveryNotPossible => write!(f, "Discovery is not possible"),
+            Self::AdapterNotAvailable => write!(f, "No Bluetooth adapter available"),
+            Self::FileTooLarge { size, max } => {
+                write!(f, "File of {size} bytes exceeds the limit of {max} bytes")
             }
-            Error::DiscoveryNotPossible => {
-                let blue_error_class_name =
-                    format!("{}$DiscoveryNotPossible", blue_error_class_name);
-                let blue_error_class = env.find_class(&blue_error_class_name).unwrap();
-                let blue_error_instance = env
-                    .get_static_field(
-                        blue_error_class,
-                        "INSTANCE",
-                        format!("L{};", &blue_error_class_name),
-                    )
-                    .unwrap();
-                JObject::from(blue_error_instance.l().unwrap())
+            Self::InsufficientSpace {
+                required,
+                available,
+            } => write!(f, "{required} bytes required, {available} bytes available"),
+            Self::VerificationFailed(msg) => write!(f, "Verification failed: {msg}"),
+            Self::FileQuarantined { file_name, reason } => {
+                write!(f, "{file_name} was quarantined: {reason}")
             }
-            Error::AdapterNotAvailable => {
-                let blue_error_class_name =
-                    format!("{}$AdapterNotAvailable", blue_error_class_name);
-                let blue_error_class = env.find_class(&blue_error_class_name).unwrap();
-                let blue_error_instance = env
-                    .get_static_field(
-                        blue_error_class,
-                        "INSTANCE",
-                        format!("L{};", &blue_error_class_name),
-                    )
-                    .unwrap();
-                JObject::from(blue_error_instance.l().unwrap())
+            Self::Internal(msg) => write!(f, "Internal error: {msg}"),
+            Self::Failed { kind, context } => {
+                if let Some(operation) = &context.operation {
+                    write!(f, "{operation}")?;
+                    if let Some(device) = &context.device {
+                        write!(f, " on {device}")?;
+                    }
+                    write!(f, " failed: ")?;
+                }
+                write!(f, "{}", kind.name())?;
+                if !context.message.is_empty() {
+                    write!(f, " ({})", context.message)?;
+                }
+                Ok(())
             }
+        }
+    }
+}
+
+/// Adds context to failed operations
+pub(crate) trait ResultExt<T> {
+    fn during(self, operation: &str) -> Result<T>;
+    fn on_device(self, operation: &str, device: &Address) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for core::result::Result<T, E> {
+    fn during(self, operation: &str) -> Result<T> {
+        self.map_err(|err| err.into().with_context(operation, None))
+    }
+
+    fn on_device(self, operation: &str, device: &Address) -> Result<T> {
+        self.map_err(|err| err.into().with_context(operation, Some(device)))
+    }
+}
+
+impl From<bluer::Error> for Error {
+    fn from(err: bluer::Error) -> Self {
+        use bluer::{ErrorKind, InternalErrorKind};
+        use FailureKind::*;
+
+        let message = if err.message.is_empty() {
+            err.kind.to_string()
+        } else {
+            err.message
         };
+        let kind = match err.kind {
+            ErrorKind::DiscoveryActive => return Self::DiscoveryNotPossible,
+            ErrorKind::NotReady | ErrorKind::ServicesUnresolved | ErrorKind::NotRegistered => {
+                NotReady
+            }
+            ErrorKind::AuthenticationFailed | ErrorKind::AuthenticationCanceled => {
+                AuthenticationFailed
+            }
+            ErrorKind::AuthenticationRejected => AuthenticationRejected,
+            ErrorKind::ConnectionAttemptFailed => ConnectionRefused,
+            ErrorKind::AuthenticationTimeout => Timeout,
+            ErrorKind::InProgress => InProgress,
+            ErrorKind::NotSupported
+            | ErrorKind::NotAvailable
+            | ErrorKind::AdvertisementMonitorRejected => NotSupported,
+            ErrorKind::DoesNotExist | ErrorKind::NotFound => DoesNotExist,
+            ErrorKind::NotPermitted => NotPermitted,
+            ErrorKind::NotAuthorized
+            | ErrorKind::NotificationSessionStopped
+            | ErrorKind::IndicationUnconfirmed => PeerRejected,
+            ErrorKind::InvalidArguments
+            | ErrorKind::InvalidLength
+            | ErrorKind::InvalidOffset
+            | ErrorKind::Internal(
+                InternalErrorKind::InvalidUuid(_)
+                | InternalErrorKind::InvalidValue
+                | InternalErrorKind::InvalidModalias(_)
+                | InternalErrorKind::MissingKey(_),
+            ) => ProtocolViolation,
+            ErrorKind::Internal(InternalErrorKind::Io(kind)) => io_error_kind(kind),
+            ErrorKind::Internal(InternalErrorKind::DBusConnectionLost) => Io,
+            ErrorKind::Internal(InternalErrorKind::JoinError) => {
+                return Self::Internal(message);
+            }
+            ErrorKind::Failed => match failed_kind(&message) {
+                Some(kind) => kind,
+                None => return Self::Generic(message),
+            },
+            // Already connected, invalid address or name, and failures BlueZ does not classify
+            _ => return Self::Generic(message),
+        };
+        Self::failed(kind, message)
+    }
+}
 
-        let blue_manager_cls = env
-            .find_class("de/schweizer/bft/BlueManager")
-            .expect("BlueManger could not be found");
-        env.call_static_method(
-            blue_manager_cls,
-            "onError",
-            "(Lde/schweizer/bft/BlueError;)V",
-            &[JValue::from(&error_object)],
-        )
-        .unwrap()
-        .v()
-    });
+/// BlueZ reports most connection failures as `org.bluez.Error.Failed`, only the message tells them apart
+fn failed_kind(message: &str) -> Option<FailureKind> {
+    if message.contains("br-connection-refused") {
+        Some(FailureKind::ConnectionRefused)
+    } else if message.contains("br-connection-page-timeout") || message.contains("Host is down") {
+        Some(FailureKind::Timeout)
+    } else {
+        None
+    }
+}
+
+/// Files and sockets share `io::Error`, so only kinds that can only come from a connection are told apart
+fn io_error_kind(kind: io::ErrorKind) -> FailureKind {
+    match kind {
+        io::ErrorKind::TimedOut => FailureKind::Timeout,
+        io::ErrorKind::ConnectionRefused => FailureKind::ConnectionRefused,
+        _ => FailureKind::Io,
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::failed(io_error_kind(err.kind()), err.to_string())
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(err: ProtocolError) -> Self {
+        Self::failed(FailureKind::ProtocolViolation, err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Generic(err.to_string())
+    }
+}
+
+pub(crate) fn on_error(error: Error) {
+    events::emit(Event::Error(error));
+}
+
+/// Creates the `BlueError` subclass `variant` with its cached constructor
+fn new_variant<'local>(
+    env: &mut JNIEnv<'local>,
+    variant: &str,
+    arguments: &[JValue],
+) -> jni::errors::Result<JObject<'local>> {
+    upcall::new_object(env, &format!("{BLUE_ERROR}${variant}"), arguments)
+}
+
+fn optional_string<'local>(
+    env: &mut JNIEnv<'local>,
+    value: Option<String>,
+) -> jni::errors::Result<JObject<'local>> {
+    match value {
+        Some(value) => Ok(env.new_string(value)?.into()),
+        None => Ok(JObject::null()),
+    }
+}
+
+/// Converts the error into the matching `BlueError` subclass
+pub(crate) fn blue_error_object<'local>(
+    env: &mut JNIEnv<'local>,
+    error: Error,
+) -> jni::errors::Result<JObject<'local>> {
+    let error_object = match error {
+        Error::Generic(msg) => {
+            let msg = env.new_string(msg)?;
+            new_variant(env, "Generic", &[JValue::from(&msg)])?
+        }
+        Error::DiscoveryNotPossible => {
+            upcall::instance(env, &format!("{BLUE_ERROR}$DiscoveryNotPossible"))?
+        }
+        Error::AdapterNotAvailable => {
+            upcall::instance(env, &format!("{BLUE_ERROR}$AdapterNotAvailable"))?
+        }
+        Error::FileTooLarge { size, max } => new_variant(
+            env,
+            "FileTooLarge",
+            &[JValue::from(size as i64), JValue::from(max as i64)],
+        )?,
+        Error::InsufficientSpace {
+            required,
+            available,
+        } => new_variant(
+            env,
+            "InsufficientSpace",
+            &[
+                JValue::from(required as i64),
+                JValue::from(available as i64),
+            ],
+        )?,
+        Error::VerificationFailed(msg) => {
+            let msg = env.new_string(msg)?;
+            new_variant(env, "VerificationFailed", &[JValue::from(&msg)])?
+        }
+        Error::Internal(msg) => {
+            let msg = env.new_string(msg)?;
+            new_variant(env, "Internal", &[JValue::from(&msg)])?
+        }
+        Error::Failed { kind, context } => {
+            let operation = optional_string(env, context.operation)?;
+            let device = optional_string(env, context.device)?;
+            let message = env.new_string(context.message)?;
+            new_variant(
+                env,
+                kind.name(),
+                &[
+                    JValue::from(&operation),
+                    JValue::from(&device),
+                    JValue::from(&message),
+                ],
+            )?
+        }
+        Error::FileQuarantined { file_name, reason } => {
+            let file_name = env.new_string(file_name)?;
+            let reason = env.new_string(reason)?;
+            new_variant(
+                env,
+                "FileQuarantined",
+                &[JValue::from(&file_name), JValue::from(&reason)],
+            )?
+        }
+    };
+    Ok(error_object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bluer_error(kind: bluer::ErrorKind, message: &str) -> bluer::Error {
+        bluer::Error {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn maps_bluer_errors() {
+        let address = Address::new([0, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let error: bluer::Result<()> = Err(bluer_error(
+            bluer::ErrorKind::AuthenticationRejected,
+            "Pairing rejected",
+        ));
+        assert_eq!(
+            error.on_device("pair", &address),
+            Err(Error::Failed {
+                kind: FailureKind::AuthenticationRejected,
+                context: ErrorContext {
+                    operation: Some("pair".to_string()),
+                    device: Some("00:11:22:33:44:55".to_string()),
+                    message: "Pairing rejected".to_string(),
+                },
+            })
+        );
+
+        assert_eq!(
+            Error::from(bluer_error(bluer::ErrorKind::NotReady, "")),
+            Error::failed(FailureKind::NotReady, "Bluetooth device not ready")
+        );
+        assert_eq!(
+            Error::from(bluer::Error::from(io::Error::from(io::ErrorKind::TimedOut))),
+            Error::failed(FailureKind::Timeout, "timed out")
+        );
+        assert_eq!(
+            Error::from(bluer_error(bluer::ErrorKind::DiscoveryActive, "")),
+            Error::DiscoveryNotPossible
+        );
+        assert_eq!(
+            Error::from(bluer_error(bluer::ErrorKind::Failed, "Operation failed")),
+            Error::Generic("Operation failed".to_string())
+        );
+        assert_eq!(
+            Error::from(bluer_error(
+                bluer::ErrorKind::Failed,
+                "br-connection-refused"
+            )),
+            Error::failed(FailureKind::ConnectionRefused, "br-connection-refused")
+        );
+        assert_eq!(
+            Error::from(bluer_error(
+                bluer::ErrorKind::Failed,
+                "br-connection-page-timeout"
+            )),
+            Error::failed(FailureKind::Timeout, "br-connection-page-timeout")
+        );
+        assert_eq!(
+            Error::from(bluer_error(bluer::ErrorKind::Failed, "Host is down")),
+            Error::failed(FailureKind::Timeout, "Host is down")
+        );
+        assert_eq!(
+            Error::from(bluer_error(bluer::ErrorKind::NotPermitted, "Not paired")),
+            Error::failed(FailureKind::NotPermitted, "Not paired")
+        );
+        // Only failures carry context
+        assert_eq!(
+            Err::<(), _>(Error::AdapterNotAvailable).during("pair"),
+            Err(Error::AdapterNotAvailable)
+        );
+    }
 }
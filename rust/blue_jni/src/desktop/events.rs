@@ -0,0 +1,339 @@
+use std::env;
+use std::io::{self, Stdout, Write};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde_json::json;
+use tracing::warn;
+
+use crate::desktop::adapter_state::AdapterState;
+use crate::desktop::error::Error;
+use crate::desktop::transfer::protocol::MessageKind;
+use crate::desktop::transfer::watch::WatchStatus;
+use crate::desktop::transfer::TransferId;
+use crate::desktop::upcall::{self, Upcall};
+use crate::desktop::GLOBAL_JVM;
+
+/// Environment variable that additionally prints all events to stdout when set to `jsonl`
+const EVENTS_ENV: &str = "BFT_EVENTS";
+
+/// Something the backend reports to the UI
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Event {
+    DeviceDiscovered {
+        name: String,
+        address: String,
+    },
+    /// A property of a discovered device changed, only the changed one is set
+    DeviceUpdated {
+        address: String,
+        name: Option<String>,
+        rssi: Option<i16>,
+    },
+    DeviceLost {
+        address: String,
+    },
+    DiscoveryStopped,
+    StateChanged(AdapterState),
+    Error(Error),
+    TransferProgress {
+        transfer_id: TransferId,
+        transferred: u64,
+        total: u64,
+    },
+    DiscoverableCountdown {
+        remaining_secs: u32,
+    },
+    DiscoverableEnded,
+    TextReceived {
+        sender: String,
+        text: String,
+        kind: MessageKind,
+    },
+    /// The user has to accept or reject an offer no rule decided
+    TransferOffered {
+        transfer_id: TransferId,
+        sender: String,
+        sender_name: Option<String>,
+        file_name: String,
+        size: u64,
+    },
+    WatchFolderStatus {
+        path: String,
+        status: WatchStatus,
+        message: Option<String>,
+    },
+}
+
+impl Event {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::DeviceDiscovered { name, address } => {
+                json!({ "event": "device_discovered", "name": name, "address": address })
+            }
+            Self::DeviceUpdated {
+                address,
+                name,
+                rssi,
+            } => {
+                json!({ "event": "device_updated", "address": address, "name": name, "rssi": rssi })
+            }
+            Self::DeviceLost { address } => json!({ "event": "device_lost", "address": address }),
+            Self::DiscoveryStopped => json!({ "event": "discovery_stopped" }),
+            Self::StateChanged(state) => json!({ "event": "state_changed", "state": state.name() }),
+            Self::Error(error) => json!({ "event": "error", "error": format!("{error:?}") }),
+            Self::TransferProgress {
+                transfer_id,
+                transferred,
+                total,
+            } => json!({
+                "event": "transfer_progress",
+                "transfer_id": transfer_id,
+                "transferred": transferred,
+                "total": total,
+            }),
+            Self::DiscoverableCountdown { remaining_secs } => json!({
+                "event": "discoverable_countdown",
+                "remaining_secs": remaining_secs,
+            }),
+            Self::DiscoverableEnded => json!({ "event": "discoverable_ended" }),
+            // Messages may contain one-time codes, so only their length is written
+            Self::TextReceived { sender, text, kind } => json!({
+                "event": "text_received",
+                "sender": sender,
+                "kind": kind.name(),
+                "length": text.len(),
+            }),
+            Self::TransferOffered {
+                transfer_id,
+                sender,
+                sender_name,
+                file_name,
+                size,
+            } => json!({
+                "event": "transfer_offered",
+                "transfer_id": transfer_id,
+                "sender": sender,
+                "sender_name": sender_name,
+                "file_name": file_name,
+                "size": size,
+            }),
+            Self::WatchFolderStatus {
+                path,
+                status,
+                message,
+            } => json!({
+                "event": "watch_folder_status",
+                "path": path,
+                "status": status.name(),
+                "message": message,
+            }),
+        }
+    }
+}
+
+/// Receiver of backend events. The backend only talks to the UI through a sink, so it can run without a JVM.
+pub(crate) trait EventSink: Send + Sync {
+    fn emit(&self, event: Event);
+}
+
+/// Forwards events to Kotlin
+pub(crate) struct JniSink;
+
+impl EventSink for JniSink {
+    fn emit(&self, event: Event) {
+        match event {
+            Event::DeviceDiscovered { name, address } => {
+                upcall::call(Upcall::DeviceDiscovered {
+                    name: &name,
+                    address: &address,
+                });
+            }
+            Event::DeviceUpdated {
+                address,
+                name,
+                rssi,
+            } => upcall::call(Upcall::DeviceUpdated {
+                address: &address,
+                name: name.as_deref(),
+                rssi,
+            }),
+            Event::DeviceLost { address } => upcall::call(Upcall::DeviceLost { address: &address }),
+            Event::DiscoveryStopped => upcall::call(Upcall::DiscoveryStopped),
+            Event::StateChanged(state) => upcall::call(Upcall::BluetoothState(state.name())),
+            Event::Error(error) => {
+                // Without a JVM, e.g. in tests, there is nobody to report the error to
+                if GLOBAL_JVM.get().is_none() {
+                    tracing::error!("{:?}", error);
+                    return;
+                }
+                upcall::call(Upcall::Error(error));
+            }
+            Event::TransferProgress {
+                transfer_id,
+                transferred,
+                total,
+            } => upcall::call(Upcall::TransferProgress {
+                transfer_id,
+                transferred,
+                total,
+            }),
+            Event::DiscoverableCountdown { remaining_secs } => {
+                upcall::call(Upcall::DiscoverableCountdown(remaining_secs))
+            }
+            Event::DiscoverableEnded => upcall::call(Upcall::DiscoverableEnded),
+            Event::TextReceived { sender, text, kind } => upcall::call(Upcall::TextReceived {
+                sender: &sender,
+                text: &text,
+                kind: kind.name(),
+            }),
+            Event::TransferOffered {
+                transfer_id,
+                sender,
+                sender_name,
+                file_name,
+                size,
+            } => upcall::call(Upcall::TransferOffered {
+                transfer_id,
+                sender: &sender,
+                sender_name: sender_name.as_deref(),
+                file_name: &file_name,
+                size,
+            }),
+            Event::WatchFolderStatus {
+                path,
+                status,
+                message,
+            } => upcall::call(Upcall::WatchFolderStatus {
+                path: &path,
+                status: status.name(),
+                message: message.as_deref(),
+            }),
+        }
+    }
+}
+
+/// Writes every event as one JSON object per line
+pub(crate) struct JsonLinesSink<W> {
+    out: Mutex<W>,
+}
+
+impl<W> JsonLinesSink<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl JsonLinesSink<Stdout> {
+    pub(crate) fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonLinesSink<W> {
+    fn emit(&self, event: Event) {
+        let mut out = self.out.lock().unwrap();
+        if let Err(err) = writeln!(out, "{}", event.to_json()).and_then(|_| out.flush()) {
+            warn!("Could not write event: {err}");
+        }
+    }
+}
+
+/// Passes every event on to several sinks
+pub(crate) struct FanoutSink(Vec<Box<dyn EventSink>>);
+
+impl EventSink for FanoutSink {
+    fn emit(&self, event: Event) {
+        for sink in &self.0 {
+            sink.emit(event.clone());
+        }
+    }
+}
+
+/// Records events in a channel for tests
+#[cfg(test)]
+pub(crate) struct RecordingSink(Mutex<std::sync::mpsc::Sender<Event>>);
+
+#[cfg(test)]
+impl RecordingSink {
+    pub(crate) fn new() -> (Self, std::sync::mpsc::Receiver<Event>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        (Self(Mutex::new(tx)), rx)
+    }
+}
+
+#[cfg(test)]
+impl EventSink for RecordingSink {
+    fn emit(&self, event: Event) {
+        let _ = self.0.lock().unwrap().send(event);
+    }
+}
+
+lazy_static! {
+    static ref APP_SINK: FanoutSink = {
+        let mut sinks: Vec<Box<dyn EventSink>> = vec![Box::new(JniSink)];
+        if env::var(EVENTS_ENV).is_ok_and(|value| value == "jsonl") {
+            sinks.push(Box::new(JsonLinesSink::stdout()));
+        }
+        FanoutSink(sinks)
+    };
+}
+
+/// Sink of the app: Kotlin, and stdout if enabled through `BFT_EVENTS=jsonl`
+pub(crate) fn app_sink() -> &'static dyn EventSink {
+    &*APP_SINK
+}
+
+pub(crate) fn emit(event: Event) {
+    app_sink().emit(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_json_lines() {
+        let sink = JsonLinesSink::new(Vec::new());
+        sink.emit(Event::DeviceUpdated {
+            address: "00:11:22:33:44:55".to_string(),
+            name: None,
+            rssi: Some(-60),
+        });
+        sink.emit(Event::StateChanged(AdapterState::On));
+        sink.emit(Event::Error(Error::AdapterNotAvailable));
+        sink.emit(Event::TextReceived {
+            sender: "00:11:22:33:44:55".to_string(),
+            text: "123456".to_string(),
+            kind: MessageKind::Text,
+        });
+
+        let out = String::from_utf8(sink.out.into_inner().unwrap()).unwrap();
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                json!({ "event": "device_updated", "address": "00:11:22:33:44:55", "name": null, "rssi": -60 }),
+                json!({ "event": "state_changed", "state": "On" }),
+                json!({ "event": "error", "error": "AdapterNotAvailable" }),
+                json!({ "event": "text_received", "sender": "00:11:22:33:44:55", "kind": "Text", "length": 6 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn fans_out_to_all_sinks() {
+        let (first, first_events) = RecordingSink::new();
+        let (second, second_events) = RecordingSink::new();
+        let sink = FanoutSink(vec![Box::new(first), Box::new(second)]);
+        sink.emit(Event::DiscoveryStopped);
+
+        assert_eq!(first_events.try_recv(), Ok(Event::DiscoveryStopped));
+        assert_eq!(second_events.try_recv(), Ok(Event::DiscoveryStopped));
+    }
+}
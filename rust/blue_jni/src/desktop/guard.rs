@@ -0,0 +1,99 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+
+use futures::FutureExt;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use jni::JNIEnv;
+
+use crate::desktop::error::{on_error, Error};
+
+use super::rt_handle;
+
+/// Thrown into the JVM when an entry point panicked, Kotlin's `error()` throws the same
+const PANIC_EXCEPTION_CLASS: &str = "java/lang/IllegalStateException";
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown panic".to_string())
+}
+
+/// Runs `f` and returns the message of the panic if it panicked
+pub(crate) fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(&*payload))
+}
+
+/// Runs the body of a JNI entry point. Unwinding out of an `extern "system"` function aborts the whole app,
+/// so a panic is logged and thrown as a Java exception instead and `default` is returned.
+pub(crate) fn jni_entry<'local, R>(
+    env: &mut JNIEnv<'local>,
+    name: &str,
+    default: R,
+    f: impl FnOnce(&mut JNIEnv<'local>) -> R,
+) -> R {
+    match catch_panic(|| f(env)) {
+        Ok(result) => result,
+        Err(msg) => {
+            error!("{name} panicked: {msg}");
+            // A failed JNI call may already have left an exception pending, which must not be replaced
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new(PANIC_EXCEPTION_CLASS, format!("{name} failed: {msg}"));
+            }
+            default
+        }
+    }
+}
+
+/// Spawns a task on the runtime and reports a panic to Kotlin as `BlueError.Internal`
+pub(crate) fn spawn_guarded<F>(future: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    rt_handle().spawn(async move {
+        if let Err(payload) = AssertUnwindSafe(future).catch_unwind().await {
+            let msg = panic_message(&*payload);
+            error!("Background task panicked: {msg}");
+            on_error(Error::Internal(msg));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_panics() {
+        assert_eq!(catch_panic(|| 7), Ok(7));
+        assert_eq!(
+            catch_panic(|| -> u8 { panic!("static message") }),
+            Err("static message".to_string())
+        );
+        let value = 3;
+        assert_eq!(
+            catch_panic(|| -> u8 { panic!("formatted {value}") }),
+            Err("formatted 3".to_string())
+        );
+        assert_eq!(
+            catch_panic(|| std::panic::panic_any(42)),
+            Err("Unknown panic".to_string())
+        );
+    }
+
+    #[test]
+    fn guarded_task_survives_panic() {
+        // Without a JVM the error is only logged
+        let task = spawn_guarded(async { panic!("Adapter should be available") });
+        assert!(rt_handle().block_on(task).is_ok());
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let task = spawn_guarded(async move { tx.send(5).unwrap() });
+        assert!(rt_handle().block_on(task).is_ok());
+        assert_eq!(rx.recv().unwrap(), 5);
+    }
+}
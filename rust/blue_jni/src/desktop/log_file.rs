@@ -0,0 +1,148 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::desktop::dirs::data_dir;
+
+const LOG_FILE_NAME: &str = "bft.log";
+/// Size at which the log file is rotated
+pub(crate) const MAX_LOG_FILE_SIZE: u64 = 5 * 1024 * 1024;
+/// Rotated files kept besides the current one, older ones are deleted
+pub(crate) const KEPT_LOG_FILES: usize = 4;
+
+/// Directory of the log files (e.g. ~/.local/share/bft/logs)
+pub(crate) fn log_dir() -> PathBuf {
+    data_dir().join("logs")
+}
+
+/// Log file that is rotated once it would grow beyond `max_size`. `bft.log` becomes `bft.log.1`,
+/// `bft.log.1` becomes `bft.log.2` and so on, so the logs never take more than `(kept + 1) * max_size`.
+#[derive(Debug)]
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    kept: usize,
+    current: Mutex<Current>,
+}
+
+#[derive(Debug)]
+struct Current {
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(path: PathBuf, max_size: u64, kept: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            kept,
+            current: Mutex::new(Current { file, size }),
+        })
+    }
+
+    /// Opens `bft.log` in [`log_dir`]
+    pub(crate) fn open_default() -> io::Result<Self> {
+        Self::open(
+            log_dir().join(LOG_FILE_NAME),
+            MAX_LOG_FILE_SIZE,
+            KEPT_LOG_FILES,
+        )
+    }
+
+    /// The current file followed by the rotated ones that exist, newest first
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        (0..=self.kept)
+            .map(|index| self.rotated_path(index))
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{index}"));
+        self.path.with_file_name(name)
+    }
+
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        for index in (1..=self.kept).rev() {
+            let from = self.rotated_path(index - 1);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index))?;
+            }
+        }
+        if self.kept == 0 {
+            fs::remove_file(&self.path)?;
+        }
+        current.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        current.size = 0;
+        Ok(())
+    }
+
+    fn append(&self, buf: &[u8]) -> io::Result<()> {
+        let mut current = self.current.lock().unwrap();
+        // An entry larger than the limit still goes into a file of its own
+        if current.size > 0 && current.size + buf.len() as u64 > self.max_size {
+            self.rotate(&mut current)?;
+        }
+        current.file.write_all(buf)?;
+        current.size += buf.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Every write is one formatted log entry, so entries are never split across files
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.lock().unwrap().file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RotatingFile::open(dir.path().join("logs").join("bft.log"), 10, 2).unwrap();
+
+        for entry in ["first\n", "second\n", "third\n", "fourth\n"] {
+            (&log).write_all(entry.as_bytes()).unwrap();
+        }
+
+        let contents: Vec<String> = ["bft.log", "bft.log.1", "bft.log.2"]
+            .iter()
+            .map(|name| fs::read_to_string(dir.path().join("logs").join(name)).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["fourth\n", "third\n", "second\n"]);
+        assert!(!dir.path().join("logs/bft.log.3").exists());
+
+        // Reopening continues the current file
+        drop(log);
+        let log = RotatingFile::open(dir.path().join("logs/bft.log"), 10, 2).unwrap();
+        (&log).write_all(b"x\n").unwrap();
+        assert_eq!(fs::read_to_string(log.path()).unwrap(), "fourth\nx\n");
+    }
+}
@@ -1,10 +1,310 @@
+use std::cell::Cell;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use env_logger::Env;
-use log::info;
+use log::LevelFilter;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info, warn, Event, Level, Metadata, Subscriber};
+use tracing_log::{AsLog, LogTracer, NormalizeEvent};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt::layer as fmt_layer, Layer, Registry};
+
+use jni::objects::{JClass, JString};
+use jni::JNIEnv;
+
+use crate::desktop::guard::jni_entry;
+use crate::desktop::log_file::RotatingFile;
+use crate::desktop::upcall::{self, Upcall};
+
+/// Environment variable that switches the log file to one JSON object per line when set to `json`
+const LOG_FORMAT_ENV: &str = "BFT_LOG_FORMAT";
+/// Records waiting for the forwarder, further records go to stderr instead of blocking the caller
+const QUEUE_CAPACITY: usize = 1024;
+/// Most records passed to Kotlin with one attach of the forwarder thread
+const MAX_BATCH_LEN: usize = 64;
+/// Time the forwarder waits for more records before passing on a batch
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    /// Set on the forwarder thread, whose own records would otherwise be queued again
+    static IS_FORWARDER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Log file, set once the logger is installed
+static LOG_FILE: OnceLock<Option<Arc<RotatingFile>>> = OnceLock::new();
+
+/// Current log file and the rotated ones, newest first
+pub(crate) fn log_files() -> Vec<PathBuf> {
+    match LOG_FILE.get() {
+        Some(Some(file)) => file.files(),
+        _ => Vec::new(),
+    }
+}
+
+/// Log record as passed to Kotlin
+#[derive(Debug, PartialEq)]
+struct LogRecord {
+    level: Level,
+    target: String,
+    /// Message prefixed with the spans it was logged in, e.g. `discovery{adapter=hci0}: Device added`
+    message: String,
+    location: Option<String>,
+}
+
+impl LogRecord {
+    fn from_event<S>(event: &Event, ctx: &Context<S>) -> Self
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        // Records of the log crate carry their origin in fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut message = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                message.push_str(span.name());
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    if !fields.0.is_empty() {
+                        let _ = write!(message, "{{{}}}", fields.0);
+                    }
+                }
+                message.push_str(": ");
+            }
+        }
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        message.push_str(&fields.message);
+        if !fields.rest.is_empty() {
+            message.push(' ');
+            message.push_str(&fields.rest);
+        }
+
+        Self {
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message,
+            location: metadata
+                .file()
+                .zip(metadata.line())
+                .map(|(file, line)| format!("{file}:{line}")),
+        }
+    }
+}
+
+/// Fields of a span formatted as `name=value name=value`
+#[derive(Default)]
+struct SpanFields(String);
+
+impl Visit for SpanFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{}={value:?}", field.name());
+    }
 
-use self::jni::objects::JClass;
-use self::jni::JNIEnv;
-use jni;
-use jni::objects::JString;
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+}
+
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for EventFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            // Origin of records of the log crate, already part of the normalized metadata
+            name if name.starts_with("log.") => {}
+            name => {
+                if !self.rest.is_empty() {
+                    self.rest.push(' ');
+                }
+                let _ = write!(self.rest, "{name}={value:?}");
+            }
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+}
+
+/// Forwards records to Kermit through `NativeLogger.onLog`. Records are passed to Kotlin on a separate
+/// thread, so logging never waits for the JVM. env_logger writes the records that cannot be forwarded.
+struct KotlinLayer {
+    fallback: env_logger::Logger,
+    queue: SyncSender<LogRecord>,
+    is_ready: fn() -> bool,
+}
+
+impl<S> Layer<S> for KotlinLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let mut fields = SpanFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        let record = LogRecord::from_event(event, &ctx);
+        if !(self.is_ready)() || IS_FORWARDER.with(Cell::get) {
+            self.write_fallback(record);
+            return;
+        }
+        if let Err(TrySendError::Full(record) | TrySendError::Disconnected(record)) =
+            self.queue.try_send(record)
+        {
+            self.write_fallback(record);
+        }
+    }
+}
+
+impl KotlinLayer {
+    /// RUST_LOG still narrows down what is written to stderr
+    fn write_fallback(&self, record: LogRecord) {
+        let args = format_args!("{}", record.message);
+        let (file, line) = match record.location.as_deref().and_then(|l| l.rsplit_once(':')) {
+            Some((file, line)) => (Some(file), line.parse().ok()),
+            None => (None, None),
+        };
+        let log_record = log::Record::builder()
+            .level(record.level.as_log())
+            .target(&record.target)
+            .args(args)
+            .file(file)
+            .line(line)
+            .build();
+        if self.fallback.matches(&log_record) {
+            log::Log::log(&self.fallback, &log_record);
+        }
+    }
+}
+
+/// Collects the next batch of records, returns `None` once the logger is gone
+fn next_batch(queue: &Receiver<LogRecord>) -> Option<Vec<LogRecord>> {
+    let first = queue.recv().ok()?;
+    let deadline = Instant::now() + BATCH_INTERVAL;
+    let mut batch = vec![first];
+    while batch.len() < MAX_BATCH_LEN {
+        match queue.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(record) => batch.push(record),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn forward(queue: Receiver<LogRecord>) {
+    IS_FORWARDER.with(|is_forwarder| is_forwarder.set(true));
+    while let Some(batch) = next_batch(&queue) {
+        upcall::call_all(batch.iter().map(|record| Upcall::Log {
+            level: record.level.as_str(),
+            target: &record.target,
+            message: &record.message,
+            location: record.location.as_deref(),
+        }));
+    }
+}
+
+/// Events are filtered by the level set from Kotlin, spans are always kept for their context
+fn is_enabled(metadata: &Metadata) -> bool {
+    metadata.is_span() || metadata.level().as_log() <= log::max_level()
+}
+
+fn open_log_file() -> Option<Arc<RotatingFile>> {
+    match RotatingFile::open_default() {
+        Ok(file) => Some(Arc::new(file)),
+        Err(err) => {
+            eprintln!("Could not open the log file: {err}");
+            None
+        }
+    }
+}
+
+/// Installs the subscriber once, later calls only change the level
+fn install(level: LevelFilter) {
+    LOG_FILE.get_or_init(|| {
+        let fallback = env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .parse_env(Env::default())
+            .build();
+        let (queue, records) = mpsc::sync_channel(QUEUE_CAPACITY);
+        thread::Builder::new()
+            .name("bft-log-forwarder".to_string())
+            .spawn(move || forward(records))
+            .expect("Spawning the log forwarder should not fail");
+        let kotlin = KotlinLayer {
+            fallback,
+            queue,
+            is_ready: upcall::is_ready,
+        };
+
+        let log_file = open_log_file();
+        let json = env::var(LOG_FORMAT_ENV).is_ok_and(|value| value == "json");
+        let file_layer = log_file.clone().map(|file| {
+            if json {
+                fmt_layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(file)
+                    .boxed()
+            } else {
+                fmt_layer().with_ansi(false).with_writer(file).boxed()
+            }
+        });
+
+        let subscriber = Registry::default()
+            .with(filter_fn(is_enabled))
+            .with(kotlin)
+            .with(file_layer);
+        if tracing::subscriber::set_global_default(subscriber).is_err()
+            || LogTracer::init().is_err()
+        {
+            eprintln!("Another logger is already installed, native logs are not forwarded");
+        }
+        log_file
+    });
+    log::set_max_level(level);
+}
+
+fn level_filter(env: &mut JNIEnv, log_level: &JString) -> Option<LevelFilter> {
+    let log_level: String = env
+        .get_string(log_level)
+        .expect("Getting String from env should not fail")
+        .into();
+    LevelFilter::from_str(&log_level)
+        .map_err(|_| warn!("Unknown log level {log_level}"))
+        .ok()
+}
 
 #[no_mangle]
 pub extern "system" fn Java_de_schweizer_bft_NativeLogger_init<'local>(
@@ -12,11 +312,97 @@ pub extern "system" fn Java_de_schweizer_bft_NativeLogger_init<'local>(
     _class: JClass<'local>,
     log_level: JString<'local>,
 ) {
-    let log_level: String = env
-        .get_string(&log_level)
-        .expect("Getting String from env should not fail")
-        .into();
-    let log_level = log_level.to_lowercase();
-    env_logger::Builder::from_env(Env::default().default_filter_or(&log_level)).init();
-    info!("NativeLogger::init({})", &log_level);
+    jni_entry(&mut env, "NativeLogger::init", (), |env| {
+        let level = level_filter(env, &log_level).unwrap_or(LevelFilter::Info);
+        install(level);
+        info!("NativeLogger::init({level})");
+        if let Some(Some(file)) = LOG_FILE.get() {
+            info!("Logging to {}", file.path().display());
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_NativeLogger_setLevel<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    log_level: JString<'local>,
+) {
+    jni_entry(&mut env, "NativeLogger::setLevel", (), |env| {
+        if let Some(level) = level_filter(env, &log_level) {
+            log::set_max_level(level);
+            info!("NativeLogger::setLevel({level})");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::INFO,
+            target: "blue_jni::desktop".to_string(),
+            message: message.to_string(),
+            location: None,
+        }
+    }
+
+    #[test]
+    fn batches_queued_records() {
+        let (queue, records) = mpsc::sync_channel(QUEUE_CAPACITY);
+        for index in 0..MAX_BATCH_LEN + 2 {
+            queue.send(record(&index.to_string())).unwrap();
+        }
+
+        assert_eq!(next_batch(&records).unwrap().len(), MAX_BATCH_LEN);
+        assert_eq!(
+            next_batch(&records).unwrap(),
+            vec![
+                record(&MAX_BATCH_LEN.to_string()),
+                record(&(MAX_BATCH_LEN + 1).to_string())
+            ]
+        );
+        drop(queue);
+        assert_eq!(next_batch(&records), None);
+    }
+
+    #[test]
+    fn records_spans_and_fields() {
+        let (queue, records) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let layer = KotlinLayer {
+            fallback: env_logger::Builder::new().build(),
+            queue,
+            is_ready: || true,
+        };
+
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            let transfer = info_span!(
+                "transfer",
+                transfer_id = 7_u64,
+                device = "00:11:22:33:44:55"
+            );
+            let _transfer = transfer.enter();
+            let file = info_span!("incoming_file", name = tracing::field::Empty);
+            file.record("name", "report.pdf");
+            let _file = file.enter();
+            tracing::warn!(received = 512_u64, "Size mismatch");
+        });
+
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.level, Level::WARN);
+        assert_eq!(record.target, "blue_jni::desktop::logger::tests");
+        assert_eq!(
+            record.message,
+            "transfer{transfer_id=7 device=00:11:22:33:44:55}: incoming_file{name=report.pdf}: \
+             Size mismatch received=512"
+        );
+        assert!(record
+            .location
+            .unwrap()
+            .starts_with("blue_jni/src/desktop/logger.rs:"));
+    }
 }
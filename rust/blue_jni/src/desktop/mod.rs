@@ -1,18 +1,32 @@
-use bluer::Session;
 use lazy_static::lazy_static;
-use log::error;
 use std::sync::{Arc, OnceLock};
 use tokio::runtime::{Handle, Runtime};
 use tokio::sync::Mutex;
+use tracing::{error, info};
 
 use jni::objects::JClass;
 use jni::{JNIEnv, JavaVM};
 
 use crate::desktop::blue_manager::BlueManager;
+use crate::desktop::guard::jni_entry;
 
+mod adapter;
+mod adapter_state;
+mod backend;
 mod blue_manager;
+mod completion;
+mod config;
+mod diagnostics;
+mod dirs;
+mod discoverable;
 mod error;
+mod events;
+mod guard;
+mod log_file;
 mod logger;
+mod rfkill;
+mod transfer;
+mod upcall;
 
 static GLOBAL_JVM: OnceLock<Arc<JavaVM>> = OnceLock::new();
 
@@ -25,27 +39,8 @@ fn rt_handle() -> Handle {
 }
 
 lazy_static! {
-    static ref BLUETOOTH_MANAGER: Mutex<BlueManager> = {
-        let handle = rt_handle();
-        let block = async {
-            let session = Session::new()
-                .await
-                .expect("Creating bluer Session should not fail");
-            let adapter = session.default_adapter().await.ok();
-            let blue_manager = BlueManager {
-                session: session,
-                adapter: adapter,
-            };
-            blue_manager
-                .set_discovery_filter()
-                .await
-                .unwrap_or_else(|_| error!("Could not set discovery filter"));
-            blue_manager
-        };
-        let blue_manager = handle.block_on(block);
-
-        Mutex::new(blue_manager)
-    };
+    /// Starts without a BlueZ session, the backend module attaches one as soon as BlueZ is reachable
+    static ref BLUETOOTH_MANAGER: Mutex<BlueManager> = Mutex::new(BlueManager::default());
 }
 
 fn bt_manager() -> &'static Mutex<BlueManager> {
@@ -54,11 +49,34 @@ fn bt_manager() -> &'static Mutex<BlueManager> {
 
 #[no_mangle]
 pub extern "system" fn Java_de_schweizer_bft_ui_BftApp_init<'local>(
-    env: JNIEnv<'local>,
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) {
+    jni_entry(&mut env, "BftApp::init", (), |env| {
+        // Called again when the app is started after a shutdown, there is only one JVM per process
+        if GLOBAL_JVM.get().is_none() {
+            let jvm = env
+                .get_java_vm()
+                .expect("Initializing the Global JVM should not fail");
+            let _ = GLOBAL_JVM.set(Arc::new(jvm));
+        }
+        upcall::init(env)
+            .unwrap_or_else(|err| error!("Could not resolve the classes for upcalls: {err}"));
+
+        transfer::watch::restart_watcher()
+            .unwrap_or_else(|err| error!("Could not start watching the watch folder: {:?}", err));
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_ui_BftApp_shutdown<'local>(
+    mut env: JNIEnv<'local>,
     _class: JClass<'local>,
 ) {
-    let jvm = env
-        .get_java_vm()
-        .expect("Initializing the Global JVM should not fail");
-    GLOBAL_JVM.set(Arc::new(jvm)).unwrap();
+    jni_entry(&mut env, "BftApp::shutdown", (), |_env| {
+        info!("Shutting down the native backend");
+
+        transfer::watch::stop_watcher();
+        rt_handle().block_on(blue_manager::shutdown());
+    })
 }
@@ -0,0 +1,361 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+static RFKILL_DEVICE: &str = "/dev/rfkill";
+/// Holds a directory per adapter, e.g. `hci0`, with its rfkill switch as `rfkill<index>` subdirectory
+static SYSFS_BLUETOOTH: &str = "/sys/class/bluetooth";
+
+/// `struct rfkill_event` from `linux/rfkill.h`, newer kernels append fields
+const EVENT_LEN: usize = 8;
+const TYPE_ALL: u8 = 0;
+const TYPE_BLUETOOTH: u8 = 2;
+const OP_ADD: u8 = 0;
+const OP_DEL: u8 = 1;
+const OP_CHANGE: u8 = 2;
+const OP_CHANGE_ALL: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct RfkillEvent {
+    pub(crate) idx: u32,
+    pub(crate) kind: u8,
+    pub(crate) op: u8,
+    pub(crate) soft: bool,
+    pub(crate) hard: bool,
+}
+
+impl RfkillEvent {
+    pub(crate) fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < EVENT_LEN {
+            return None;
+        }
+        Some(Self {
+            idx: u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            kind: buf[4],
+            op: buf[5],
+            soft: buf[6] != 0,
+            hard: buf[7] != 0,
+        })
+    }
+
+    fn encode(&self) -> [u8; EVENT_LEN] {
+        let idx = self.idx.to_ne_bytes();
+        [
+            idx[0],
+            idx[1],
+            idx[2],
+            idx[3],
+            self.kind,
+            self.op,
+            self.soft as u8,
+            self.hard as u8,
+        ]
+    }
+}
+
+/// Block state of the rfkill switch of an adapter, or of all Bluetooth switches combined
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BlockState {
+    pub(crate) soft_blocked: bool,
+    pub(crate) hard_blocked: bool,
+}
+
+/// Tracks the Bluetooth rfkill switches by index
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RfkillSwitches {
+    switches: Vec<RfkillEvent>,
+}
+
+impl RfkillSwitches {
+    /// Applies an event, returns whether it concerned a Bluetooth switch
+    pub(crate) fn apply(&mut self, event: RfkillEvent) -> bool {
+        if event.kind != TYPE_BLUETOOTH && event.kind != TYPE_ALL {
+            return false;
+        }
+        match event.op {
+            OP_ADD | OP_CHANGE => {
+                self.switches.retain(|switch| switch.idx != event.idx);
+                self.switches.push(event);
+            }
+            OP_DEL => self.switches.retain(|switch| switch.idx != event.idx),
+            OP_CHANGE_ALL => {
+                for switch in &mut self.switches {
+                    switch.soft = event.soft;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// State of the switch with index `switch`, or of all switches combined if the switch of the adapter is unknown
+    pub(crate) fn state(&self, switch: Option<u32>) -> BlockState {
+        let switches = self
+            .switches
+            .iter()
+            .filter(|event| switch.is_none_or(|idx| event.idx == idx));
+        switches.fold(BlockState::default(), |state, event| BlockState {
+            soft_blocked: state.soft_blocked || event.soft,
+            hard_blocked: state.hard_blocked || event.hard,
+        })
+    }
+}
+
+/// Index of the rfkill switch of an adapter like `hci0`, `None` if sysfs does not list one
+pub(crate) fn adapter_switch(adapter: &str) -> Option<u32> {
+    switch_in(&Path::new(SYSFS_BLUETOOTH).join(adapter))
+}
+
+fn switch_in(adapter_dir: &Path) -> Option<u32> {
+    std::fs::read_dir(adapter_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("rfkill")?
+                .parse()
+                .ok()
+        })
+}
+
+fn open(write: bool, nonblocking: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(write);
+    if nonblocking {
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+    options.open(RFKILL_DEVICE)
+}
+
+/// Current block state of `switch`, see [RfkillSwitches::state]. On open the kernel reports every switch with an add event.
+pub(crate) fn block_state(switch: Option<u32>) -> io::Result<BlockState> {
+    let mut device = open(false, true)?;
+    let mut switches = RfkillSwitches::default();
+    let mut buf = [0u8; 64];
+    loop {
+        match device.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                if let Some(event) = RfkillEvent::parse(&buf[..len]) {
+                    switches.apply(event);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(switches.state(switch))
+}
+
+/// Removes the soft block of `switch`, or of all Bluetooth switches if the switch of the adapter is unknown.
+/// A hard block can only be lifted by the user.
+pub(crate) fn unblock_bluetooth(switch: Option<u32>) -> io::Result<()> {
+    info!("Unblocking Bluetooth through rfkill");
+    let event = RfkillEvent {
+        idx: switch.unwrap_or(0),
+        kind: TYPE_BLUETOOTH,
+        op: if switch.is_some() {
+            OP_CHANGE
+        } else {
+            OP_CHANGE_ALL
+        },
+        soft: false,
+        hard: false,
+    };
+    open(true, false)?.write_all(&event.encode())
+}
+
+/// Changes of the Bluetooth switches, read by a background thread. Dropping it stops the thread.
+pub(crate) struct SwitchWatch {
+    switches: mpsc::UnboundedReceiver<RfkillSwitches>,
+    /// eventfd the thread polls next to the rfkill device
+    stop: Arc<File>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl SwitchWatch {
+    pub(crate) async fn recv(&mut self) -> Option<RfkillSwitches> {
+        self.switches.recv().await
+    }
+}
+
+impl Drop for SwitchWatch {
+    fn drop(&mut self) {
+        if let Err(err) = (&*self.stop).write_all(&1u64.to_ne_bytes()) {
+            warn!("Could not stop reading rfkill events: {err}");
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reports every change of the Bluetooth switches
+pub(crate) fn watch_switches() -> io::Result<SwitchWatch> {
+    watch(open(false, false)?)
+}
+
+fn watch(mut device: File) -> io::Result<SwitchWatch> {
+    // SAFETY: eventfd has no preconditions
+    let stop = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if stop < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor is valid and owned by nothing else
+    let stop = Arc::new(unsafe { File::from_raw_fd(stop) });
+    let (tx, rx) = mpsc::unbounded_channel();
+    let thread_stop = stop.clone();
+    let thread = thread::Builder::new()
+        .name("bft-rfkill".to_string())
+        .spawn(move || {
+            let mut switches = RfkillSwitches::default();
+            let mut buf = [0u8; 64];
+            loop {
+                match wait_readable(&device, &thread_stop) {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        warn!("Waiting for rfkill events failed: {err}");
+                        break;
+                    }
+                }
+                let len = match device.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        warn!("Reading rfkill events failed: {err}");
+                        break;
+                    }
+                };
+                let Some(event) = RfkillEvent::parse(&buf[..len]) else {
+                    continue;
+                };
+                let previous = switches.clone();
+                if switches.apply(event)
+                    && switches != previous
+                    && tx.send(switches.clone()).is_err()
+                {
+                    break;
+                }
+            }
+        })?;
+    Ok(SwitchWatch {
+        switches: rx,
+        stop,
+        thread: Some(thread),
+    })
+}
+
+/// Blocks until `device` can be read, returns `false` once `stop` was signalled
+fn wait_readable(device: &File, stop: &File) -> io::Result<bool> {
+    let mut fds = [
+        libc::pollfd {
+            fd: device.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stop.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    // SAFETY: both descriptors stay open for the call, the length matches the array
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fds[1].revents == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(idx: u32, kind: u8, op: u8, soft: bool, hard: bool) -> RfkillEvent {
+        RfkillEvent {
+            idx,
+            kind,
+            op,
+            soft,
+            hard,
+        }
+    }
+
+    #[test]
+    fn parses_events() {
+        let encoded = event(3, TYPE_BLUETOOTH, OP_CHANGE, true, false).encode();
+        assert_eq!(
+            RfkillEvent::parse(&encoded),
+            Some(event(3, TYPE_BLUETOOTH, OP_CHANGE, true, false))
+        );
+        // Extended events of newer kernels
+        let mut extended = encoded.to_vec();
+        extended.extend_from_slice(&[0, 0]);
+        assert!(RfkillEvent::parse(&extended).is_some());
+        assert_eq!(RfkillEvent::parse(&encoded[..7]), None);
+    }
+
+    #[test]
+    fn combines_bluetooth_switches() {
+        let mut switches = RfkillSwitches::default();
+        // WLAN switches are ignored
+        assert!(!switches.apply(event(0, 1, OP_ADD, true, true)));
+        switches.apply(event(1, TYPE_BLUETOOTH, OP_ADD, true, false));
+        switches.apply(event(2, TYPE_BLUETOOTH, OP_ADD, false, true));
+        assert_eq!(
+            switches.state(None),
+            BlockState {
+                soft_blocked: true,
+                hard_blocked: true
+            }
+        );
+        // The switch of the selected adapter only
+        assert_eq!(
+            switches.state(Some(1)),
+            BlockState {
+                soft_blocked: true,
+                hard_blocked: false
+            }
+        );
+        assert_eq!(switches.state(Some(5)), BlockState::default());
+
+        switches.apply(event(0, TYPE_BLUETOOTH, OP_CHANGE_ALL, false, false));
+        switches.apply(event(2, TYPE_BLUETOOTH, OP_DEL, false, false));
+        assert_eq!(switches.state(None), BlockState::default());
+    }
+
+    #[test]
+    fn stops_watching_when_dropped() {
+        let (reader, mut writer) = io::pipe().unwrap();
+        let mut watch = watch(File::from(std::os::fd::OwnedFd::from(reader))).unwrap();
+        writer
+            .write_all(&event(1, TYPE_BLUETOOTH, OP_ADD, true, false).encode())
+            .unwrap();
+        let switches = crate::desktop::rt_handle().block_on(watch.recv()).unwrap();
+        assert!(switches.state(Some(1)).soft_blocked);
+        // Joins the thread, which is blocked waiting for the next event
+        drop(watch);
+    }
+
+    #[test]
+    fn finds_switch_of_adapter() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("hci0:11")).unwrap();
+        std::fs::create_dir(dir.path().join("rfkill7")).unwrap();
+        assert_eq!(switch_in(dir.path()), Some(7));
+        assert_eq!(switch_in(&dir.path().join("hci0:11")), None);
+        assert_eq!(switch_in(&dir.path().join("missing")), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::{AbortHandle, Abortable};
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+
+use crate::desktop::error::{Error, Result};
+
+/// Time between two checks whether the cancelled transfers ended
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Running {
+    cancel: watch::Sender<bool>,
+    abort: AbortHandle,
+}
+
+lazy_static! {
+    /// Transfers currently running, by a key only used to deregister them
+    static ref RUNNING: Mutex<HashMap<u64, Running>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// Tells the connection of a transfer that the transfer was cancelled
+#[derive(Clone, Debug)]
+pub(crate) struct CancelSignal(watch::Receiver<bool>);
+
+impl CancelSignal {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once the transfer is cancelled, never if it ends before
+    pub(crate) async fn cancelled(&self) {
+        let mut receiver = self.0.clone();
+        if receiver.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Removes a transfer from [RUNNING] however it ends
+struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.0);
+    }
+}
+
+pub(crate) fn cancelled() -> Error {
+    Error::Generic("The transfer was cancelled".to_string())
+}
+
+/// Runs `transfer` until it ends or [cancel_all] stops it.
+/// The transfer gets the signal for its connection, which tells the peer once it fires.
+pub(crate) async fn cancellable<T, F, Fut>(transfer: F) -> Result<T>
+where
+    F: FnOnce(CancelSignal) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let (cancel, signal) = watch::channel(false);
+    let (abort, abort_registration) = AbortHandle::new_pair();
+    let key = NEXT_KEY.fetch_add(1, Ordering::Relaxed);
+    RUNNING
+        .lock()
+        .unwrap()
+        .insert(key, Running { cancel, abort });
+    let _registration = Registration(key);
+
+    Abortable::new(transfer(CancelSignal(signal)), abort_registration)
+        .await
+        .unwrap_or_else(|_| Err(cancelled()))
+}
+
+/// Cancels every running transfer, so their peers learn about it.
+/// Transfers which are still running after `grace` are aborted.
+pub(crate) async fn cancel_all(grace: Duration) {
+    let count = {
+        let running = RUNNING.lock().unwrap();
+        for transfer in running.values() {
+            // Fails only if the transfer dropped its connection already
+            let _ = transfer.cancel.send(true);
+        }
+        running.len()
+    };
+    if count == 0 {
+        return;
+    }
+    info!("Cancelling {count} transfers");
+
+    let deadline = Instant::now() + grace;
+    while !RUNNING.lock().unwrap().is_empty() && Instant::now() < deadline {
+        sleep(POLL_INTERVAL).await;
+    }
+    let running = RUNNING.lock().unwrap();
+    if !running.is_empty() {
+        warn!("Aborting {} transfers", running.len());
+    }
+    for transfer in running.values() {
+        transfer.abort.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::desktop::rt_handle;
+    use crate::desktop::transfer::history::{Direction, Outcome};
+    use crate::desktop::transfer::placement::PlacementPolicy;
+    use crate::desktop::transfer::protocol::{FileMetadata, FileOffer, Frame};
+    use crate::desktop::transfer::rules::{Rule, RuleAction};
+    use crate::desktop::transfer::session::{
+        receive, Connection, Peer, ReceiveSettings, TransferLog,
+    };
+    use crate::desktop::transfer::to_hex;
+
+    #[test]
+    fn tells_the_peer_when_cancelled() {
+        let root = tempfile::tempdir().unwrap();
+        let settings = ReceiveSettings {
+            placement: PlacementPolicy {
+                download_root: root.path().to_path_buf(),
+                ..Default::default()
+            },
+            rules: vec![Rule {
+                name: "Everything".to_string(),
+                sender: None,
+                extensions: Vec::new(),
+                min_size: None,
+                max_size: None,
+                time_window: None,
+                action: RuleAction::Accept,
+            }],
+            ..Default::default()
+        };
+        let peer = Peer {
+            address: "00:00:00:00:00:01".to_string(),
+            name: None,
+        };
+        let offer = FileOffer {
+            name: "notes.txt".to_string(),
+            size: 5,
+            sha256: to_hex(&Sha256::digest(b"notes")),
+            metadata: FileMetadata::default(),
+        };
+        let (sending, receiving) = duplex(64 * 1024);
+        let mut log = TransferLog::new(2, Direction::Received, &peer);
+
+        let (reply, received) = rt_handle().block_on(async {
+            let log = &mut log;
+            tokio::join!(
+                async {
+                    let mut connection = Connection::new(sending);
+                    connection.write_frame(&Frame::Offer(offer)).await.unwrap();
+                    assert_eq!(connection.read_frame().await.unwrap(), Some(Frame::Accept));
+                    cancel_all(Duration::from_secs(5)).await;
+                    connection.read_frame().await.unwrap()
+                },
+                cancellable(|cancel| async move {
+                    let mut connection = Connection::new(receiving).with_cancel(cancel);
+                    receive(&mut connection, 2, &peer, &settings, log).await
+                }),
+            )
+        });
+
+        assert_eq!(reply, Some(Frame::Cancel));
+        assert_eq!(received.unwrap_err().to_string(), cancelled().to_string());
+        assert_eq!(log.finish().unwrap().outcome, Outcome::Cancelled);
+        assert!(RUNNING.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+//! Capture of the frames exchanged during a transfer, to find out what went over the wire.
+//!
+//! A capture is a JSON-lines file, every line holds one [CaptureRecord].
+//! Like [super::protocol], this module has no dependencies on the rest of the crate, so the replay tool
+//! in `src/bin` can include it.
+use std::fs::{self, File};
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::protocol::{Frame, FrameType, ProtocolError, MAX_PAYLOAD_LEN};
+
+/// Bytes of file data kept per data frame by default
+const DEFAULT_MAX_PAYLOAD_LEN: usize = 512;
+
+/// Whether frames are captured. Capturing is opt-in, payloads contain file data and text messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct CaptureConfig {
+    pub(crate) enabled: bool,
+    /// Data payloads are truncated to this many bytes. Control frames and messages are kept complete,
+    /// the replay needs them.
+    pub(crate) max_payload_len: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Creates the capture file of a transfer in `dir`, returns `None` if capturing is disabled
+    pub(crate) fn open(&self, dir: &Path, transfer_id: u64) -> io::Result<Option<Capture<File>>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        fs::create_dir_all(dir)?;
+        let file = File::create(capture_path(dir, transfer_id))?;
+        Ok(Some(Capture::new(file, self.max_payload_len)))
+    }
+}
+
+/// Capture file of a transfer, e.g. `transfer-42.jsonl`
+pub(crate) fn capture_path(dir: &Path, transfer_id: u64) -> PathBuf {
+    dir.join(format!("transfer-{transfer_id}.jsonl"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+/// A single captured frame
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CaptureRecord {
+    pub(crate) direction: Direction,
+    /// Milliseconds since the Unix epoch
+    pub(crate) timestamp_ms: u64,
+    pub(crate) frame_type: FrameType,
+    /// Length of the complete payload
+    pub(crate) length: usize,
+    /// Lowercase hex of the payload, truncated for data frames
+    pub(crate) payload: String,
+}
+
+impl CaptureRecord {
+    pub(crate) fn new(
+        direction: Direction,
+        timestamp_ms: u64,
+        frame: &Frame,
+        max_payload_len: usize,
+    ) -> Self {
+        let payload = frame.payload();
+        let frame_type = frame.frame_type();
+        let kept = if frame_type == FrameType::Data {
+            max_payload_len
+        } else {
+            payload.len()
+        };
+        Self {
+            direction,
+            timestamp_ms,
+            frame_type,
+            length: payload.len(),
+            payload: payload
+                .iter()
+                .take(kept)
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn payload_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        let hex = self.payload.as_bytes();
+        if !hex.len().is_multiple_of(2) {
+            return Err(ProtocolError::MalformedPayload(
+                "Odd number of hex digits".to_string(),
+            ));
+        }
+        hex.chunks(2)
+            .map(|digits| {
+                std::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| {
+                        ProtocolError::MalformedPayload(format!(
+                            "Invalid hex digits {:?}",
+                            String::from_utf8_lossy(digits)
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.payload.len() / 2 < self.length
+    }
+
+    /// Rebuilds the captured frame. Only the length of data frames matters to the receiver,
+    /// so a truncated data payload is padded with zeros. Other frames need their complete payload,
+    /// which edited captures may lack.
+    pub(crate) fn frame(&self) -> Result<Frame, ProtocolError> {
+        let mut payload = self.payload_bytes()?;
+        if self.is_truncated() {
+            if self.frame_type != FrameType::Data {
+                return Err(ProtocolError::MalformedPayload(format!(
+                    "Payload truncated to {} of {} bytes",
+                    payload.len(),
+                    self.length
+                )));
+            }
+            if self.length > MAX_PAYLOAD_LEN {
+                return Err(ProtocolError::FrameTooLarge(self.length));
+            }
+            payload.resize(self.length, 0);
+        }
+        Frame::from_payload(self.frame_type, &payload)
+    }
+}
+
+/// Writes the frames of a transfer to a capture file
+pub(crate) struct Capture<W: Write> {
+    out: W,
+    max_payload_len: usize,
+}
+
+impl<W: Write> Capture<W> {
+    pub(crate) fn new(out: W, max_payload_len: usize) -> Self {
+        Self {
+            out,
+            max_payload_len,
+        }
+    }
+
+    pub(crate) fn record(&mut self, direction: Direction, frame: &Frame) -> io::Result<()> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        let record = CaptureRecord::new(direction, timestamp_ms, frame, self.max_payload_len);
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        // One write per frame, so a crash loses at most the frame being written
+        self.out.write_all(&line)
+    }
+}
+
+/// Reads the records of a capture file, blank lines are skipped
+pub(crate) fn read_capture(reader: impl BufRead) -> io::Result<Vec<CaptureRecord>> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(ErrorKind::InvalidData, format!("Line {}: {err}", index + 1))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::protocol::FileOffer;
+    use super::*;
+
+    #[test]
+    fn captures_and_rebuilds_frames() {
+        let offer = Frame::Offer(FileOffer {
+            name: "photo.jpg".to_string(),
+            size: 2048,
+            sha256: "ab".repeat(32),
+            metadata: Default::default(),
+        });
+        let mut buf = Vec::new();
+        let mut capture = Capture::new(&mut buf, 512);
+        capture.record(Direction::Received, &offer).unwrap();
+        capture.record(Direction::Sent, &Frame::Accept).unwrap();
+        capture
+            .record(Direction::Received, &Frame::Data(vec![7; 2048]))
+            .unwrap();
+        capture
+            .record(Direction::Sent, &Frame::Reject("x".repeat(600)))
+            .unwrap();
+
+        let records = read_capture(buf.as_slice()).unwrap();
+        let types: Vec<FrameType> = records.iter().map(|record| record.frame_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                FrameType::Offer,
+                FrameType::Accept,
+                FrameType::Data,
+                FrameType::Reject
+            ]
+        );
+        assert_eq!(records[0].frame(), Ok(offer));
+        assert_eq!(records[1].direction, Direction::Sent);
+
+        let data = &records[2];
+        assert_eq!((data.length, data.payload.len()), (2048, 1024));
+        assert!(data.is_truncated());
+        // Only the captured prefix is kept, the rest is padded
+        let mut expected = vec![7; 512];
+        expected.resize(2048, 0);
+        assert_eq!(data.frame(), Ok(Frame::Data(expected)));
+
+        // Control frames are never truncated
+        assert!(!records[3].is_truncated());
+        assert_eq!(records[3].frame(), Ok(Frame::Reject("x".repeat(600))));
+
+        let mut edited = records[3].clone();
+        edited.payload.truncate(20);
+        assert_eq!(
+            edited.frame(),
+            Err(ProtocolError::MalformedPayload(
+                "Payload truncated to 10 of 600 bytes".to_string()
+            ))
+        );
+    }
+}
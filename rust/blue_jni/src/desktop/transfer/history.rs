@@ -0,0 +1,475 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::sys::{jboolean, jint, jlong, jstring, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+
+use crate::desktop::dirs;
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::guard::jni_entry;
+
+use super::TransferId;
+
+static HISTORY_FILE_NAME: &str = "history.jsonl";
+/// Next to the history, holds the next transfer id so ids are never handed out twice
+static NEXT_ID_EXTENSION: &str = "next-id";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    Completed,
+    Failed,
+    Cancelled,
+    Rejected,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileRecord {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) sha256: Option<String>,
+}
+
+/// A single sent or received transfer as stored in the history log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TransferRecord {
+    pub(crate) id: TransferId,
+    pub(crate) direction: Direction,
+    pub(crate) peer_address: String,
+    pub(crate) peer_name: Option<String>,
+    pub(crate) files: Vec<FileRecord>,
+    /// Unix timestamps in milliseconds
+    pub(crate) started_at: u64,
+    pub(crate) ended_at: u64,
+    pub(crate) outcome: Outcome,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HistoryQuery {
+    pub(crate) peer_address: Option<String>,
+    pub(crate) direction: Option<Direction>,
+    pub(crate) limit: Option<usize>,
+}
+
+/// Append-only JSON-lines log of all transfers.
+/// Every line holds one [TransferRecord]; deleting rewrites the log without the removed records.
+#[derive(Debug)]
+pub(crate) struct TransferHistory {
+    path: PathBuf,
+    next_id: Option<TransferId>,
+}
+
+impl TransferHistory {
+    pub(crate) fn open(path: PathBuf) -> Self {
+        Self {
+            path,
+            next_id: None,
+        }
+    }
+
+    /// Reserves the id for a new transfer, so the record can be written once the transfer ends.
+    /// The counter is persisted, ids of transfers that were never recorded or were deleted are not reused,
+    /// their captures would be overwritten.
+    pub(crate) fn next_id(&mut self) -> Result<TransferId> {
+        let id = match self.next_id {
+            Some(id) => id,
+            // Histories written before the counter existed only have their records
+            None => self
+                .read_all()?
+                .iter()
+                .map(|r| r.id + 1)
+                .chain(self.stored_next_id()?)
+                .max()
+                .unwrap_or(1),
+        };
+        self.store_next_id(id + 1)?;
+        self.next_id = Some(id + 1);
+        Ok(id)
+    }
+
+    fn next_id_path(&self) -> PathBuf {
+        self.path.with_extension(NEXT_ID_EXTENSION)
+    }
+
+    fn stored_next_id(&self) -> Result<Option<TransferId>> {
+        match fs::read_to_string(self.next_id_path()) {
+            Ok(content) => match content.trim().parse() {
+                Ok(id) => Ok(Some(id)),
+                Err(err) => {
+                    warn!("Ignoring corrupt transfer id counter: {err}");
+                    Ok(None)
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store_next_id(&self, id: TransferId) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let path = self.next_id_path();
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, id.to_string())?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub(crate) fn record(&mut self, record: &TransferRecord) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+
+        if self.next_id.is_some_and(|id| id <= record.id) {
+            self.next_id = Some(record.id + 1);
+        }
+        info!(
+            "Recorded {:?} transfer {} with {} ({:?})",
+            record.direction, record.id, record.peer_address, record.outcome
+        );
+        Ok(())
+    }
+
+    /// Returns all matching records, most recent first
+    pub(crate) fn query(&self, query: &HistoryQuery) -> Result<Vec<TransferRecord>> {
+        let mut records: Vec<TransferRecord> = self
+            .read_all()?
+            .into_iter()
+            .filter(|record| {
+                query
+                    .peer_address
+                    .as_ref()
+                    .is_none_or(|addr| record.peer_address.eq_ignore_ascii_case(addr))
+            })
+            .filter(|record| query.direction.is_none_or(|dir| record.direction == dir))
+            .collect();
+        records.sort_by(|a, b| b.started_at.cmp(&a.started_at).then(b.id.cmp(&a.id)));
+        if let Some(limit) = query.limit {
+            records.truncate(limit);
+        }
+        Ok(records)
+    }
+
+    /// Removes the record with the given id. Returns false if no such record exists.
+    pub(crate) fn delete(&mut self, id: TransferId) -> Result<bool> {
+        let mut records = self.read_all()?;
+        let len = records.len();
+        records.retain(|record| record.id != id);
+        if records.len() == len {
+            return Ok(false);
+        }
+        self.rewrite(&records)?;
+        Ok(true)
+    }
+
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(crate) fn export_json(&self, path: &Path) -> Result<()> {
+        let records = self.query(&HistoryQuery::default())?;
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &records)?;
+        Ok(())
+    }
+
+    pub(crate) fn export_csv(&self, path: &Path) -> Result<()> {
+        let records = self.query(&HistoryQuery::default())?;
+        let mut file = File::create(path)?;
+        writeln!(
+            file,
+            "id,direction,peer_address,peer_name,file_names,file_sizes,sha256,started_at,ended_at,outcome,error"
+        )?;
+        for record in &records {
+            let join = |f: &dyn Fn(&FileRecord) -> String| {
+                record.files.iter().map(f).collect::<Vec<_>>().join(";")
+            };
+            let fields = [
+                record.id.to_string(),
+                format!("{:?}", record.direction).to_lowercase(),
+                record.peer_address.clone(),
+                record.peer_name.clone().unwrap_or_default(),
+                join(&|file| file.name.clone()),
+                join(&|file| file.size.to_string()),
+                join(&|file| file.sha256.clone().unwrap_or_default()),
+                record.started_at.to_string(),
+                record.ended_at.to_string(),
+                format!("{:?}", record.outcome).to_lowercase(),
+                record.error.clone().unwrap_or_default(),
+            ];
+            let line = fields
+                .iter()
+                .map(|field| csv_escape(field))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(file, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn read_all(&self) -> Result<Vec<TransferRecord>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut records = Vec::new();
+        for (line_nr, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(err) => warn!("Skipping corrupt history line {}: {err}", line_nr + 1),
+            }
+        }
+        Ok(records)
+    }
+
+    fn rewrite(&self, records: &[TransferRecord]) -> Result<()> {
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut file = File::create(&tmp_path)?;
+        for record in records {
+            serde_json::to_writer(&mut file, record)?;
+            file.write_all(b"\n")?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+lazy_static! {
+    static ref TRANSFER_HISTORY: Mutex<TransferHistory> = Mutex::new(TransferHistory::open(
+        dirs::data_dir().join(HISTORY_FILE_NAME)
+    ));
+}
+
+pub(crate) fn transfer_history() -> &'static Mutex<TransferHistory> {
+    &TRANSFER_HISTORY
+}
+
+fn optional_string(env: &mut JNIEnv, string: &JString) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    env.get_string(string).ok().map(Into::into)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferHistory_query<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    peer_address: JString<'local>,
+    limit: jint,
+) -> jstring {
+    jni_entry(&mut env, "TransferHistory::query", ptr::null_mut(), |env| {
+        info!("TransferHistory::query()");
+
+        let query = HistoryQuery {
+            peer_address: optional_string(env, &peer_address),
+            direction: None,
+            limit: usize::try_from(limit).ok().filter(|limit| *limit > 0),
+        };
+        let json = transfer_history()
+            .lock()
+            .unwrap()
+            .query(&query)
+            .and_then(|records| Ok(serde_json::to_string(&records)?));
+
+        match json {
+            Ok(json) => env
+                .new_string(json)
+                .map(|json| json.into_raw())
+                .unwrap_or(ptr::null_mut()),
+            Err(err) => {
+                on_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferHistory_delete<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    id: jlong,
+) -> jboolean {
+    jni_entry(&mut env, "TransferHistory::delete", JNI_FALSE, |_env| {
+        info!("TransferHistory::delete({id})");
+
+        match transfer_history().lock().unwrap().delete(id as TransferId) {
+            Ok(true) => JNI_TRUE,
+            Ok(false) => JNI_FALSE,
+            Err(err) => {
+                on_error(err);
+                JNI_FALSE
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferHistory_clear<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) {
+    jni_entry(&mut env, "TransferHistory::clear", (), |_env| {
+        info!("TransferHistory::clear()");
+
+        transfer_history()
+            .lock()
+            .unwrap()
+            .clear()
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferHistory_export<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    format: JString<'local>,
+) {
+    jni_entry(&mut env, "TransferHistory::export", (), |env| {
+        let path: String = env
+            .get_string(&path)
+            .expect("Getting String from env should not fail")
+            .into();
+        let format: String = env
+            .get_string(&format)
+            .expect("Getting String from env should not fail")
+            .into();
+        info!("TransferHistory::export({path}, {format})");
+
+        let history = transfer_history().lock().unwrap();
+        let result = match format.as_str() {
+            "CSV" => history.export_csv(Path::new(&path)),
+            "JSON" => history.export_json(Path::new(&path)),
+            _ => Err(Error::Generic(format!(
+                "Unsupported export format: {format}"
+            ))),
+        };
+        result.map_err(on_error).ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: TransferId, peer: &str, started_at: u64) -> TransferRecord {
+        TransferRecord {
+            id,
+            direction: Direction::Received,
+            peer_address: peer.to_string(),
+            peer_name: Some("Phone, \"work\"".to_string()),
+            files: vec![FileRecord {
+                name: "photo.jpg".to_string(),
+                size: 1024,
+                sha256: Some("abc".to_string()),
+            }],
+            started_at,
+            ended_at: started_at + 10,
+            outcome: Outcome::Completed,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn record_query_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = TransferHistory::open(dir.path().join(HISTORY_FILE_NAME));
+
+        let first = history.next_id().unwrap();
+        history
+            .record(&record(first, "AA:BB:CC:DD:EE:FF", 100))
+            .unwrap();
+        let second = history.next_id().unwrap();
+        history
+            .record(&record(second, "11:22:33:44:55:66", 200))
+            .unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        let all = history.query(&HistoryQuery::default()).unwrap();
+        assert_eq!(all.iter().map(|r| r.id).collect::<Vec<_>>(), vec![2, 1]);
+
+        let by_peer = history
+            .query(&HistoryQuery {
+                peer_address: Some("aa:bb:cc:dd:ee:ff".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(by_peer, vec![record(1, "AA:BB:CC:DD:EE:FF", 100)]);
+
+        assert!(history.delete(1).unwrap());
+        assert!(!history.delete(1).unwrap());
+        assert_eq!(history.query(&HistoryQuery::default()).unwrap().len(), 1);
+
+        // Ids are not reused after a restart, even if they were never recorded or are deleted
+        assert_eq!(history.next_id().unwrap(), 3);
+        history.clear().unwrap();
+        let mut reopened = TransferHistory::open(dir.path().join(HISTORY_FILE_NAME));
+        assert_eq!(reopened.next_id().unwrap(), 4);
+    }
+
+    #[test]
+    fn export_csv_escapes_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = TransferHistory::open(dir.path().join(HISTORY_FILE_NAME));
+        history
+            .record(&record(1, "AA:BB:CC:DD:EE:FF", 100))
+            .unwrap();
+
+        let csv_path = dir.path().join("history.csv");
+        history.export_csv(&csv_path).unwrap();
+        let csv = fs::read_to_string(csv_path).unwrap();
+        let row = csv.lines().nth(1).unwrap();
+        assert_eq!(
+            row,
+            "1,received,AA:BB:CC:DD:EE:FF,\"Phone, \"\"work\"\"\",photo.jpg,1024,abc,100,110,completed,"
+        );
+    }
+}
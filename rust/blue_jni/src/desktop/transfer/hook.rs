@@ -0,0 +1,274 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::{info, warn, Span};
+
+use jni::objects::{JClass, JObjectArray, JString};
+use jni::sys::jlong;
+use jni::JNIEnv;
+use util::{CommandConfig, CommandOutput};
+
+use crate::desktop::config::update_config;
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::guard::jni_entry;
+
+/// Command run after a file has been received and verified.
+/// The placeholders `{path}`, `{sender}`, `{sender_name}` and `{sha256}` in `args` are replaced,
+/// the same values are passed in the `BFT_FILE_PATH`, `BFT_SENDER`, `BFT_SENDER_NAME` and `BFT_SHA256`
+/// environment variables.
+///
+/// The file name and the sender name are chosen by the peer. The command is run directly, so they can
+/// never be interpreted as shell syntax, but a hook must not pass them on through `sh -c` either.
+/// Scripts read them from the environment variables instead.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PostReceiveHook {
+    pub(crate) command: String,
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub(crate) timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+pub(crate) struct ReceivedFile<'a> {
+    pub(crate) path: &'a Path,
+    pub(crate) sender_address: &'a str,
+    pub(crate) sender_name: Option<&'a str>,
+    pub(crate) sha256: &'a str,
+}
+
+impl PostReceiveHook {
+    pub(crate) fn run(&self, file: &ReceivedFile) -> Result<CommandOutput> {
+        let path = file.path.to_string_lossy();
+        let sender_name = file.sender_name.unwrap_or_default();
+        let values = [
+            ("{path}", path.as_ref()),
+            ("{sender}", file.sender_address),
+            ("{sender_name}", sender_name),
+            ("{sha256}", file.sha256),
+        ];
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| substitute(arg, &values))
+            .collect();
+        let command = CommandConfig {
+            command: &self.command,
+            args: args.iter().map(String::as_str).collect(),
+        };
+        let envs = [
+            ("BFT_FILE_PATH", path.as_ref()),
+            ("BFT_SENDER", file.sender_address),
+            ("BFT_SENDER_NAME", sender_name),
+            ("BFT_SHA256", file.sha256),
+        ];
+
+        info!("Running post-receive hook {} {:?}", self.command, args);
+        let output =
+            util::run_command_with_timeout(&command, &envs, Duration::from_secs(self.timeout_secs))
+                .map_err(|err| Error::Generic(format!("Could not run post-receive hook: {err}")))?;
+
+        for line in output.stdout.lines() {
+            info!("[hook] {line}");
+        }
+        for line in output.stderr.lines() {
+            warn!("[hook] {line}");
+        }
+        match output.status {
+            Some(status) if status.success() => info!("Post-receive hook finished"),
+            Some(status) => warn!("Post-receive hook failed with {status}"),
+            None => warn!(
+                "Post-receive hook killed after timeout of {}s",
+                self.timeout_secs
+            ),
+        }
+        Ok(output)
+    }
+}
+
+/// Replaces the placeholders in `arg` in a single pass,
+/// so placeholders within the substituted values are left as they are
+fn substitute(arg: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                result.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Runs `hook` for a received file without blocking the async runtime
+pub(crate) async fn run_post_receive_hook(
+    hook: PostReceiveHook,
+    path: &Path,
+    sender_address: &str,
+    sender_name: Option<&str>,
+    sha256: &str,
+) -> Result<CommandOutput> {
+    let path = path.to_path_buf();
+    let sender_address = sender_address.to_string();
+    let sender_name = sender_name.map(ToString::to_string);
+    let sha256 = sha256.to_string();
+
+    // Logged in the span of the transfer that received the file
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        span.in_scope(|| {
+            hook.run(&ReceivedFile {
+                path: &path,
+                sender_address: &sender_address,
+                sender_name: sender_name.as_deref(),
+                sha256: &sha256,
+            })
+        })
+    })
+    .await
+    .map_err(|err| Error::Generic(format!("Post-receive hook task failed: {err}")))?
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setPostReceiveHook<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    command: JString<'local>,
+    args: JObjectArray<'local>,
+    timeout_secs: jlong,
+) {
+    jni_entry(&mut env, "TransferManager::setPostReceiveHook", (), |env| {
+        info!("TransferManager::setPostReceiveHook()");
+
+        if command.is_null() {
+            update_config(|config| config.post_receive_hook = None)
+                .map_err(on_error)
+                .ok();
+            return;
+        }
+
+        let command: String = env
+            .get_string(&command)
+            .expect("Getting String from env should not fail")
+            .into();
+        let len = env
+            .get_array_length(&args)
+            .expect("Getting array length from env should not fail");
+        let mut hook_args = Vec::with_capacity(len as usize);
+        for index in 0..len {
+            let arg = env
+                .get_object_array_element(&args, index)
+                .expect("Getting array element from env should not fail");
+            let arg: String = env
+                .get_string(&JString::from(arg))
+                .expect("Getting String from env should not fail")
+                .into();
+            hook_args.push(arg);
+        }
+
+        let hook = PostReceiveHook {
+            command,
+            args: hook_args,
+            timeout_secs: if timeout_secs > 0 {
+                timeout_secs as u64
+            } else {
+                default_timeout_secs()
+            },
+        };
+        update_config(|config| config.post_receive_hook = Some(hook))
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received_file<'a>(path: &'a Path, sender_name: &'a str) -> ReceivedFile<'a> {
+        ReceivedFile {
+            path,
+            sender_address: "AA:BB:CC:DD:EE:FF",
+            sender_name: Some(sender_name),
+            sha256: "abc123",
+        }
+    }
+
+    fn hook(command: &str, args: &[&str], timeout_secs: u64) -> PostReceiveHook {
+        PostReceiveHook {
+            command: command.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            timeout_secs,
+        }
+    }
+
+    #[test]
+    fn substitutes_placeholders_once() {
+        let values = [("{path}", "/tmp/{sha256}.txt"), ("{sha256}", "abc123")];
+        assert_eq!(
+            substitute("{path}:{sha256}:{unknown}:{", &values),
+            "/tmp/{sha256}.txt:abc123:{unknown}:{"
+        );
+    }
+
+    #[test]
+    fn passes_values_as_arguments_and_environment() {
+        let path = Path::new("/tmp/$(reboot); {sender}.txt");
+        let output = hook(
+            "sh",
+            &[
+                "-c",
+                r#"printf '%s|%s|%s|%s|%s' "$1" "$BFT_FILE_PATH" "$BFT_SENDER" "$BFT_SENDER_NAME" "$BFT_SHA256""#,
+                "hook",
+                "{path}",
+            ],
+            10,
+        )
+        .run(&received_file(path, "Phone `id`"))
+        .unwrap();
+
+        assert!(output.status.unwrap().success());
+        assert_eq!(
+            output.stdout,
+            "/tmp/$(reboot); {sender}.txt|/tmp/$(reboot); {sender}.txt|AA:BB:CC:DD:EE:FF|Phone `id`|abc123"
+        );
+    }
+
+    #[test]
+    fn captures_the_output() {
+        let output = hook("sh", &["-c", "echo out; echo err >&2; exit 3"], 10)
+            .run(&received_file(Path::new("/tmp/a.txt"), "Phone"))
+            .unwrap();
+
+        assert_eq!(output.status.unwrap().code(), Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    #[test]
+    fn kills_hooks_exceeding_the_timeout() {
+        let started = std::time::Instant::now();
+        let output = hook("sleep", &["30"], 1)
+            .run(&received_file(Path::new("/tmp/a.txt"), "Phone"))
+            .unwrap();
+
+        assert!(output.timed_out());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
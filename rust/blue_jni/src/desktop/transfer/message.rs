@@ -0,0 +1,70 @@
+use std::str::FromStr;
+
+use bluer::Address;
+use tracing::info;
+
+use jni::objects::{JClass, JObject, JString};
+use jni::JNIEnv;
+
+use crate::desktop::completion::complete_with;
+use crate::desktop::error::{Error, Result};
+use crate::desktop::events::{self, Event};
+use crate::desktop::guard::jni_entry;
+
+use super::protocol::{MessageKind, TextMessage};
+use super::rfcomm;
+
+/// Hands a received text message to the UI, which offers to copy or open it.
+/// Only the length is logged, messages may contain one-time codes.
+pub(crate) fn on_text_received(sender_address: &str, message: &TextMessage) {
+    info!(
+        "Received {:?} message of {} bytes from {sender_address}",
+        message.kind,
+        message.text.len()
+    );
+
+    events::emit(Event::TextReceived {
+        sender: sender_address.to_string(),
+        text: message.text.clone(),
+        kind: message.kind,
+    });
+}
+
+fn text_message(kind: &str, text: String) -> Result<TextMessage> {
+    let kind = MessageKind::from_name(kind)
+        .ok_or_else(|| Error::Generic(format!("Unknown message kind: {kind}")))?;
+    TextMessage::new(kind, text).map_err(|err| Error::Generic(err.to_string()))
+}
+
+/// Completes `future` once the message is sent. The text is not logged, see [on_text_received].
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_sendTextAsync<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    target_address: JString<'local>,
+    text: JString<'local>,
+    kind: JString<'local>,
+    future: JObject<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::sendText", (), |env| {
+        let target_address: String = env
+            .get_string(&target_address)
+            .expect("Getting String from env should not fail")
+            .into();
+        let text: String = env
+            .get_string(&text)
+            .expect("Getting String from env should not fail")
+            .into();
+        let kind: String = env
+            .get_string(&kind)
+            .expect("Getting String from env should not fail")
+            .into();
+        info!("TransferManager::sendText({target_address}, {kind})");
+
+        complete_with(env, &future, async move {
+            let address = Address::from_str(&target_address)
+                .map_err(|_| Error::Generic(format!("Invalid device address: {target_address}")))?;
+            rfcomm::send_text(address, text_message(&kind, text)?).await
+        });
+    })
+}
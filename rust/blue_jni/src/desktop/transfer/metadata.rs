@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::{self, File, Permissions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use jni::objects::JClass;
+use jni::sys::jboolean;
+use jni::JNIEnv;
+
+use crate::desktop::config::update_config;
+use crate::desktop::error::{on_error, Result};
+use crate::desktop::guard::jni_entry;
+
+use super::protocol::FileMetadata;
+
+/// setuid, setgid and sticky bit
+const UNSAFE_MODE_BITS: u32 = 0o7000;
+/// Only user attributes are transferred, the other namespaces are security relevant or system specific
+const XATTR_NAMESPACE: &str = "user.";
+
+/// Which of the offered file attributes the receiver applies
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct MetadataPolicy {
+    pub(crate) preserve_mtime: bool,
+    pub(crate) preserve_permissions: bool,
+    pub(crate) preserve_xattrs: bool,
+    /// Keep setuid, setgid and sticky bit. Off by default since a received file must never gain privileges.
+    pub(crate) allow_unsafe_mode_bits: bool,
+}
+
+impl Default for MetadataPolicy {
+    fn default() -> Self {
+        Self {
+            preserve_mtime: true,
+            preserve_permissions: true,
+            preserve_xattrs: false,
+            allow_unsafe_mode_bits: false,
+        }
+    }
+}
+
+impl FileMetadata {
+    /// Collects the metadata of a file which is about to be offered.
+    /// Its user attributes are always included, the receiver decides whether to apply them.
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mtime_nanos = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .and_then(|mtime| i64::try_from(mtime.as_nanos()).ok());
+        let xattrs = read_xattrs(path).unwrap_or_else(|err| {
+            warn!("Could not read extended attributes of {:?}: {err}", path);
+            BTreeMap::new()
+        });
+
+        Ok(Self {
+            mtime_nanos,
+            mode: Some(metadata.mode() & 0o7777),
+            xattrs,
+            mime_type: mime_type(path).map(ToString::to_string),
+        })
+    }
+
+    /// Applies the metadata to a received file according to `policy`
+    pub(crate) fn apply(&self, file: &File, path: &Path, policy: &MetadataPolicy) -> Result<()> {
+        if policy.preserve_xattrs {
+            for (name, value) in &self.xattrs {
+                if !name.starts_with(XATTR_NAMESPACE) {
+                    warn!("Ignoring extended attribute {name} of {:?}", path);
+                    continue;
+                }
+                if let Err(err) = set_xattr(path, name, value) {
+                    warn!(
+                        "Could not set extended attribute {name} of {:?}: {err}",
+                        path
+                    );
+                }
+            }
+        }
+
+        // Setting extended attributes needs write permission, so the mode is applied after them
+        if let (true, Some(mode)) = (policy.preserve_permissions, self.mode) {
+            let mode = if policy.allow_unsafe_mode_bits {
+                mode & 0o7777
+            } else {
+                if mode & UNSAFE_MODE_BITS != 0 {
+                    warn!("Ignoring unsafe mode bits {:o} of {:?}", mode, path);
+                }
+                mode & 0o777
+            };
+            file.set_permissions(Permissions::from_mode(mode))?;
+        }
+
+        // The modification time is applied last, since changing the file would update it again
+        if let (true, Some(mtime_nanos)) = (policy.preserve_mtime, self.mtime_nanos) {
+            let mtime = if mtime_nanos >= 0 {
+                UNIX_EPOCH + Duration::from_nanos(mtime_nanos as u64)
+            } else {
+                UNIX_EPOCH - Duration::from_nanos(mtime_nanos.unsigned_abs())
+            };
+            file.set_modified(mtime)?;
+        }
+        Ok(())
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn read_xattrs(path: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let c_path = c_path(path)?;
+    // SAFETY: A null buffer of size 0 only queries the required size
+    let len = unsafe { libc::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = vec![0u8; len as usize];
+    // SAFETY: `names` is valid for writes of `names.len()` bytes
+    let len = unsafe { libc::listxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(len as usize);
+
+    let mut xattrs = BTreeMap::new();
+    for name in names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let name = String::from_utf8_lossy(name).into_owned();
+        if !name.starts_with(XATTR_NAMESPACE) {
+            continue;
+        }
+        let c_name = CString::new(name.as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        // SAFETY: A null buffer of size 0 only queries the required size
+        let len =
+            unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; len as usize];
+        // SAFETY: `value` is valid for writes of `value.len()` bytes
+        let len = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(len as usize);
+        xattrs.insert(name, value);
+    }
+    Ok(xattrs)
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let c_path = c_path(path)?;
+    let c_name =
+        CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // SAFETY: All pointers are valid for the given lengths
+    let result = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Guesses the MIME type of common file types from the file extension
+pub(crate) fn mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "html" | "htm" => "text/html",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "apk" => "application/vnd.android.package-archive",
+        _ => return None,
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_configureMetadata<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    preserve_mtime: jboolean,
+    preserve_permissions: jboolean,
+    preserve_xattrs: jboolean,
+    allow_unsafe_mode_bits: jboolean,
+) {
+    jni_entry(&mut env, "TransferManager::configureMetadata", (), |_env| {
+        let policy = MetadataPolicy {
+            preserve_mtime: preserve_mtime != 0,
+            preserve_permissions: preserve_permissions != 0,
+            preserve_xattrs: preserve_xattrs != 0,
+            allow_unsafe_mode_bits: allow_unsafe_mode_bits != 0,
+        };
+        info!("TransferManager::configureMetadata({:?})", policy);
+
+        update_config(|config| config.metadata = policy)
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_xattrs_before_read_only_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let file = File::create(&path).unwrap();
+        if set_xattr(&path, "user.probe", b"1").is_err() {
+            // The file system of the temp dir does not support user attributes
+            return;
+        }
+        let metadata = FileMetadata {
+            mtime_nanos: Some(1_600_000_000_000_000_000),
+            mode: Some(0o444),
+            xattrs: BTreeMap::from([
+                ("user.origin".to_string(), b"lab".to_vec()),
+                ("trusted.secret".to_string(), b"x".to_vec()),
+            ]),
+            mime_type: None,
+        };
+        let policy = MetadataPolicy {
+            preserve_xattrs: true,
+            ..Default::default()
+        };
+
+        metadata.apply(&file, &path, &policy).unwrap();
+
+        let applied = fs::metadata(&path).unwrap();
+        assert_eq!(applied.mode() & 0o7777, 0o444);
+        assert_eq!(
+            applied.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        let xattrs = read_xattrs(&path).unwrap();
+        assert_eq!(
+            xattrs.get("user.origin").map(Vec::as_slice),
+            Some(&b"lab"[..])
+        );
+        assert!(!xattrs.contains_key("trusted.secret"));
+    }
+
+    #[test]
+    fn offers_user_xattrs_whatever_the_receive_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let _file = File::create(&path).unwrap();
+        if set_xattr(&path, "user.origin", b"lab").is_err() {
+            // The file system of the temp dir does not support user attributes
+            return;
+        }
+
+        let metadata = FileMetadata::read(&path).unwrap();
+        assert_eq!(
+            metadata.xattrs.get("user.origin").map(Vec::as_slice),
+            Some(&b"lab"[..])
+        );
+
+        // The receiver leaves them out unless it preserves them
+        let copy = dir.path().join("copy.txt");
+        let copy_file = File::create(&copy).unwrap();
+        metadata
+            .apply(&copy_file, &copy, &MetadataPolicy::default())
+            .unwrap();
+        assert!(read_xattrs(&copy).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{info, trace, warn};
+
+use jni::objects::JClass;
+use jni::sys::{jboolean, jint};
+use jni::JNIEnv;
+
+use crate::desktop::config::{app_config, update_config};
+use crate::desktop::dirs;
+use crate::desktop::error::on_error;
+use crate::desktop::events::{self, Event};
+use crate::desktop::guard::jni_entry;
+
+use capture::{Capture, CaptureConfig};
+
+pub(crate) mod cancel;
+// The reading half is only used by the replay tool, which includes the file as well
+#[allow(dead_code)]
+pub(crate) mod capture;
+pub(crate) mod history;
+pub(crate) mod hook;
+pub(crate) mod message;
+pub(crate) mod metadata;
+pub(crate) mod placement;
+pub(crate) mod protocol;
+pub(crate) mod receiver;
+pub(crate) mod rfcomm;
+pub(crate) mod rules;
+pub(crate) mod scanner;
+pub(crate) mod session;
+pub(crate) mod sync;
+pub(crate) mod sync_round;
+pub(crate) mod throttle;
+pub(crate) mod watch;
+
+pub(crate) type TransferId = u64;
+
+/// Reports the bytes sent or received so far to the UI
+pub(crate) fn report_progress(transfer_id: TransferId, transferred: u64, total: u64) {
+    trace!(transfer_id, transferred, total, "Transfer progress");
+    events::emit(Event::TransferProgress {
+        transfer_id,
+        transferred,
+        total,
+    });
+}
+
+/// Directory of the frame captures (e.g. ~/.local/share/bft/captures)
+pub(crate) fn capture_dir() -> PathBuf {
+    dirs::data_dir().join("captures")
+}
+
+/// Starts capturing the frames of a transfer if capturing is enabled
+pub(crate) fn start_capture(transfer_id: TransferId) -> Option<Capture<File>> {
+    let config = app_config().lock().unwrap().capture.clone();
+    config
+        .open(&capture_dir(), transfer_id)
+        .map_err(|err| warn!(transfer_id, "Could not create capture file: {err}"))
+        .ok()
+        .flatten()
+}
+
+/// Milliseconds since the Unix epoch, used for all transfer timestamps
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Lowercase hex representation of a hash
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setFrameCapture<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    enabled: jboolean,
+    max_payload_len: jint,
+) {
+    jni_entry(&mut env, "TransferManager::setFrameCapture", (), |_env| {
+        let capture = CaptureConfig {
+            enabled: enabled != 0,
+            max_payload_len: usize::try_from(max_payload_len).unwrap_or_default(),
+        };
+        info!("TransferManager::setFrameCapture({:?})", capture);
+
+        update_config(|config| config.capture = capture)
+            .map_err(on_error)
+            .ok();
+    })
+}
@@ -0,0 +1,660 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File, Permissions};
+use std::io::{ErrorKind, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::sys::{jboolean, jlong};
+use jni::JNIEnv;
+
+use crate::desktop::config::update_config;
+use crate::desktop::dirs;
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::guard::jni_entry;
+
+use super::metadata::MetadataPolicy;
+use super::protocol::FileOffer;
+use super::scanner::{self, Scanner};
+use super::{to_hex, TransferId};
+
+/// Received files are written here first and only moved to their destination once verified
+static INCOMING_DIR_NAME: &str = ".incoming";
+/// Files flagged by the content scanner are kept here
+static QUARANTINE_DIR_NAME: &str = ".quarantine";
+/// Quarantined files can only be read by the owner
+const QUARANTINE_MODE: u32 = 0o400;
+
+lazy_static! {
+    /// Offered sizes of the files being received, by download root. They count against the quota
+    /// until the files are stored, so concurrent offers cannot overshoot it together.
+    static ref RESERVED: Mutex<HashMap<PathBuf, u64>> = Mutex::new(HashMap::new());
+}
+
+/// Bytes reserved under a download root for as long as it lives
+#[derive(Debug)]
+struct Reservation {
+    download_root: PathBuf,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut reserved = RESERVED.lock().unwrap();
+        if let Some(bytes) = reserved.get_mut(&self.download_root) {
+            *bytes = bytes.saturating_sub(self.bytes);
+            if *bytes == 0 {
+                reserved.remove(&self.download_root);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConflictMode {
+    /// The received file is stored as `name (1).ext`
+    #[default]
+    RenameWithSuffix,
+    /// The existing file is replaced
+    Overwrite,
+    /// The offer is rejected and the existing file is kept
+    Skip,
+    /// The existing file is moved to `name (1).ext` and the received file takes its name
+    KeepBoth,
+}
+
+/// Decides where received files go and whether they are accepted at all
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PlacementPolicy {
+    pub(crate) download_root: PathBuf,
+    pub(crate) per_sender_subfolders: bool,
+    pub(crate) conflict_mode: ConflictMode,
+    /// Largest accepted file in bytes
+    pub(crate) max_file_size: Option<u64>,
+    /// Maximum number of bytes all files in the download root may occupy
+    pub(crate) quota: Option<u64>,
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> Self {
+        Self {
+            download_root: dirs::download_dir(),
+            per_sender_subfolders: false,
+            conflict_mode: ConflictMode::default(),
+            max_file_size: None,
+            quota: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Placement {
+    Accept(PathBuf),
+    Skip(PathBuf),
+}
+
+impl PlacementPolicy {
+    /// Places an offered file and creates the temporary file its data goes to, `None` if it is skipped.
+    /// Its size is reserved against the quota until the file is dropped.
+    pub(crate) fn prepare(
+        &self,
+        id: TransferId,
+        sender: &str,
+        file_name: &str,
+        size: u64,
+    ) -> Result<Option<IncomingFile>> {
+        // Checking and reserving under one lock, offers placed in the meantime would not be counted
+        let mut reserved = RESERVED.lock().unwrap();
+        let in_flight = reserved.get(&self.download_root).copied().unwrap_or(0);
+        let target = match self.place(sender, file_name, size, in_flight)? {
+            Placement::Accept(target) => target,
+            Placement::Skip(_) => return Ok(None),
+        };
+        let mut file = self.incoming_file(id, target)?;
+        *reserved.entry(self.download_root.clone()).or_default() += size;
+        file.reservation = Some(Reservation {
+            download_root: self.download_root.clone(),
+            bytes: size,
+        });
+        Ok(Some(file))
+    }
+
+    /// Checks an incoming file offer against size limits, quota and free disk space
+    /// and determines the destination of the file. `in_flight` bytes of other files
+    /// being received count against the quota.
+    fn place(&self, sender: &str, file_name: &str, size: u64, in_flight: u64) -> Result<Placement> {
+        let file_name = sanitize_file_name(file_name)?;
+        if let Some(max) = self.max_file_size {
+            if size > max {
+                return Err(Error::FileTooLarge { size, max });
+            }
+        }
+
+        let mut target_dir = self.download_root.clone();
+        if self.per_sender_subfolders {
+            target_dir.push(sanitize_file_name(sender)?);
+        }
+        let target = target_dir.join(file_name);
+        if target.exists() && self.conflict_mode == ConflictMode::Skip {
+            info!("Skipping {:?}, file already exists", target);
+            return Ok(Placement::Skip(target));
+        }
+
+        if let Some(quota) = self.quota {
+            let mut used = dir_size(
+                &self.download_root,
+                &[INCOMING_DIR_NAME, QUARANTINE_DIR_NAME],
+            )? + in_flight;
+            if self.conflict_mode == ConflictMode::Overwrite {
+                // The replaced file no longer counts once the received file takes its place
+                used =
+                    used.saturating_sub(fs::metadata(&target).map_or(0, |metadata| metadata.len()));
+            }
+            let available = quota.saturating_sub(used);
+            if size > available {
+                return Err(Error::InsufficientSpace {
+                    required: size,
+                    available,
+                });
+            }
+        }
+        let available = free_space(&self.download_root)?;
+        if size > available {
+            return Err(Error::InsufficientSpace {
+                required: size,
+                available,
+            });
+        }
+
+        Ok(Placement::Accept(target))
+    }
+
+    /// Creates the temporary file the received data is written to
+    pub(crate) fn incoming_file(&self, id: TransferId, target: PathBuf) -> Result<IncomingFile> {
+        let incoming_dir = self.download_root.join(INCOMING_DIR_NAME);
+        fs::create_dir_all(&incoming_dir)?;
+        let file_name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let tmp_path = incoming_dir.join(format!("{id}-{file_name}.part"));
+        let file = File::create(&tmp_path)?;
+        Ok(IncomingFile {
+            file: Some(file),
+            tmp_path,
+            target,
+            quarantine_dir: self.download_root.join(QUARANTINE_DIR_NAME),
+            conflict_mode: self.conflict_mode,
+            hasher: Sha256::new(),
+            written: 0,
+            reservation: None,
+        })
+    }
+}
+
+/// A file being received. Data goes to a temporary file which is atomically renamed to the
+/// destination on [IncomingFile::commit]. Dropping an uncommitted file deletes the temporary file.
+#[derive(Debug)]
+pub(crate) struct IncomingFile {
+    file: Option<File>,
+    tmp_path: PathBuf,
+    target: PathBuf,
+    quarantine_dir: PathBuf,
+    conflict_mode: ConflictMode,
+    hasher: Sha256,
+    written: u64,
+    /// Released once the file is stored or discarded
+    reservation: Option<Reservation>,
+}
+
+impl IncomingFile {
+    pub(crate) fn write(&mut self, data: &[u8]) -> Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| Error::Generic("Incoming file already closed".to_string()))?;
+        file.write_all(data)?;
+        self.hasher.update(data);
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Verifies size and hash of the received data, scans the file, applies the offered metadata
+    /// and moves it to its destination. Files rejected by the scanner are moved to quarantine.
+    /// Returns `None` if the file was skipped because the destination exists in the meantime.
+    pub(crate) fn commit(
+        mut self,
+        offer: &FileOffer,
+        metadata_policy: &MetadataPolicy,
+        scanner: Option<&dyn Scanner>,
+    ) -> Result<Option<PathBuf>> {
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| Error::Generic("Incoming file already closed".to_string()))?;
+
+        let hash = to_hex(&self.hasher.clone().finalize());
+        if self.written != offer.size || !hash.eq_ignore_ascii_case(&offer.sha256) {
+            return Err(Error::VerificationFailed(format!(
+                "{:?}: expected {} bytes with sha256 {}, received {} bytes with sha256 {hash}",
+                self.target, offer.size, offer.sha256, self.written
+            )));
+        }
+
+        // The offered mode must not make a flagged file executable
+        if let Some(scanner) = scanner {
+            if let Err(err) = scanner::check(scanner, &self.tmp_path, &offer.name) {
+                file.set_permissions(Permissions::from_mode(QUARANTINE_MODE))?;
+                drop(file);
+                self.quarantine()?;
+                return Err(err);
+            }
+        }
+
+        offer
+            .metadata
+            .apply(&file, &self.tmp_path, metadata_policy)?;
+        file.sync_all()?;
+        drop(file);
+
+        if let Some(parent) = self.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let destination = match self.conflict_mode {
+            _ if !self.target.exists() => self.target.clone(),
+            ConflictMode::Overwrite => self.target.clone(),
+            ConflictMode::RenameWithSuffix => free_path(&self.target),
+            ConflictMode::KeepBoth => {
+                let existing = free_path(&self.target);
+                fs::rename(&self.target, &existing)?;
+                info!("Moved existing {:?} to {:?}", self.target, existing);
+                self.target.clone()
+            }
+            ConflictMode::Skip => {
+                info!("Skipping {:?}, file already exists", self.target);
+                return Ok(None);
+            }
+        };
+
+        fs::rename(&self.tmp_path, &destination)?;
+        info!("Received file stored at {:?}", destination);
+        Ok(Some(destination))
+    }
+}
+
+impl IncomingFile {
+    fn quarantine(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.quarantine_dir)?;
+        let file_name = self
+            .tmp_path
+            .file_name()
+            .map(|name| name.to_string_lossy().trim_end_matches(".part").to_string())
+            .unwrap_or_default();
+        let mut quarantined = self.quarantine_dir.join(file_name);
+        // A connection may send several files of the same name
+        if quarantined.exists() {
+            quarantined = free_path(&quarantined);
+        }
+        fs::rename(&self.tmp_path, &quarantined)?;
+        warn!("Moved {:?} to quarantine at {:?}", self.target, quarantined);
+        Ok(())
+    }
+}
+
+impl Drop for IncomingFile {
+    fn drop(&mut self) {
+        self.file = None;
+        match fs::remove_file(&self.tmp_path) {
+            Ok(()) => info!("Discarded incomplete file {:?}", self.tmp_path),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => warn!("Could not remove {:?}: {err}", self.tmp_path),
+        }
+    }
+}
+
+/// Strips everything from a peer supplied name that could escape the target directory
+pub(crate) fn sanitize_file_name(name: &str) -> Result<String> {
+    let name: String = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Error::Generic(format!("Invalid file name: {name:?}")));
+    }
+    Ok(name.to_string())
+}
+
+/// First `name (n).ext` next to `path` that does not exist yet
+pub(crate) fn free_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){extension}")))
+        .find(|candidate| !candidate.exists())
+        .expect("There should always be a free file name")
+}
+
+/// Size of all files below `path`, except for the directories named in `skip` directly in `path`
+fn dir_size(path: &Path, skip: &[&str]) -> Result<u64> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if skip.iter().any(|name| entry.file_name() == *name) {
+                continue;
+            }
+            size += dir_size(&entry.path(), &[])?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Free bytes on the file system of `path`, or of its closest existing ancestor
+fn free_space(path: &Path) -> Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("/"));
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|err| Error::Generic(err.to_string()))?;
+
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid NUL terminated string and `stat` is only read if statvfs succeeds
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        stat.assume_init()
+    };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_configureReceiving<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    download_root: JString<'local>,
+    per_sender_subfolders: jboolean,
+    conflict_mode: JString<'local>,
+    max_file_size: jlong,
+    quota: jlong,
+) {
+    jni_entry(&mut env, "TransferManager::configureReceiving", (), |env| {
+        let download_root: String = env
+            .get_string(&download_root)
+            .expect("Getting String from env should not fail")
+            .into();
+        let conflict_mode: String = env
+            .get_string(&conflict_mode)
+            .expect("Getting String from env should not fail")
+            .into();
+        info!("TransferManager::configureReceiving({download_root}, {conflict_mode})");
+
+        let conflict_mode = match conflict_mode.as_str() {
+            "RenameWithSuffix" => ConflictMode::RenameWithSuffix,
+            "Overwrite" => ConflictMode::Overwrite,
+            "Skip" => ConflictMode::Skip,
+            "KeepBoth" => ConflictMode::KeepBoth,
+            _ => {
+                on_error(Error::Generic(format!(
+                    "Unknown conflict mode: {conflict_mode}"
+                )));
+                return;
+            }
+        };
+        let policy = PlacementPolicy {
+            download_root: PathBuf::from(download_root),
+            per_sender_subfolders: per_sender_subfolders != 0,
+            conflict_mode,
+            max_file_size: (max_file_size > 0).then_some(max_file_size as u64),
+            quota: (quota > 0).then_some(quota as u64),
+        };
+        update_config(|config| config.receive = policy)
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(root: &Path, conflict_mode: ConflictMode) -> PlacementPolicy {
+        PlacementPolicy {
+            download_root: root.to_path_buf(),
+            per_sender_subfolders: true,
+            conflict_mode,
+            max_file_size: Some(1024),
+            quota: None,
+        }
+    }
+
+    fn offer(name: &str, data: &[u8]) -> FileOffer {
+        FileOffer {
+            name: name.to_string(),
+            size: data.len() as u64,
+            sha256: to_hex(&Sha256::digest(data)),
+            metadata: Default::default(),
+        }
+    }
+
+    fn receive(policy: &PlacementPolicy, name: &str, data: &[u8]) -> Option<PathBuf> {
+        let target = match policy.place("Phone", name, data.len() as u64, 0).unwrap() {
+            Placement::Accept(target) => target,
+            Placement::Skip(_) => return None,
+        };
+        let mut file = policy.incoming_file(1, target).unwrap();
+        file.write(data).unwrap();
+        file.commit(&offer(name, data), &MetadataPolicy::default(), None)
+            .unwrap()
+    }
+
+    #[test]
+    fn rejects_unsafe_names_and_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = policy(dir.path(), ConflictMode::RenameWithSuffix);
+
+        assert_eq!(
+            policy.place("Phone", "../../etc/passwd", 10, 0).unwrap(),
+            Placement::Accept(dir.path().join("Phone").join("passwd"))
+        );
+        assert!(policy.place("Phone", "..", 10, 0).is_err());
+        assert!(matches!(
+            policy.place("Phone", "big.iso", 2048, 0),
+            Err(Error::FileTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let sender_dir = dir.path().join("Phone");
+
+        let rename = policy(dir.path(), ConflictMode::RenameWithSuffix);
+        assert_eq!(
+            receive(&rename, "a.txt", b"1"),
+            Some(sender_dir.join("a.txt"))
+        );
+        assert_eq!(
+            receive(&rename, "a.txt", b"2"),
+            Some(sender_dir.join("a (1).txt"))
+        );
+
+        let keep_both = policy(dir.path(), ConflictMode::KeepBoth);
+        assert_eq!(
+            receive(&keep_both, "a.txt", b"3"),
+            Some(sender_dir.join("a.txt"))
+        );
+        assert_eq!(fs::read(sender_dir.join("a (2).txt")).unwrap(), b"1");
+
+        let overwrite = policy(dir.path(), ConflictMode::Overwrite);
+        receive(&overwrite, "a.txt", b"4");
+        assert_eq!(fs::read(sender_dir.join("a.txt")).unwrap(), b"4");
+
+        let skip = policy(dir.path(), ConflictMode::Skip);
+        assert_eq!(receive(&skip, "a.txt", b"5"), None);
+        assert_eq!(fs::read(sender_dir.join("a.txt")).unwrap(), b"4");
+    }
+
+    #[test]
+    fn quota_ignores_partial_files_and_replaced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut policy = policy(dir.path(), ConflictMode::Overwrite);
+        policy.per_sender_subfolders = false;
+        policy.quota = Some(100);
+        fs::write(dir.path().join("a.bin"), [0; 60]).unwrap();
+        let _incoming = policy.incoming_file(1, dir.path().join("b.bin")).unwrap();
+        fs::create_dir_all(dir.path().join(QUARANTINE_DIR_NAME)).unwrap();
+        fs::write(dir.path().join(QUARANTINE_DIR_NAME).join("c.bin"), [0; 90]).unwrap();
+
+        assert_eq!(
+            policy.place("Phone", "b.bin", 40, 0).unwrap(),
+            Placement::Accept(dir.path().join("b.bin"))
+        );
+        assert!(matches!(
+            policy.place("Phone", "b.bin", 41, 0),
+            Err(Error::InsufficientSpace { available: 40, .. })
+        ));
+        // Overwriting a.bin frees its 60 bytes
+        assert!(policy.place("Phone", "a.bin", 100, 0).is_ok());
+
+        policy.conflict_mode = ConflictMode::RenameWithSuffix;
+        assert!(policy.place("Phone", "a.bin", 100, 0).is_err());
+    }
+
+    #[test]
+    fn quota_counts_files_being_received() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut policy = policy(dir.path(), ConflictMode::RenameWithSuffix);
+        policy.quota = Some(100);
+
+        let first = policy.prepare(1, "Phone", "a.bin", 60).unwrap();
+        assert!(first.is_some());
+        assert!(matches!(
+            policy.prepare(2, "Laptop", "b.bin", 60),
+            Err(Error::InsufficientSpace { available: 40, .. })
+        ));
+        drop(first);
+        assert!(policy.prepare(2, "Laptop", "b.bin", 60).unwrap().is_some());
+    }
+
+    #[test]
+    fn corrupt_data_is_not_committed() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = policy(dir.path(), ConflictMode::RenameWithSuffix);
+        let target = dir.path().join("b.txt");
+
+        let mut file = policy.incoming_file(2, target.clone()).unwrap();
+        file.write(b"data").unwrap();
+        let tmp_path = file.tmp_path.clone();
+        assert!(matches!(
+            file.commit(&offer("b.txt", b"atad"), &MetadataPolicy::default(), None),
+            Err(Error::VerificationFailed(_))
+        ));
+        assert!(!target.exists());
+        assert!(!tmp_path.exists());
+    }
+
+    #[test]
+    fn applies_offered_metadata() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let dir = tempfile::tempdir().unwrap();
+        let policy = policy(dir.path(), ConflictMode::RenameWithSuffix);
+        let mut offer = offer("run.sh", b"echo");
+        offer.metadata.mode = Some(0o4755);
+        offer.metadata.mtime_nanos = Some(1_600_000_000_000_000_000);
+
+        let mut file = policy.incoming_file(3, dir.path().join("run.sh")).unwrap();
+        file.write(b"echo").unwrap();
+        let path = file
+            .commit(&offer, &MetadataPolicy::default(), None)
+            .unwrap()
+            .unwrap();
+
+        let metadata = fs::metadata(path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+    }
+
+    #[test]
+    fn flagged_files_are_quarantined_without_offered_mode() {
+        use super::super::scanner::ScanVerdict;
+
+        struct FlagEverything;
+        impl Scanner for FlagEverything {
+            fn name(&self) -> &str {
+                "test"
+            }
+            fn scan(&self, _path: &Path) -> Result<ScanVerdict> {
+                Ok(ScanVerdict::Infected("Test-Signature".to_string()))
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let policy = policy(dir.path(), ConflictMode::RenameWithSuffix);
+        let mut offer = offer("run.sh", b"echo");
+        offer.metadata.mode = Some(0o755);
+
+        let mut file = policy.incoming_file(4, dir.path().join("run.sh")).unwrap();
+        file.write(b"echo").unwrap();
+        assert!(matches!(
+            file.commit(&offer, &MetadataPolicy::default(), Some(&FlagEverything)),
+            Err(Error::FileQuarantined { .. })
+        ));
+
+        let quarantined = dir.path().join(QUARANTINE_DIR_NAME).join("4-run.sh");
+        let mode = fs::metadata(&quarantined).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, QUARANTINE_MODE);
+        assert!(!dir.path().join("run.sh").exists());
+
+        // The next file of the same name in the connection does not replace it
+        let mut file = policy.incoming_file(4, dir.path().join("run.sh")).unwrap();
+        file.write(b"echo").unwrap();
+        assert!(file
+            .commit(&offer, &MetadataPolicy::default(), Some(&FlagEverything))
+            .is_err());
+        assert!(quarantined.exists());
+        assert!(dir
+            .path()
+            .join(QUARANTINE_DIR_NAME)
+            .join("4-run (1).sh")
+            .exists());
+    }
+}
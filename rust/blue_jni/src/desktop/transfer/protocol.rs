@@ -0,0 +1,469 @@
+//! Wire format of the file transfer protocol.
+//!
+//! Every frame is encoded as `[type: u8][length: u32 big endian][payload]`.
+//! Control frames carry a JSON payload, data frames carry raw file bytes.
+//! This module has no dependencies on the rest of the crate, so tools in `src/bin` can include it.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+pub(crate) const HEADER_LEN: usize = 5;
+pub(crate) const MAX_PAYLOAD_LEN: usize = 1 << 20;
+/// Text messages are meant for URLs, codes and snippets, larger texts have to be sent as file
+pub(crate) const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub(crate) enum FrameType {
+    Offer = 1,
+    Accept = 2,
+    Reject = 3,
+    Data = 4,
+    Done = 5,
+    Cancel = 6,
+    Message = 7,
+    SyncIndex = 8,
+    Fetch = 9,
+    Stored = 10,
+}
+
+impl FrameType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Offer),
+            2 => Some(Self::Accept),
+            3 => Some(Self::Reject),
+            4 => Some(Self::Data),
+            5 => Some(Self::Done),
+            6 => Some(Self::Cancel),
+            7 => Some(Self::Message),
+            8 => Some(Self::SyncIndex),
+            9 => Some(Self::Fetch),
+            10 => Some(Self::Stored),
+            _ => None,
+        }
+    }
+}
+
+/// File attributes carried in the offer, applied by the receiver according to its policy
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct FileMetadata {
+    /// Modification time in nanoseconds since the Unix epoch
+    pub(crate) mtime_nanos: Option<i64>,
+    /// Unix permission bits including setuid, setgid and sticky bit
+    pub(crate) mode: Option<u32>,
+    pub(crate) xattrs: BTreeMap<String, Vec<u8>>,
+    pub(crate) mime_type: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileOffer {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) sha256: String,
+    #[serde(default)]
+    pub(crate) metadata: FileMetadata,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MessageKind {
+    Text,
+    Url,
+    Clipboard,
+}
+
+impl MessageKind {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Text => "Text",
+            Self::Url => "Url",
+            Self::Clipboard => "Clipboard",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [Self::Text, Self::Url, Self::Clipboard]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+/// Small text payload which is handed to the UI instead of being written to disk
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TextMessage {
+    pub(crate) kind: MessageKind,
+    pub(crate) text: String,
+}
+
+impl TextMessage {
+    pub(crate) fn new(kind: MessageKind, text: String) -> Result<Self, ProtocolError> {
+        if text.len() > MAX_MESSAGE_LEN {
+            return Err(ProtocolError::MessageTooLarge(text.len()));
+        }
+        Ok(Self { kind, text })
+    }
+}
+
+/// State of a single file. Deleted files are kept as tombstones, so deletions reach the peer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    pub(crate) size: u64,
+    /// Modification time, for tombstones the time the deletion was noticed
+    pub(crate) mtime_nanos: i64,
+    pub(crate) sha256: String,
+    #[serde(default)]
+    pub(crate) deleted: bool,
+}
+
+/// Index of a sync folder, keyed by the `/` separated path relative to the folder
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FolderIndex {
+    pub(crate) entries: BTreeMap<String, IndexEntry>,
+}
+
+/// Index of a sync folder, sent by both devices at the start and at the end of a sync round.
+/// Indexes too large for a single frame are sent as several parts, see [SyncIndex::parts].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SyncIndex {
+    /// Name of the folder, devices sync the folders of the same name
+    pub(crate) folder: String,
+    pub(crate) index: FolderIndex,
+    /// Further parts of the index follow
+    #[serde(default)]
+    pub(crate) more: bool,
+}
+
+impl SyncIndex {
+    /// Splits `index` into parts whose frames stay within [MAX_PAYLOAD_LEN].
+    /// All parts but the last have `more` set, an empty index is a single part.
+    pub(crate) fn parts(folder: &str, index: &FolderIndex) -> Vec<Self> {
+        let empty = Self {
+            folder: folder.to_string(),
+            index: FolderIndex::default(),
+            more: false,
+        };
+        let overhead = json_len(&empty);
+
+        let mut parts = Vec::new();
+        let mut part = empty.clone();
+        let mut len = overhead;
+        for (path, entry) in &index.entries {
+            // `"path":{...},`
+            let entry_len = json_len(path) + json_len(entry) + 2;
+            if len + entry_len > MAX_PAYLOAD_LEN && !part.index.entries.is_empty() {
+                let mut full = std::mem::replace(&mut part, empty.clone());
+                full.more = true;
+                parts.push(full);
+                len = overhead;
+            }
+            part.index.entries.insert(path.clone(), entry.clone());
+            len += entry_len;
+        }
+        parts.push(part);
+        parts
+    }
+}
+
+/// Length of the JSON encoding of an index or a part of it
+fn json_len(value: &impl Serialize) -> usize {
+    serde_json::to_vec(value)
+        .expect("Serializing an index should not fail")
+        .len()
+}
+
+/// Asks the peer for the content of a file during a sync round.
+/// The hash identifies the content, in case the peer renamed the file in the meantime.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FetchRequest {
+    pub(crate) path: String,
+    pub(crate) sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Frame {
+    Offer(FileOffer),
+    Accept,
+    Reject(String),
+    Data(Vec<u8>),
+    /// All data of the offered file has been sent. The receiver answers with [Frame::Stored],
+    /// or with [Frame::Reject] if verifying, scanning or storing the file failed.
+    Done,
+    Stored,
+    Cancel,
+    Message(TextMessage),
+    SyncIndex(SyncIndex),
+    /// Answered with the data frames of the file and [Frame::Done], or [Frame::Reject]
+    Fetch(FetchRequest),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ProtocolError {
+    UnknownFrameType(u8),
+    FrameTooLarge(usize),
+    MessageTooLarge(usize),
+    MalformedPayload(String),
+    UnexpectedFrame {
+        state: &'static str,
+        frame: FrameType,
+    },
+    SizeMismatch {
+        expected: u64,
+        received: u64,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFrameType(value) => write!(f, "Unknown frame type {value}"),
+            Self::FrameTooLarge(len) => write!(f, "Frame payload of {len} bytes is too large"),
+            Self::MessageTooLarge(len) => write!(f, "Text message of {len} bytes is too large"),
+            Self::MalformedPayload(msg) => write!(f, "Malformed frame payload: {msg}"),
+            Self::UnexpectedFrame { state, frame } => {
+                write!(f, "Unexpected {frame:?} frame in state {state}")
+            }
+            Self::SizeMismatch { expected, received } => {
+                write!(f, "Expected {expected} bytes but received {received}")
+            }
+        }
+    }
+}
+
+impl Frame {
+    pub(crate) fn frame_type(&self) -> FrameType {
+        match self {
+            Self::Offer(_) => FrameType::Offer,
+            Self::Accept => FrameType::Accept,
+            Self::Reject(_) => FrameType::Reject,
+            Self::Data(_) => FrameType::Data,
+            Self::Done => FrameType::Done,
+            Self::Cancel => FrameType::Cancel,
+            Self::Message(_) => FrameType::Message,
+            Self::SyncIndex(_) => FrameType::SyncIndex,
+            Self::Fetch(_) => FrameType::Fetch,
+            Self::Stored => FrameType::Stored,
+        }
+    }
+
+    pub(crate) fn payload(&self) -> Vec<u8> {
+        match self {
+            Self::Offer(offer) => {
+                serde_json::to_vec(offer).expect("Serializing an offer should not fail")
+            }
+            Self::Reject(reason) => reason.as_bytes().to_vec(),
+            Self::Data(data) => data.clone(),
+            Self::Message(message) => {
+                serde_json::to_vec(message).expect("Serializing a message should not fail")
+            }
+            Self::SyncIndex(index) => {
+                serde_json::to_vec(index).expect("Serializing an index should not fail")
+            }
+            Self::Fetch(request) => {
+                serde_json::to_vec(request).expect("Serializing a request should not fail")
+            }
+            Self::Accept | Self::Done | Self::Cancel | Self::Stored => Vec::new(),
+        }
+    }
+
+    /// Encodes the frame, payloads larger than [MAX_PAYLOAD_LEN] are rejected like [Frame::decode] does
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let payload = self.payload();
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::FrameTooLarge(payload.len()));
+        }
+        let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+        buf.push(self.frame_type() as u8);
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+
+    pub(crate) fn from_payload(
+        frame_type: FrameType,
+        payload: &[u8],
+    ) -> Result<Self, ProtocolError> {
+        let malformed = |err: &dyn fmt::Display| ProtocolError::MalformedPayload(err.to_string());
+        Ok(match frame_type {
+            FrameType::Offer => {
+                Self::Offer(serde_json::from_slice(payload).map_err(|err| malformed(&err))?)
+            }
+            FrameType::Accept => Self::Accept,
+            FrameType::Reject => {
+                Self::Reject(String::from_utf8(payload.to_vec()).map_err(|err| malformed(&err))?)
+            }
+            FrameType::Data => Self::Data(payload.to_vec()),
+            FrameType::Done => Self::Done,
+            FrameType::Stored => Self::Stored,
+            FrameType::Cancel => Self::Cancel,
+            FrameType::Message => {
+                let message: TextMessage =
+                    serde_json::from_slice(payload).map_err(|err| malformed(&err))?;
+                Self::Message(TextMessage::new(message.kind, message.text)?)
+            }
+            FrameType::SyncIndex => {
+                Self::SyncIndex(serde_json::from_slice(payload).map_err(|err| malformed(&err))?)
+            }
+            FrameType::Fetch => {
+                Self::Fetch(serde_json::from_slice(payload).map_err(|err| malformed(&err))?)
+            }
+        })
+    }
+
+    /// Decodes the first frame in `buf`.
+    /// Returns the frame and the number of consumed bytes, or `None` if `buf` holds no complete frame yet.
+    pub(crate) fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, ProtocolError> {
+        if buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let frame_type =
+            FrameType::from_u8(buf[0]).ok_or(ProtocolError::UnknownFrameType(buf[0]))?;
+        let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        if len > MAX_PAYLOAD_LEN {
+            return Err(ProtocolError::FrameTooLarge(len));
+        }
+        if buf.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let frame = Self::from_payload(frame_type, &buf[HEADER_LEN..HEADER_LEN + len])?;
+        Ok(Some((frame, HEADER_LEN + len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_roundtrip() {
+        let offer = Frame::Offer(FileOffer {
+            name: "log.txt".to_string(),
+            size: 3,
+            sha256: "abc".to_string(),
+            metadata: FileMetadata {
+                mtime_nanos: Some(1_700_000_000_123_456_789),
+                mode: Some(0o644),
+                xattrs: BTreeMap::from([("user.origin".to_string(), b"lab".to_vec())]),
+                mime_type: Some("text/plain".to_string()),
+            },
+        });
+        let message = Frame::Message(TextMessage {
+            kind: MessageKind::Url,
+            text: "https://example.com".to_string(),
+        });
+        let index = Frame::SyncIndex(SyncIndex {
+            folder: "Shared".to_string(),
+            index: FolderIndex {
+                entries: BTreeMap::from([(
+                    "docs/a.txt".to_string(),
+                    IndexEntry {
+                        size: 1,
+                        mtime_nanos: 1_700_000_000_000_000_000,
+                        sha256: "def".to_string(),
+                        deleted: false,
+                    },
+                )]),
+            },
+            more: false,
+        });
+        let fetch = Frame::Fetch(FetchRequest {
+            path: "docs/a.txt".to_string(),
+            sha256: "def".to_string(),
+        });
+        let frames = [
+            offer,
+            Frame::Data(vec![1, 2, 3]),
+            Frame::Done,
+            Frame::Stored,
+            message,
+            index,
+            fetch,
+        ];
+
+        let mut buf: Vec<u8> = frames
+            .iter()
+            .flat_map(|frame| frame.encode().unwrap())
+            .collect();
+        let mut decoded = Vec::new();
+        while let Some((frame, len)) = Frame::decode(&buf).unwrap() {
+            decoded.push(frame);
+            buf.drain(..len);
+        }
+        assert_eq!(decoded, frames);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn incomplete_and_invalid_frames() {
+        let encoded = Frame::Data(vec![0; 10]).encode().unwrap();
+        assert_eq!(Frame::decode(&encoded[..8]), Ok(None));
+        assert_eq!(
+            Frame::decode(&[42, 0, 0, 0, 0]),
+            Err(ProtocolError::UnknownFrameType(42))
+        );
+
+        let text = "a".repeat(MAX_MESSAGE_LEN + 1);
+        assert_eq!(
+            TextMessage::new(MessageKind::Text, text.clone()),
+            Err(ProtocolError::MessageTooLarge(MAX_MESSAGE_LEN + 1))
+        );
+        let encoded = Frame::Message(TextMessage {
+            kind: MessageKind::Text,
+            text,
+        })
+        .encode()
+        .unwrap();
+        assert_eq!(
+            Frame::decode(&encoded),
+            Err(ProtocolError::MessageTooLarge(MAX_MESSAGE_LEN + 1))
+        );
+        assert_eq!(
+            Frame::Data(vec![0; MAX_PAYLOAD_LEN + 1]).encode(),
+            Err(ProtocolError::FrameTooLarge(MAX_PAYLOAD_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn splits_large_indexes_into_frames() {
+        let entries: BTreeMap<String, IndexEntry> = (0..10_000)
+            .map(|n| {
+                let entry = IndexEntry {
+                    size: n,
+                    mtime_nanos: 1_700_000_000_000_000_000,
+                    sha256: format!("{n:064x}"),
+                    deleted: false,
+                };
+                (format!("photos/{}/{n}.jpg", "a".repeat(100)), entry)
+            })
+            .collect();
+        let index = FolderIndex { entries };
+        let whole = Frame::SyncIndex(SyncIndex {
+            folder: "Shared".to_string(),
+            index: index.clone(),
+            more: false,
+        });
+        assert!(matches!(
+            whole.encode(),
+            Err(ProtocolError::FrameTooLarge(len)) if len > MAX_PAYLOAD_LEN
+        ));
+
+        let parts = SyncIndex::parts("Shared", &index);
+        assert!(parts.len() > 1);
+        let count = parts.len();
+        let mut merged = FolderIndex::default();
+        for (n, part) in parts.into_iter().enumerate() {
+            assert_eq!(part.more, n + 1 < count);
+            let encoded = Frame::SyncIndex(part.clone()).encode().unwrap();
+            let (decoded, _) = Frame::decode(&encoded).unwrap().unwrap();
+            assert_eq!(decoded, Frame::SyncIndex(part.clone()));
+            merged.entries.extend(part.index.entries);
+        }
+        assert_eq!(merged, index);
+
+        let empty = SyncIndex::parts("Shared", &FolderIndex::default());
+        assert_eq!(empty.len(), 1);
+        assert!(!empty[0].more);
+    }
+}
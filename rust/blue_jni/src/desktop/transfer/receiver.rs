@@ -0,0 +1,286 @@
+use super::protocol::{FileOffer, Frame, FrameType, ProtocolError, SyncIndex, TextMessage};
+
+/// What the driver of a [Receiver] has to do in response to a frame
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// The peer offers a file, the driver has to [Receiver::accept] or [Receiver::reject] it
+    Decide(FileOffer),
+    /// Append data to the file being received
+    Write(Vec<u8>),
+    /// All data has been received, the driver verifies and stores the file and tells the peer
+    /// with [Receiver::stored] or [Receiver::reject]
+    Finish(FileOffer),
+    /// The peer cancelled the current file, everything written so far has to be discarded
+    Abort,
+    /// The peer sent a text message, nothing is written to disk
+    TextReceived(TextMessage),
+    /// The peer starts a sync round with its index, the driver runs the round before handling further frames
+    SyncRequested(SyncIndex),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) enum ReceiverState {
+    #[default]
+    Idle,
+    AwaitingDecision(FileOffer),
+    Receiving {
+        offer: FileOffer,
+        received: u64,
+    },
+    /// All data is received, the peer waits for the result of storing the file
+    Storing(FileOffer),
+}
+
+impl ReceiverState {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "Idle",
+            Self::AwaitingDecision(_) => "AwaitingDecision",
+            Self::Receiving { .. } => "Receiving",
+            Self::Storing(_) => "Storing",
+        }
+    }
+}
+
+/// State machine of the receiving side of a transfer.
+/// It performs no I/O, so it can be driven by the RFCOMM transport as well as by recorded frames.
+#[derive(Debug, Default)]
+pub(crate) struct Receiver {
+    state: ReceiverState,
+}
+
+impl Receiver {
+    pub(crate) fn state(&self) -> &ReceiverState {
+        &self.state
+    }
+
+    /// Advances the state machine with a frame of the peer. A protocol violation leaves the state unchanged,
+    /// if a file is in progress the driver gives up on it with [Receiver::cancel].
+    pub(crate) fn handle(&mut self, frame: Frame) -> Result<Option<Action>, ProtocolError> {
+        let state = std::mem::take(&mut self.state);
+        let unexpected = |state: &ReceiverState, frame: &Frame| ProtocolError::UnexpectedFrame {
+            state: state.name(),
+            frame: frame.frame_type(),
+        };
+
+        let (state, action) = match (state, frame) {
+            (_, Frame::Cancel) => (ReceiverState::Idle, Some(Action::Abort)),
+            (ReceiverState::Idle, Frame::Offer(offer)) => (
+                ReceiverState::AwaitingDecision(offer.clone()),
+                Some(Action::Decide(offer)),
+            ),
+            (ReceiverState::Idle, Frame::Message(message)) => {
+                (ReceiverState::Idle, Some(Action::TextReceived(message)))
+            }
+            (ReceiverState::Idle, Frame::SyncIndex(index)) => {
+                (ReceiverState::Idle, Some(Action::SyncRequested(index)))
+            }
+            (ReceiverState::Receiving { offer, received }, Frame::Data(data)) => {
+                let total = received + data.len() as u64;
+                if total > offer.size {
+                    let err = ProtocolError::SizeMismatch {
+                        expected: offer.size,
+                        received: total,
+                    };
+                    self.state = ReceiverState::Receiving { offer, received };
+                    return Err(err);
+                }
+                (
+                    ReceiverState::Receiving {
+                        offer,
+                        received: total,
+                    },
+                    Some(Action::Write(data)),
+                )
+            }
+            (ReceiverState::Receiving { offer, received }, Frame::Done) => {
+                if received != offer.size {
+                    let err = ProtocolError::SizeMismatch {
+                        expected: offer.size,
+                        received,
+                    };
+                    self.state = ReceiverState::Receiving { offer, received };
+                    return Err(err);
+                }
+                (
+                    ReceiverState::Storing(offer.clone()),
+                    Some(Action::Finish(offer)),
+                )
+            }
+            (state, frame) => {
+                let err = unexpected(&state, &frame);
+                self.state = state;
+                return Err(err);
+            }
+        };
+        self.state = state;
+        Ok(action)
+    }
+
+    /// Accepts the pending offer and returns the frame to send to the peer
+    pub(crate) fn accept(&mut self) -> Result<Frame, ProtocolError> {
+        match std::mem::take(&mut self.state) {
+            ReceiverState::AwaitingDecision(offer) => {
+                self.state = ReceiverState::Receiving { offer, received: 0 };
+                Ok(Frame::Accept)
+            }
+            state => {
+                let err = ProtocolError::UnexpectedFrame {
+                    state: state.name(),
+                    frame: FrameType::Accept,
+                };
+                self.state = state;
+                Err(err)
+            }
+        }
+    }
+
+    /// Reports the received file as stored and returns the frame to send to the peer
+    pub(crate) fn stored(&mut self) -> Result<Frame, ProtocolError> {
+        match std::mem::take(&mut self.state) {
+            ReceiverState::Storing(_) => Ok(Frame::Stored),
+            state => {
+                let err = ProtocolError::UnexpectedFrame {
+                    state: state.name(),
+                    frame: FrameType::Stored,
+                };
+                self.state = state;
+                Err(err)
+            }
+        }
+    }
+
+    /// Rejects the pending offer, or the received file if it could not be stored,
+    /// and returns the frame to send to the peer
+    pub(crate) fn reject(&mut self, reason: &str) -> Result<Frame, ProtocolError> {
+        match std::mem::take(&mut self.state) {
+            ReceiverState::AwaitingDecision(_) | ReceiverState::Storing(_) => {
+                Ok(Frame::Reject(reason.to_string()))
+            }
+            state => {
+                let err = ProtocolError::UnexpectedFrame {
+                    state: state.name(),
+                    frame: FrameType::Reject,
+                };
+                self.state = state;
+                Err(err)
+            }
+        }
+    }
+
+    /// Gives up on the current file, e.g. after a protocol violation or when storing it failed,
+    /// and returns the frame telling the peer. `None` if no file is in progress.
+    pub(crate) fn cancel(&mut self) -> Option<Frame> {
+        match std::mem::take(&mut self.state) {
+            ReceiverState::Idle => None,
+            ReceiverState::AwaitingDecision(_)
+            | ReceiverState::Receiving { .. }
+            | ReceiverState::Storing(_) => Some(Frame::Cancel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::protocol::MessageKind;
+    use super::*;
+
+    fn offer(size: u64) -> FileOffer {
+        FileOffer {
+            name: "a.txt".to_string(),
+            size,
+            sha256: String::new(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn receives_a_file() {
+        let mut receiver = Receiver::default();
+        assert_eq!(
+            receiver.handle(Frame::Offer(offer(4))),
+            Ok(Some(Action::Decide(offer(4))))
+        );
+        assert_eq!(receiver.accept(), Ok(Frame::Accept));
+        assert_eq!(
+            receiver.handle(Frame::Data(b"da".to_vec())),
+            Ok(Some(Action::Write(b"da".to_vec())))
+        );
+        receiver.handle(Frame::Data(b"ta".to_vec())).unwrap();
+        assert_eq!(
+            receiver.handle(Frame::Done),
+            Ok(Some(Action::Finish(offer(4))))
+        );
+        assert_eq!(receiver.state(), &ReceiverState::Storing(offer(4)));
+        assert!(receiver.handle(Frame::Offer(offer(1))).is_err());
+        assert_eq!(receiver.stored(), Ok(Frame::Stored));
+        assert_eq!(receiver.state(), &ReceiverState::Idle);
+
+        // A file that fails verification is rejected after all
+        receiver.handle(Frame::Offer(offer(1))).unwrap();
+        receiver.accept().unwrap();
+        receiver.handle(Frame::Data(vec![1])).unwrap();
+        receiver.handle(Frame::Done).unwrap();
+        assert_eq!(
+            receiver.reject("Hash mismatch"),
+            Ok(Frame::Reject("Hash mismatch".to_string()))
+        );
+        assert_eq!(
+            receiver.stored(),
+            Err(ProtocolError::UnexpectedFrame {
+                state: "Idle",
+                frame: FrameType::Stored,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_protocol_violations() {
+        let mut receiver = Receiver::default();
+        assert!(receiver.handle(Frame::Data(vec![1])).is_err());
+
+        receiver.handle(Frame::Offer(offer(1))).unwrap();
+        receiver.accept().unwrap();
+        assert_eq!(
+            receiver.handle(Frame::Data(vec![1, 2])),
+            Err(ProtocolError::SizeMismatch {
+                expected: 1,
+                received: 2
+            })
+        );
+        // The file stays in progress until the driver tells the peer
+        assert!(matches!(
+            receiver.state(),
+            ReceiverState::Receiving { received: 0, .. }
+        ));
+        assert_eq!(receiver.cancel(), Some(Frame::Cancel));
+        assert_eq!(receiver.state(), &ReceiverState::Idle);
+        assert_eq!(receiver.cancel(), None);
+
+        receiver.handle(Frame::Offer(offer(2))).unwrap();
+        receiver.accept().unwrap();
+        receiver.handle(Frame::Data(vec![1])).unwrap();
+        assert!(receiver.handle(Frame::Done).is_err());
+        assert_eq!(receiver.handle(Frame::Cancel), Ok(Some(Action::Abort)));
+        assert_eq!(receiver.state(), &ReceiverState::Idle);
+    }
+
+    #[test]
+    fn receives_text_messages_between_files() {
+        let message = TextMessage {
+            kind: MessageKind::Url,
+            text: "https://example.com".to_string(),
+        };
+        let mut receiver = Receiver::default();
+        assert_eq!(
+            receiver.handle(Frame::Message(message.clone())),
+            Ok(Some(Action::TextReceived(message.clone())))
+        );
+        assert_eq!(receiver.state(), &ReceiverState::Idle);
+
+        receiver.handle(Frame::Offer(offer(1))).unwrap();
+        receiver.accept().unwrap();
+        assert!(receiver.handle(Frame::Message(message)).is_err());
+        assert!(matches!(receiver.state(), ReceiverState::Receiving { .. }));
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bluer::rfcomm::{Profile, Role, SocketAddr, Stream};
+use bluer::{Address, Session, Uuid};
+use futures::StreamExt;
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Instrument, Span};
+
+use jni::objects::{JClass, JObject, JString};
+use jni::JNIEnv;
+
+use crate::desktop::bt_manager;
+use crate::desktop::completion::complete_with;
+use crate::desktop::config::app_config;
+use crate::desktop::error::{on_error, Error, FailureKind, Result, ResultExt};
+use crate::desktop::guard::{jni_entry, spawn_guarded};
+
+use super::cancel::{cancellable, CancelSignal};
+use super::history::{transfer_history, Direction};
+use super::protocol::TextMessage;
+use super::session::{self, blocking, Connection, Peer, ReceiveSettings, TransferLog};
+use super::sync::{self, SyncFolderConfig, SyncReport};
+use super::sync_round::{start_sync, SyncContext};
+use super::{now_millis, start_capture, TransferId};
+
+/// Service UUID the transfer profile is registered with
+pub(crate) const SERVICE_UUID: Uuid = Uuid::from_u128(0x6b3f1c2e_5a7d_4e8b_9f10_2c3d4e5f6a7b);
+/// RFCOMM channel of the transfer service, peers connect to it without a service lookup
+pub(crate) const CHANNEL: u8 = 22;
+/// Time between two attempts to sync the sync folders with their peers
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Registers the transfer service with BlueZ and receives from every paired device that connects.
+/// Runs while attached to BlueZ, the service is unregistered when the session is dropped.
+pub(crate) async fn serve(session: Session) {
+    let profile = Profile {
+        uuid: SERVICE_UUID,
+        name: Some("Blue File Transfer".to_string()),
+        role: Some(Role::Server),
+        channel: Some(CHANNEL.into()),
+        require_authentication: Some(true),
+        require_authorization: Some(false),
+        ..Default::default()
+    };
+    let mut requests = match session
+        .register_profile(profile)
+        .await
+        .during("register the transfer service")
+    {
+        Ok(requests) => requests,
+        Err(err) => {
+            on_error(err);
+            return;
+        }
+    };
+    info!("Accepting transfers on RFCOMM channel {CHANNEL}");
+
+    while let Some(request) = requests.next().await {
+        let address = request.device();
+        match request.accept() {
+            Ok(stream) => {
+                let span = info_span!("transfer", transfer_id = Empty, device = %address);
+                let receive = cancellable(move |cancel| receive_from(address, stream, cancel));
+                spawn_guarded(
+                    async move {
+                        receive.await.map_err(on_error).ok();
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(err) => warn!("Could not accept the connection of {address}: {err}"),
+        }
+    }
+}
+
+async fn receive_from(address: Address, stream: Stream, cancel: CancelSignal) -> Result<()> {
+    let peer = peer(address).await;
+    let id = next_id().await?;
+    Span::current().record("transfer_id", id);
+    info!("Receiving");
+
+    let local_device = local_address(&stream).unwrap_or_default();
+    let mut log = TransferLog::new(id, Direction::Received, &peer);
+    let settings = ReceiveSettings::current(local_device);
+    let mut connection = connection(stream, id, cancel).await;
+    let result = session::receive(&mut connection, id, &peer, &settings, &mut log).await;
+    record(log).await;
+    result.on_device("receive files", &address)
+}
+
+/// Connects to the transfer service of `address` and sends the file at `path`
+pub(crate) async fn send_file(address: Address, path: PathBuf) -> Result<()> {
+    let span = info_span!("transfer", transfer_id = Empty, device = %address);
+    cancellable(|cancel| send_file_to(address, path, cancel))
+        .instrument(span)
+        .await
+}
+
+async fn send_file_to(address: Address, path: PathBuf, cancel: CancelSignal) -> Result<()> {
+    let stream = Stream::connect(SocketAddr::new(address, CHANNEL))
+        .await
+        .on_device("connect to the transfer service", &address)?;
+    let peer = peer(address).await;
+    let id = next_id().await?;
+    Span::current().record("transfer_id", id);
+    info!("Sending {path:?}");
+
+    let mut log = TransferLog::new(id, Direction::Sent, &peer);
+    let mut connection = connection(stream, id, cancel).await;
+    let result = session::send_file(&mut connection, id, &path, &mut log).await;
+    record(log).await;
+    result.on_device("send file", &address)
+}
+
+/// Connects to the transfer service of `address` and sends a text message
+pub(crate) async fn send_text(address: Address, message: TextMessage) -> Result<()> {
+    let span = info_span!("transfer", transfer_id = Empty, device = %address);
+    cancellable(|cancel| send_text_to(address, message, cancel))
+        .instrument(span)
+        .await
+}
+
+async fn send_text_to(address: Address, message: TextMessage, cancel: CancelSignal) -> Result<()> {
+    let stream = Stream::connect(SocketAddr::new(address, CHANNEL))
+        .await
+        .on_device("connect to the transfer service", &address)?;
+    let id = next_id().await?;
+    Span::current().record("transfer_id", id);
+    info!(
+        "Sending {:?} message of {} bytes",
+        message.kind,
+        message.text.len()
+    );
+    let mut connection = connection(stream, id, cancel).await;
+    session::send_text(&mut connection, message)
+        .await
+        .on_device("send text", &address)
+}
+
+/// Syncs every sync folder with its peer whenever the peer is in range. Runs while attached to BlueZ.
+pub(crate) async fn sync_periodically() {
+    loop {
+        let folders = app_config().lock().unwrap().sync_folders.clone();
+        for config in folders {
+            let span = info_span!(
+                "sync",
+                folder = config.name(),
+                device = config.peer_address,
+                transfer_id = Empty
+            );
+            let sync = cancellable(|cancel| sync_folder(&config, cancel));
+            match sync.instrument(span).await {
+                Ok(report) => info!("Synced {:?}: {report:?}", config.name()),
+                // Mostly the peer is out of range or its app is not running
+                Err(err) => info!("Could not sync {:?}: {err}", config.name()),
+            }
+        }
+        // Keeps two devices from running into each other's rounds every time
+        let jitter = Duration::from_millis(now_millis() % 30_000);
+        tokio::time::sleep(SYNC_INTERVAL + jitter).await;
+    }
+}
+
+async fn sync_folder(config: &SyncFolderConfig, cancel: CancelSignal) -> Result<SyncReport> {
+    let address = Address::from_str(&config.peer_address)
+        .map_err(|_| Error::Generic(format!("Invalid device address: {}", config.peer_address)))?;
+    let stream = Stream::connect(SocketAddr::new(address, CHANNEL))
+        .await
+        .on_device("connect to the transfer service", &address)?;
+    let local_device = local_address(&stream)?;
+    let state_root = sync::state_root();
+    let id = next_id().await?;
+    Span::current().record("transfer_id", id);
+    let context = SyncContext {
+        transfer_id: id,
+        state_root: &state_root,
+        local_device: &local_device,
+        peer_device: &config.peer_address,
+    };
+    let mut connection = connection(stream, id, cancel).await;
+    start_sync(&mut connection, config, &context)
+        .await
+        .on_device("sync folder", &address)
+}
+
+/// Address of the adapter the connection runs on
+fn local_address(stream: &Stream) -> Result<String> {
+    let address = stream
+        .as_ref()
+        .local_addr()
+        .map_err(|err| Error::failed(FailureKind::Io, format!("No local address: {err}")))?;
+    Ok(address.addr.to_string())
+}
+
+/// Address and name of a device, the name is only known if the selected adapter has seen the device
+async fn peer(address: Address) -> Peer {
+    let adapter = bt_manager().lock().await.adapter.clone();
+    let name = match adapter.map(|adapter| adapter.device(address)) {
+        Some(Ok(device)) => device.name().await.ok().flatten(),
+        _ => None,
+    };
+    Peer {
+        address: address.to_string(),
+        name,
+    }
+}
+
+/// Connection of a transfer, with its frames captured if capturing is enabled
+async fn connection(stream: Stream, id: TransferId, cancel: CancelSignal) -> Connection<Stream> {
+    let capture = blocking(move || start_capture(id)).await;
+    Connection::new(stream)
+        .with_capture(capture.unwrap_or_default())
+        .with_cancel(cancel)
+}
+
+async fn next_id() -> Result<TransferId> {
+    blocking(|| transfer_history().lock().unwrap().next_id()).await?
+}
+
+async fn record(log: TransferLog) {
+    let Some(record) = log.finish() else {
+        return;
+    };
+    let result = blocking(move || {
+        transfer_history()
+            .lock()
+            .unwrap()
+            .record(&record)
+            .map_err(|err| warn!(transfer_id = record.id, "Could not record transfer: {err}"))
+    })
+    .await;
+    if let Err(err) = result {
+        warn!("Could not record transfer: {err}");
+    }
+}
+
+/// Completes `future` once the device received the file
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_sendFileAsync<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    target_address: JString<'local>,
+    path: JString<'local>,
+    future: JObject<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::sendFile", (), |env| {
+        let target_address: String = env
+            .get_string(&target_address)
+            .expect("Getting String from env should not fail")
+            .into();
+        let path: String = env
+            .get_string(&path)
+            .expect("Getting String from env should not fail")
+            .into();
+        info!("TransferManager::sendFile({target_address}, {path})");
+
+        complete_with(env, &future, async move {
+            let address = Address::from_str(&target_address)
+                .map_err(|_| Error::Generic(format!("Invalid device address: {target_address}")))?;
+            send_file(address, PathBuf::from(path)).await
+        });
+    })
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::path::Path;
+use std::ptr;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+use tracing::{info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::sys::{jboolean, jlong, jstring};
+use jni::JNIEnv;
+
+use crate::desktop::config::{app_config, update_config};
+use crate::desktop::error::{on_error, Error};
+use crate::desktop::events::{self, Event};
+use crate::desktop::guard::jni_entry;
+use crate::desktop::upcall;
+
+use super::protocol::FileOffer;
+use super::TransferId;
+
+/// Time the user has to answer an offer
+const ASK_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Offers waiting for an answer of the user by transfer ID
+    static ref PENDING_OFFERS: Mutex<HashMap<TransferId, oneshot::Sender<bool>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RuleAction {
+    Accept,
+    Reject,
+    Ask,
+}
+
+/// Time of day in minutes after midnight, written as `HH:MM`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TimeOfDay(u16);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("Invalid time of day {value:?}, expected HH:MM");
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: u16 = hours.trim().parse().map_err(|_| invalid())?;
+        let minutes: u16 = minutes.trim().parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self(hours * 60 + minutes))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl TimeOfDay {
+    /// Current local time of day
+    pub(crate) fn now() -> Self {
+        // SAFETY: `time` with a null pointer only returns the current time,
+        // `localtime_r` only writes to the provided `tm`, which is read after it succeeded
+        let tm = unsafe {
+            let now = libc::time(ptr::null_mut());
+            let mut tm = MaybeUninit::<libc::tm>::uninit();
+            if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+                return Self(0);
+            }
+            tm.assume_init()
+        };
+        Self((tm.tm_hour * 60 + tm.tm_min) as u16)
+    }
+}
+
+/// Time window in which a rule applies. A window whose end lies before its start wraps around midnight,
+/// a window whose start and end are equal covers the whole day.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TimeWindow {
+    pub(crate) start: TimeOfDay,
+    pub(crate) end: TimeOfDay,
+}
+
+impl TimeWindow {
+    fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// A rule for incoming offers. All given conditions have to match for the rule to apply.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Rule {
+    pub(crate) name: String,
+    /// Device address of the sender. Device names are chosen by the peer, so they are not matched.
+    #[serde(default)]
+    pub(crate) sender: Option<String>,
+    /// File extensions without leading dot, e.g. `["jpg", "png"]`
+    #[serde(default)]
+    pub(crate) extensions: Vec<String>,
+    #[serde(default)]
+    pub(crate) min_size: Option<u64>,
+    #[serde(default)]
+    pub(crate) max_size: Option<u64>,
+    #[serde(default)]
+    pub(crate) time_window: Option<TimeWindow>,
+    pub(crate) action: RuleAction,
+}
+
+pub(crate) struct OfferContext<'a> {
+    pub(crate) sender_address: &'a str,
+    pub(crate) sender_name: Option<&'a str>,
+    pub(crate) offer: &'a FileOffer,
+    pub(crate) time: TimeOfDay,
+}
+
+impl Rule {
+    fn matches(&self, ctx: &OfferContext) -> bool {
+        let sender_matches = self
+            .sender
+            .as_ref()
+            .is_none_or(|sender| sender.eq_ignore_ascii_case(ctx.sender_address));
+        let extension_matches = self.extensions.is_empty()
+            || Path::new(&ctx.offer.name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    self.extensions
+                        .iter()
+                        .any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(ext))
+                });
+        let size_matches = self.min_size.is_none_or(|min| ctx.offer.size >= min)
+            && self.max_size.is_none_or(|max| ctx.offer.size <= max);
+        let time_matches = self
+            .time_window
+            .as_ref()
+            .is_none_or(|window| window.contains(ctx.time));
+
+        sender_matches && extension_matches && size_matches && time_matches
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Decision {
+    pub(crate) action: RuleAction,
+    /// Name of the rule that produced the decision, `None` if no rule matched
+    pub(crate) rule: Option<String>,
+}
+
+/// Evaluates the rules in order, the first matching rule decides. Without a match the user is asked.
+pub(crate) fn evaluate(rules: &[Rule], ctx: &OfferContext) -> Decision {
+    let decision = rules
+        .iter()
+        .find(|rule| rule.matches(ctx))
+        .map(|rule| Decision {
+            action: rule.action,
+            rule: Some(rule.name.clone()),
+        })
+        .unwrap_or(Decision {
+            action: RuleAction::Ask,
+            rule: None,
+        });
+
+    info!(
+        "Offer of {:?} ({} bytes) from {} ({}): {:?} by {}",
+        ctx.offer.name,
+        ctx.offer.size,
+        ctx.sender_address,
+        ctx.sender_name.unwrap_or("unknown"),
+        decision.action,
+        decision
+            .rule
+            .as_ref()
+            .map_or("default".to_string(), |rule| format!("rule {rule:?}"))
+    );
+    decision
+}
+
+/// Asks the user whether to accept an offer no rule decided. Without an answer in time the offer is rejected.
+pub(crate) async fn ask_user(
+    transfer_id: TransferId,
+    sender_address: &str,
+    sender_name: Option<&str>,
+    offer: &FileOffer,
+) -> bool {
+    // E.g. in tests there is nobody to ask
+    if !upcall::is_ready() {
+        return false;
+    }
+    let (tx, rx) = oneshot::channel();
+    PENDING_OFFERS.lock().unwrap().insert(transfer_id, tx);
+    events::emit(Event::TransferOffered {
+        transfer_id,
+        sender: sender_address.to_string(),
+        sender_name: sender_name.map(ToString::to_string),
+        file_name: offer.name.clone(),
+        size: offer.size,
+    });
+
+    let answer = timeout(ASK_TIMEOUT, rx).await;
+    PENDING_OFFERS.lock().unwrap().remove(&transfer_id);
+    match answer {
+        Ok(Ok(accept)) => accept,
+        Ok(Err(_)) => false,
+        Err(_) => {
+            info!(transfer_id, "No answer to the offer of {:?}", offer.name);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_getAutoAcceptRules<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jstring {
+    jni_entry(
+        &mut env,
+        "TransferManager::getAutoAcceptRules",
+        ptr::null_mut(),
+        |env| {
+            info!("TransferManager::getAutoAcceptRules()");
+
+            let json = serde_json::to_string(&app_config().lock().unwrap().rules)
+                .expect("Serializing rules should not fail");
+            env.new_string(json)
+                .map(|json| json.into_raw())
+                .unwrap_or(ptr::null_mut())
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setAutoAcceptRules<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    rules: JString<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::setAutoAcceptRules", (), |env| {
+        info!("TransferManager::setAutoAcceptRules()");
+
+        let rules: String = env
+            .get_string(&rules)
+            .expect("Getting String from env should not fail")
+            .into();
+        let rules: Vec<Rule> = match serde_json::from_str(&rules) {
+            Ok(rules) => rules,
+            Err(err) => {
+                on_error(Error::Generic(format!("Invalid auto-accept rules: {err}")));
+                return;
+            }
+        };
+        update_config(|config| config.rules = rules)
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_respondToOffer<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    transfer_id: jlong,
+    accept: jboolean,
+) {
+    jni_entry(&mut env, "TransferManager::respondToOffer", (), |_env| {
+        info!("TransferManager::respondToOffer({transfer_id}, {accept})");
+
+        let pending = PENDING_OFFERS
+            .lock()
+            .unwrap()
+            .remove(&(transfer_id as TransferId));
+        match pending {
+            Some(tx) => {
+                let _ = tx.send(accept != 0);
+            }
+            None => warn!(transfer_id, "Offer is not waiting for an answer anymore"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(name: &str, size: u64) -> FileOffer {
+        FileOffer {
+            name: name.to_string(),
+            size,
+            sha256: String::new(),
+            metadata: Default::default(),
+        }
+    }
+
+    fn time(value: &str) -> TimeOfDay {
+        TimeOfDay::try_from(value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules: Vec<Rule> = serde_json::from_str(
+            r#"[
+                {"name": "no executables", "extensions": ["exe", ".sh"], "action": "reject"},
+                {"name": "my phone", "sender": "AA:BB:CC:DD:EE:FF", "max_size": 1000,
+                 "time_window": {"start": "22:00", "end": "06:00"}, "action": "accept"}
+            ]"#,
+        )
+        .unwrap();
+        let decide_from = |sender_address: &str, name: &str, size: u64, at: &str| {
+            let offer = offer(name, size);
+            let ctx = OfferContext {
+                sender_address,
+                sender_name: Some("AA:BB:CC:DD:EE:FF"),
+                offer: &offer,
+                time: time(at),
+            };
+            let decision = evaluate(&rules, &ctx);
+            (decision.action, decision.rule)
+        };
+        let decide =
+            |name: &str, size: u64, at: &str| decide_from("aa:bb:cc:dd:ee:ff", name, size, at);
+
+        assert_eq!(
+            decide("run.SH", 10, "23:00"),
+            (RuleAction::Reject, Some("no executables".to_string()))
+        );
+        assert_eq!(
+            decide("photo.jpg", 10, "01:30"),
+            (RuleAction::Accept, Some("my phone".to_string()))
+        );
+        assert_eq!(decide("photo.jpg", 10, "12:00"), (RuleAction::Ask, None));
+        assert_eq!(decide("video.mp4", 5000, "23:00"), (RuleAction::Ask, None));
+        // A peer naming itself like the trusted address does not match
+        assert_eq!(
+            decide_from("11:22:33:44:55:66", "photo.jpg", 10, "01:30"),
+            (RuleAction::Ask, None)
+        );
+    }
+
+    #[test]
+    fn equal_start_and_end_cover_the_whole_day() {
+        let window = TimeWindow {
+            start: time("08:00"),
+            end: time("08:00"),
+        };
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("08:00")));
+        assert!(window.contains(time("23:59")));
+
+        let window = TimeWindow {
+            start: time("08:00"),
+            end: time("08:01"),
+        };
+        assert!(window.contains(time("08:00")));
+        assert!(!window.contains(time("08:01")));
+    }
+
+    #[test]
+    fn parses_time_of_day() {
+        assert_eq!(time("07:05").to_string(), "07:05");
+        assert!(TimeOfDay::try_from("24:00".to_string()).is_err());
+        assert!(TimeOfDay::try_from("noon".to_string()).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::JNIEnv;
+use util::CommandConfig;
+
+use crate::desktop::config::update_config;
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::guard::jni_entry;
+
+static DEFAULT_CLAMD_SOCKET: &str = "/run/clamav/clamd.ctl";
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ScanVerdict {
+    Clean,
+    /// The scanner flagged the file, e.g. with the name of the detected signature
+    Infected(String),
+}
+
+/// Checks received files before they leave quarantine
+pub(crate) trait Scanner: Send + Sync {
+    fn name(&self) -> &str;
+    fn scan(&self, path: &Path) -> Result<ScanVerdict>;
+}
+
+/// Talks to a local clamd over its Unix socket using the INSTREAM command
+pub(crate) struct ClamdScanner {
+    socket_path: PathBuf,
+    timeout: Duration,
+}
+
+impl ClamdScanner {
+    pub(crate) fn new(socket_path: PathBuf, timeout: Duration) -> Self {
+        Self {
+            socket_path,
+            timeout,
+        }
+    }
+}
+
+impl Scanner for ClamdScanner {
+    fn name(&self) -> &str {
+        "clamd"
+    }
+
+    fn scan(&self, path: &Path) -> Result<ScanVerdict> {
+        let scan_error = |err: std::io::Error| {
+            Error::Generic(format!("clamd at {:?} failed: {err}", self.socket_path))
+        };
+        let mut stream = UnixStream::connect(&self.socket_path).map_err(scan_error)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(scan_error)?;
+        stream
+            .set_write_timeout(Some(self.timeout))
+            .map_err(scan_error)?;
+
+        stream.write_all(b"zINSTREAM\0").map_err(scan_error)?;
+        let mut file = File::open(path)?;
+        let mut chunk = vec![0u8; CLAMD_CHUNK_SIZE];
+        loop {
+            let len = file.read(&mut chunk)?;
+            if len == 0 {
+                break;
+            }
+            stream
+                .write_all(&(len as u32).to_be_bytes())
+                .map_err(scan_error)?;
+            stream.write_all(&chunk[..len]).map_err(scan_error)?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).map_err(scan_error)?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).map_err(scan_error)?;
+        let reply = String::from_utf8_lossy(&reply);
+        let reply = reply.trim_end_matches(['\0', '\n']);
+        parse_clamd_reply(reply)
+    }
+}
+
+/// Parses replies like `stream: OK` or `stream: Eicar-Signature FOUND`
+fn parse_clamd_reply(reply: &str) -> Result<ScanVerdict> {
+    let result = reply.split_once(": ").map_or(reply, |(_, result)| result);
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected(signature.to_string()))
+    } else {
+        Err(Error::Generic(format!(
+            "Unexpected reply from clamd: {reply}"
+        )))
+    }
+}
+
+/// Runs a command with the file path as last argument.
+/// Exit code 0 means clean, 1 means infected, everything else is treated as a failed scan.
+pub(crate) struct CommandScanner {
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandScanner {
+    pub(crate) fn new(command: String, args: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            command,
+            args,
+            timeout,
+        }
+    }
+}
+
+impl Scanner for CommandScanner {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn scan(&self, path: &Path) -> Result<ScanVerdict> {
+        let path = path.to_string_lossy();
+        let mut args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        args.push(&path);
+        let command = CommandConfig {
+            command: &self.command,
+            args,
+        };
+        let output =
+            util::run_command_with_timeout(&command, &[], self.timeout).map_err(|err| {
+                Error::Generic(format!("Could not run scanner {}: {err}", self.command))
+            })?;
+
+        match output.status.and_then(|status| status.code()) {
+            Some(0) => Ok(ScanVerdict::Clean),
+            Some(1) => {
+                let signature = output.stdout.lines().last().unwrap_or_default().trim();
+                Ok(ScanVerdict::Infected(if signature.is_empty() {
+                    format!("flagged by {}", self.command)
+                } else {
+                    signature.to_string()
+                }))
+            }
+            Some(code) => Err(Error::Generic(format!(
+                "Scanner {} failed with exit code {code}: {}",
+                self.command,
+                output.stderr.trim()
+            ))),
+            None => Err(Error::Generic(format!(
+                "Scanner {} did not finish within {:?}",
+                self.command, self.timeout
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ScannerConfig {
+    Clamd {
+        #[serde(default = "default_clamd_socket")]
+        socket_path: PathBuf,
+        #[serde(default = "default_timeout_secs")]
+        timeout_secs: u64,
+    },
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default = "default_timeout_secs")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_clamd_socket() -> PathBuf {
+    PathBuf::from(DEFAULT_CLAMD_SOCKET)
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+impl ScannerConfig {
+    pub(crate) fn scanner(&self) -> Box<dyn Scanner> {
+        match self {
+            Self::Clamd {
+                socket_path,
+                timeout_secs,
+            } => Box::new(ClamdScanner::new(
+                socket_path.clone(),
+                Duration::from_secs(*timeout_secs),
+            )),
+            Self::Command {
+                command,
+                args,
+                timeout_secs,
+            } => Box::new(CommandScanner::new(
+                command.clone(),
+                args.clone(),
+                Duration::from_secs(*timeout_secs),
+            )),
+        }
+    }
+}
+
+/// Scans a quarantined file. Flagged files and files that could not be scanned
+/// result in an error and have to stay in quarantine.
+pub(crate) fn check(scanner: &dyn Scanner, path: &Path, file_name: &str) -> Result<()> {
+    match scanner.scan(path) {
+        Ok(ScanVerdict::Clean) => {
+            info!("{} found no threats in {:?}", scanner.name(), file_name);
+            Ok(())
+        }
+        Ok(ScanVerdict::Infected(signature)) => {
+            warn!("{} flagged {:?}: {signature}", scanner.name(), file_name);
+            Err(Error::FileQuarantined {
+                file_name: file_name.to_string(),
+                reason: signature,
+            })
+        }
+        Err(err) => {
+            warn!("Scanning {:?} failed: {err}", file_name);
+            Err(Error::FileQuarantined {
+                file_name: file_name.to_string(),
+                reason: format!("Scan failed: {err}"),
+            })
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setContentScanner<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    scanner: JString<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::setContentScanner", (), |env| {
+        info!("TransferManager::setContentScanner()");
+
+        let scanner = if scanner.is_null() {
+            None
+        } else {
+            let scanner: String = env
+                .get_string(&scanner)
+                .expect("Getting String from env should not fail")
+                .into();
+            match serde_json::from_str::<ScannerConfig>(&scanner) {
+                Ok(scanner) => Some(scanner),
+                Err(err) => {
+                    on_error(Error::Generic(format!("Invalid scanner config: {err}")));
+                    return;
+                }
+            }
+        };
+        update_config(|config| config.scanner = scanner)
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Minimal clamd speaking the INSTREAM protocol, flags every stream containing "EICAR"
+    fn fake_clamd(socket_path: &Path) -> thread::JoinHandle<Vec<u8>> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 10];
+            stream.read_exact(&mut command).unwrap();
+            assert_eq!(&command, b"zINSTREAM\0");
+
+            let mut data = Vec::new();
+            loop {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len).unwrap();
+                let len = u32::from_be_bytes(len) as usize;
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0u8; len];
+                stream.read_exact(&mut chunk).unwrap();
+                data.extend_from_slice(&chunk);
+            }
+
+            let infected = data.windows(5).any(|window| window == b"EICAR");
+            let reply: &[u8] = if infected {
+                b"stream: Eicar-Test-Signature FOUND\0"
+            } else {
+                b"stream: OK\0"
+            };
+            stream.write_all(reply).unwrap();
+            data
+        })
+    }
+
+    #[test]
+    fn clamd_scanner_reports_verdicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("clamd.sock");
+        let scanner = ClamdScanner::new(socket_path.clone(), Duration::from_secs(5));
+
+        let clean = dir.path().join("clean.txt");
+        std::fs::write(&clean, b"hello").unwrap();
+        let server = fake_clamd(&socket_path);
+        assert_eq!(scanner.scan(&clean).unwrap(), ScanVerdict::Clean);
+        assert_eq!(server.join().unwrap(), b"hello");
+
+        std::fs::remove_file(&socket_path).unwrap();
+        let infected = dir.path().join("infected.txt");
+        std::fs::write(&infected, b"X5O!P%@AP EICAR").unwrap();
+        let server = fake_clamd(&socket_path);
+        assert!(matches!(
+            check(&scanner, &infected, "infected.txt"),
+            Err(Error::FileQuarantined { reason, .. }) if reason == "Eicar-Test-Signature"
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn command_scanner_uses_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let scanner = CommandScanner::new(
+            "sh".to_string(),
+            vec![
+                "-c".to_string(),
+                r#"grep -q EICAR "$0" && echo Eicar && exit 1; exit 0"#.to_string(),
+            ],
+            Duration::from_secs(5),
+        );
+
+        let clean = dir.path().join("clean.txt");
+        std::fs::write(&clean, b"hello").unwrap();
+        assert_eq!(scanner.scan(&clean).unwrap(), ScanVerdict::Clean);
+
+        let infected = dir.path().join("infected.txt");
+        std::fs::write(&infected, b"EICAR").unwrap();
+        assert_eq!(
+            scanner.scan(&infected).unwrap(),
+            ScanVerdict::Infected("Eicar".to_string())
+        );
+    }
+}
@@ -0,0 +1,914 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::FutureExt;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn, Instrument, Span};
+
+use crate::desktop::config::app_config;
+use crate::desktop::error::{on_error, Error, FailureKind, Result};
+use crate::desktop::guard::spawn_guarded;
+
+use super::cancel::{cancelled, CancelSignal};
+use super::capture::{self, Capture};
+use super::history::{Direction, FileRecord, Outcome, TransferRecord};
+use super::hook::{run_post_receive_hook, PostReceiveHook};
+use super::message::on_text_received;
+use super::metadata::MetadataPolicy;
+use super::placement::{IncomingFile, PlacementPolicy};
+use super::protocol::{FileMetadata, FileOffer, Frame, ProtocolError, SyncIndex, TextMessage};
+use super::receiver::{Action, Receiver, ReceiverState};
+use super::rules::{self, OfferContext, Rule, RuleAction, TimeOfDay};
+use super::scanner::ScannerConfig;
+use super::sync::{self, SyncFolderConfig};
+use super::sync_round::{answer_sync, SyncContext};
+use super::throttle::ThrottleGuard;
+use super::{now_millis, report_progress, to_hex, TransferId};
+
+/// File data sent per data frame
+pub(crate) const CHUNK_LEN: usize = 32 * 1024;
+/// Bytes read from the connection at once
+const READ_LEN: usize = 16 * 1024;
+/// Time the peer gets to take the cancel frame of a cancelled transfer
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The device on the other end of a connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Peer {
+    pub(crate) address: String,
+    /// Name announced by the device, only known for devices BlueZ has seen
+    pub(crate) name: Option<String>,
+}
+
+impl Peer {
+    /// Name for folders and logs, the address if the device has no name
+    fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.address)
+    }
+}
+
+/// Settings of the receiving side, taken from the app config when a connection is accepted
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReceiveSettings {
+    pub(crate) placement: PlacementPolicy,
+    pub(crate) metadata: MetadataPolicy,
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) post_receive_hook: Option<PostReceiveHook>,
+    pub(crate) scanner: Option<ScannerConfig>,
+    pub(crate) sync_folders: Vec<SyncFolderConfig>,
+    /// See [sync::state_root]
+    pub(crate) sync_state_root: PathBuf,
+    /// Address of the adapter the connection was accepted on, the device id of the sync folders
+    pub(crate) local_device: String,
+}
+
+impl ReceiveSettings {
+    pub(crate) fn current(local_device: String) -> Self {
+        let config = app_config().lock().unwrap();
+        Self {
+            placement: config.receive.clone(),
+            metadata: config.metadata.clone(),
+            rules: config.rules.clone(),
+            post_receive_hook: config.post_receive_hook.clone(),
+            scanner: config.scanner.clone(),
+            sync_folders: config.sync_folders.clone(),
+            sync_state_root: sync::state_root(),
+            local_device,
+        }
+    }
+}
+
+/// Frames over a byte stream, e.g. an RFCOMM socket
+pub(crate) struct Connection<S> {
+    stream: S,
+    buf: Vec<u8>,
+    /// Records every frame read or written, see [super::start_capture]
+    capture: Option<Capture<File>>,
+    /// Fires when the transfer is cancelled, see [super::cancel::cancellable]
+    cancel: Option<CancelSignal>,
+    /// Whether the peer was told about the cancellation already
+    cancel_sent: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+            capture: None,
+            cancel: None,
+            cancel_sent: false,
+        }
+    }
+
+    pub(crate) fn with_capture(mut self, capture: Option<Capture<File>>) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    pub(crate) fn with_cancel(mut self, cancel: CancelSignal) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelSignal::is_cancelled)
+    }
+
+    /// Tells the peer the transfer was cancelled. Best effort, the peer may be gone already.
+    async fn send_cancel(&mut self) -> Error {
+        if !self.cancel_sent {
+            self.cancel_sent = true;
+            self.capture(capture::Direction::Sent, &Frame::Cancel);
+            let cancel = async {
+                self.stream.write_all(&Frame::Cancel.encode()?).await?;
+                self.stream.flush().await?;
+                Ok::<_, Error>(())
+            };
+            match tokio::time::timeout(CANCEL_TIMEOUT, cancel).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => info!("Could not tell the peer about the cancellation: {err}"),
+                Err(_) => info!("Could not tell the peer about the cancellation in time"),
+            }
+        }
+        cancelled()
+    }
+
+    fn capture(&mut self, direction: capture::Direction, frame: &Frame) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        if let Err(err) = capture.record(direction, frame) {
+            // The transfer goes on, the capture would have a gap anyway
+            warn!("Stopped capturing frames: {err}");
+            self.capture = None;
+        }
+    }
+
+    /// Next frame of the peer, `None` once the peer closed the connection.
+    /// Cancel safe: nothing is lost if the future is dropped before it completes.
+    pub(crate) async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some((frame, len)) = Frame::decode(&self.buf)? {
+                self.buf.drain(..len);
+                self.capture(capture::Direction::Received, &frame);
+                return Ok(Some(frame));
+            }
+            let mut chunk = [0; READ_LEN];
+            let len = match self.cancel.clone() {
+                Some(cancel) => tokio::select! {
+                    len = self.stream.read(&mut chunk) => len?,
+                    _ = cancel.cancelled() => return Err(self.send_cancel().await),
+                },
+                None => self.stream.read(&mut chunk).await?,
+            };
+            if len == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(closed("in the middle of a frame"));
+            }
+            self.buf.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    pub(crate) async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(self.send_cancel().await);
+        }
+        let encoded = frame.encode()?;
+        self.capture(capture::Direction::Sent, frame);
+        self.stream.write_all(&encoded).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Tells the peer nothing more is sent and waits until it closed its side as well,
+    /// so the last frames are not lost when the socket is dropped
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        self.stream.shutdown().await?;
+        while self.read_frame().await?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Outcome of a transfer which ended with an error
+fn failure<S: AsyncRead + AsyncWrite + Unpin>(connection: &Connection<S>) -> Outcome {
+    if connection.is_cancelled() {
+        Outcome::Cancelled
+    } else {
+        Outcome::Failed
+    }
+}
+
+pub(crate) fn closed(when: &str) -> Error {
+    Error::failed(FailureKind::Io, format!("Connection closed {when}"))
+}
+
+/// Runs blocking file system work off the runtime, in the span of the caller
+pub(crate) async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T> {
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(work))
+        .await
+        .map_err(|err| Error::Internal(format!("Transfer task failed: {err}")))
+}
+
+/// Collects the history record of a connection while it runs
+#[derive(Debug)]
+pub(crate) struct TransferLog {
+    record: TransferRecord,
+}
+
+impl TransferLog {
+    pub(crate) fn new(id: TransferId, direction: Direction, peer: &Peer) -> Self {
+        Self {
+            record: TransferRecord {
+                id,
+                direction,
+                peer_address: peer.address.clone(),
+                peer_name: peer.name.clone(),
+                files: Vec::new(),
+                started_at: now_millis(),
+                ended_at: 0,
+                outcome: Outcome::Completed,
+                error: None,
+            },
+        }
+    }
+
+    fn add_file(&mut self, offer: &FileOffer) {
+        self.record.files.push(FileRecord {
+            name: offer.name.clone(),
+            size: offer.size,
+            sha256: Some(offer.sha256.clone()),
+        });
+    }
+
+    /// Keeps the first failure, later ones are usually caused by it
+    pub(crate) fn fail(&mut self, outcome: Outcome, error: impl ToString) {
+        if self.record.outcome == Outcome::Completed {
+            self.record.outcome = outcome;
+            self.record.error = Some(error.to_string());
+        }
+    }
+
+    /// The record to store in the history, `None` if no file was offered, e.g. for text messages
+    pub(crate) fn finish(mut self) -> Option<TransferRecord> {
+        if self.record.files.is_empty() {
+            return None;
+        }
+        self.record.ended_at = now_millis();
+        Some(self.record)
+    }
+}
+
+/// Answers the offers of `peer` until it closes the connection. Failures of single files are reported
+/// and noted in `log`, the returned error ends the connection.
+pub(crate) async fn receive<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    id: TransferId,
+    peer: &Peer,
+    settings: &ReceiveSettings,
+    log: &mut TransferLog,
+) -> Result<()> {
+    let mut session = ReceiveSession {
+        connection,
+        id,
+        peer,
+        settings,
+        receiver: Receiver::default(),
+        file: None,
+        throttle: None,
+    };
+    let result = session.run(log).await;
+    if let Err(err) = &result {
+        log.fail(failure(session.connection), err);
+    }
+    result
+}
+
+struct ReceiveSession<'a, S> {
+    connection: &'a mut Connection<S>,
+    id: TransferId,
+    peer: &'a Peer,
+    settings: &'a ReceiveSettings,
+    receiver: Receiver,
+    /// The accepted file while its data is received
+    file: Option<IncomingFile>,
+    /// Only registered while data is received, so idle connections and pending offers
+    /// take no share of the bandwidth
+    throttle: Option<ThrottleGuard>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ReceiveSession<'_, S> {
+    async fn run(&mut self, log: &mut TransferLog) -> Result<()> {
+        while let Some(frame) = self.connection.read_frame().await? {
+            let action = match self.receiver.handle(frame) {
+                Ok(action) => action,
+                Err(err) => {
+                    // The peer is out of step, nothing it sends can be trusted anymore
+                    self.abandon().await?;
+                    return Err(err.into());
+                }
+            };
+            match action {
+                None => {}
+                Some(Action::Decide(offer)) => self.decide(offer, log).await?,
+                Some(Action::Write(data)) => {
+                    if let Err(err) = self.write(data).await {
+                        // Data frames already on their way would follow the cancel
+                        self.abandon().await?;
+                        return Err(err);
+                    }
+                }
+                Some(Action::Finish(offer)) => self.finish(offer, log).await?,
+                Some(Action::Abort) => {
+                    self.file = None;
+                    self.throttle = None;
+                    log.fail(Outcome::Cancelled, "Cancelled by the sender");
+                }
+                Some(Action::TextReceived(message)) => {
+                    on_text_received(&self.peer.address, &message);
+                }
+                Some(Action::SyncRequested(request)) => self.sync(request).await?,
+            }
+        }
+        if self.file.take().is_some() {
+            return Err(closed("before the file was complete"));
+        }
+        Ok(())
+    }
+
+    /// Runs the sync round the peer started, the connection stays open for further frames afterwards
+    async fn sync(&mut self, request: SyncIndex) -> Result<()> {
+        let folder = request.folder.clone();
+        let context = SyncContext {
+            transfer_id: self.id,
+            state_root: &self.settings.sync_state_root,
+            local_device: &self.settings.local_device,
+            peer_device: &self.peer.address,
+        };
+        let report = answer_sync(
+            self.connection,
+            request,
+            &self.settings.sync_folders,
+            &context,
+        );
+        if let Some(report) = report.await? {
+            info!(
+                "Synced {folder:?} with {}: {report:?}",
+                self.peer.display_name()
+            );
+        }
+        Ok(())
+    }
+
+    async fn decide(&mut self, offer: FileOffer, log: &mut TransferLog) -> Result<()> {
+        log.add_file(&offer);
+        if let Some(reason) = self.refusal(&offer).await {
+            log.fail(Outcome::Rejected, &reason);
+            let reply = self.receiver.reject(&reason)?;
+            return self.connection.write_frame(&reply).await;
+        }
+        let reply = match self.prepare(&offer).await {
+            Ok(Some(file)) => {
+                self.file = Some(file);
+                self.throttle = Some(ThrottleGuard::register(self.id));
+                self.receiver.accept()?
+            }
+            Ok(None) => {
+                log.fail(Outcome::Rejected, "File already exists");
+                self.receiver.reject("File already exists")?
+            }
+            Err(err) => {
+                info!("Rejected {:?}: {err}", offer.name);
+                let reply = self.receiver.reject(&err.to_string())?;
+                log.fail(Outcome::Rejected, &err);
+                on_error(err);
+                reply
+            }
+        };
+        self.connection.write_frame(&reply).await
+    }
+
+    /// Why the auto-accept rules or the user refuse the offer, `None` if it is accepted
+    async fn refusal(&self, offer: &FileOffer) -> Option<String> {
+        let (address, name) = (self.peer.address.as_str(), self.peer.name.as_deref());
+        let ctx = OfferContext {
+            sender_address: address,
+            sender_name: name,
+            offer,
+            time: TimeOfDay::now(),
+        };
+        let decision = rules::evaluate(&self.settings.rules, &ctx);
+        match decision.action {
+            RuleAction::Accept => None,
+            RuleAction::Reject => Some(format!(
+                "Rejected by rule {:?}",
+                decision.rule.unwrap_or_default()
+            )),
+            RuleAction::Ask => {
+                let accepted = rules::ask_user(self.id, address, name, offer).await;
+                (!accepted).then(|| "Rejected by the user".to_string())
+            }
+        }
+    }
+
+    /// Checks the offer against the receive settings and creates the file its data goes to,
+    /// `None` if the file is skipped because it exists
+    async fn prepare(&self, offer: &FileOffer) -> Result<Option<IncomingFile>> {
+        let policy = self.settings.placement.clone();
+        let (id, sender) = (self.id, self.peer.display_name().to_string());
+        let (name, size) = (offer.name.clone(), offer.size);
+        blocking(move || policy.prepare(id, &sender, &name, size)).await?
+    }
+
+    async fn write(&mut self, data: Vec<u8>) -> Result<()> {
+        // Not reading while throttled makes the sender wait as well
+        if let Some(throttle) = &self.throttle {
+            throttle.acquire(data.len() as u64).await;
+        }
+        let mut file = self
+            .file
+            .take()
+            .ok_or_else(|| Error::Internal("Data without an accepted file".to_string()))?;
+        let (file, result) = blocking(move || {
+            let result = file.write(&data);
+            (file, result)
+        })
+        .await?;
+        result?;
+        if let ReceiverState::Receiving { offer, .. } = self.receiver.state() {
+            report_progress(self.id, file.written(), offer.size);
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Stores the received file and tells the peer whether it was stored
+    async fn finish(&mut self, offer: FileOffer, log: &mut TransferLog) -> Result<()> {
+        self.throttle = None;
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| Error::Internal("Done without an accepted file".to_string()))?;
+        let metadata = self.settings.metadata.clone();
+        let scanner = self.settings.scanner.as_ref().map(ScannerConfig::scanner);
+        let committed = {
+            let offer = offer.clone();
+            blocking(move || file.commit(&offer, &metadata, scanner.as_deref())).await
+        };
+        let reply = match committed.and_then(|committed| committed) {
+            Ok(Some(path)) => {
+                self.run_hook(path, offer);
+                self.receiver.stored()?
+            }
+            Ok(None) => {
+                info!("Skipped {:?}, it was stored in the meantime", offer.name);
+                log.fail(Outcome::Rejected, "File already exists");
+                self.receiver.reject("File already exists")?
+            }
+            Err(err) => {
+                let reply = self.receiver.reject(&err.to_string())?;
+                log.fail(Outcome::Failed, &err);
+                on_error(err);
+                reply
+            }
+        };
+        self.connection.write_frame(&reply).await
+    }
+
+    /// Runs the post-receive hook for a stored file. The hook may take a while, the next offer of the peer
+    /// does not wait for it.
+    fn run_hook(&self, path: PathBuf, offer: FileOffer) {
+        let Some(hook) = self.settings.post_receive_hook.clone() else {
+            return;
+        };
+        let peer = self.peer.clone();
+        spawn_guarded(
+            async move {
+                let (address, name) = (&peer.address, peer.name.as_deref());
+                if let Err(err) =
+                    run_post_receive_hook(hook, &path, address, name, &offer.sha256).await
+                {
+                    on_error(err);
+                }
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    /// Gives up on the file in progress and tells the peer
+    async fn abandon(&mut self) -> Result<()> {
+        self.file = None;
+        self.throttle = None;
+        if let Some(frame) = self.receiver.cancel() {
+            self.connection.write_frame(&frame).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends a text message, the peer hands it to its UI without asking
+pub(crate) async fn send_text<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    message: TextMessage,
+) -> Result<()> {
+    connection.write_frame(&Frame::Message(message)).await?;
+    connection.close().await
+}
+
+/// Offers the file at `path` and sends it once the peer accepts. Returns when the peer stored the file.
+pub(crate) async fn send_file<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    id: TransferId,
+    path: &Path,
+    log: &mut TransferLog,
+) -> Result<()> {
+    let result = send(connection, id, path, log).await;
+    if let Err(err) = &result {
+        log.fail(failure(connection), err);
+    }
+    result
+}
+
+async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    id: TransferId,
+    path: &Path,
+    log: &mut TransferLog,
+) -> Result<()> {
+    let offer = {
+        let path = path.to_path_buf();
+        blocking(move || offer_for(&path)).await??
+    };
+    log.add_file(&offer);
+    connection.write_frame(&Frame::Offer(offer.clone())).await?;
+    match connection.read_frame().await? {
+        Some(Frame::Accept) => {}
+        Some(frame) => return Err(refused(frame, log)),
+        None => return Err(closed("before the offer was answered")),
+    }
+    // Registered once the peer accepted, waiting for its decision takes no bandwidth
+    let throttle = ThrottleGuard::register(id);
+
+    let mut file = {
+        let path = path.to_path_buf();
+        blocking(move || File::open(path)).await??
+    };
+    let mut sent = 0;
+    while sent < offer.size {
+        let len = (offer.size - sent).min(CHUNK_LEN as u64) as usize;
+        let (returned, chunk) = blocking(move || {
+            let mut chunk = vec![0; len];
+            let result = file.read_exact(&mut chunk);
+            (file, result.map(|_| chunk))
+        })
+        .await?;
+        file = returned;
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                connection.write_frame(&Frame::Cancel).await?;
+                return Err(err.into());
+            }
+        };
+        // The receiver cancels e.g. when storing the data fails
+        if let Some(frame) = connection.read_frame().now_or_never() {
+            return Err(match frame? {
+                Some(frame) => refused(frame, log),
+                None => closed("while sending"),
+            });
+        }
+        throttle.acquire(len as u64).await;
+        connection.write_frame(&Frame::Data(chunk)).await?;
+        sent += len as u64;
+        report_progress(id, sent, offer.size);
+    }
+    connection.write_frame(&Frame::Done).await?;
+    drop(throttle);
+    match connection.read_frame().await? {
+        Some(Frame::Stored) => {}
+        // Verifying, scanning or storing the file failed on the peer
+        Some(Frame::Reject(reason)) => {
+            log.fail(Outcome::Failed, &reason);
+            return Err(Error::failed(FailureKind::PeerRejected, reason));
+        }
+        Some(frame) => return Err(refused(frame, log)),
+        None => return Err(closed("before the file was stored")),
+    }
+    connection.close().await?;
+    info!("Sent {:?}", offer.name);
+    Ok(())
+}
+
+/// Turns the answer of a peer that does not take the file into an error
+fn refused(frame: Frame, log: &mut TransferLog) -> Error {
+    match frame {
+        Frame::Reject(reason) => {
+            log.fail(Outcome::Rejected, &reason);
+            Error::failed(FailureKind::PeerRejected, reason)
+        }
+        Frame::Cancel => {
+            log.fail(Outcome::Cancelled, "Cancelled by the receiver");
+            Error::failed(FailureKind::PeerRejected, "Cancelled by the receiver")
+        }
+        frame => ProtocolError::UnexpectedFrame {
+            state: "Sending",
+            frame: frame.frame_type(),
+        }
+        .into(),
+    }
+}
+
+fn offer_for(path: &Path) -> Result<FileOffer> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| Error::Generic(format!("Not a file: {path:?}")))?;
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(FileOffer {
+        name,
+        size,
+        sha256: to_hex(&hasher.finalize()),
+        metadata: FileMetadata::read(path)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::*;
+    use crate::desktop::rt_handle;
+    use crate::desktop::transfer::protocol::{FrameType, MessageKind};
+
+    fn peer(address: &str) -> Peer {
+        Peer {
+            address: address.to_string(),
+            name: Some("Phone".to_string()),
+        }
+    }
+
+    /// Files stored in `root`, without the folders for incoming and quarantined files
+    fn received_files(root: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn settings(root: &Path) -> ReceiveSettings {
+        ReceiveSettings {
+            placement: PlacementPolicy {
+                download_root: root.to_path_buf(),
+                ..Default::default()
+            },
+            rules: vec![Rule {
+                name: "Everything".to_string(),
+                sender: None,
+                extensions: Vec::new(),
+                min_size: None,
+                max_size: None,
+                time_window: None,
+                action: RuleAction::Accept,
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Sends `path` from one end of an in-memory connection to a receiver on the other end
+    fn transfer(path: &Path, settings: &ReceiveSettings) -> (Result<()>, TransferLog, TransferLog) {
+        let (sending, receiving): (DuplexStream, DuplexStream) = duplex(64 * 1024);
+        rt_handle().block_on(async {
+            let mut sent_log = TransferLog::new(1, Direction::Sent, &peer("00:00:00:00:00:02"));
+            let mut received_log =
+                TransferLog::new(2, Direction::Received, &peer("00:00:00:00:00:01"));
+            let (sent, received) = tokio::join!(
+                async {
+                    let mut connection = Connection::new(sending);
+                    send_file(&mut connection, 1, path, &mut sent_log).await
+                },
+                async {
+                    let mut connection = Connection::new(receiving);
+                    let peer = peer("00:00:00:00:00:01");
+                    receive(&mut connection, 2, &peer, settings, &mut received_log).await
+                },
+            );
+            received.unwrap();
+            (sent, sent_log, received_log)
+        })
+    }
+
+    #[test]
+    fn sends_and_receives_a_file() {
+        let source = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let path = source.path().join("photo.jpg");
+        fs::write(&path, &data).unwrap();
+
+        let (sent, sent_log, received_log) = transfer(&path, &settings(root.path()));
+        sent.unwrap();
+
+        assert_eq!(
+            received_files(root.path()),
+            vec![root.path().join("photo.jpg")]
+        );
+        assert_eq!(fs::read(root.path().join("photo.jpg")).unwrap(), data);
+        let sent = sent_log.finish().unwrap();
+        let received = received_log.finish().unwrap();
+        assert_eq!(sent.outcome, Outcome::Completed);
+        assert_eq!(received.outcome, Outcome::Completed);
+        assert_eq!(sent.files, received.files);
+        assert_eq!(received.files[0].size, 100_000);
+    }
+
+    #[test]
+    fn reports_rejected_offers_on_both_sides() {
+        let source = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let path = source.path().join("large.bin");
+        fs::write(&path, vec![0; 2048]).unwrap();
+        let mut settings = settings(root.path());
+        settings.placement.max_file_size = Some(1024);
+
+        let (sent, sent_log, received_log) = transfer(&path, &settings);
+
+        let Err(Error::Failed { kind, .. }) = sent else {
+            panic!("Expected a rejection, got {sent:?}");
+        };
+        assert_eq!(kind, FailureKind::PeerRejected);
+        assert!(received_files(root.path()).is_empty());
+        assert_eq!(sent_log.finish().unwrap().outcome, Outcome::Rejected);
+        assert_eq!(received_log.finish().unwrap().outcome, Outcome::Rejected);
+    }
+
+    #[test]
+    fn rejects_offers_nobody_accepts() {
+        let source = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let path = source.path().join("notes.txt");
+        fs::write(&path, "notes").unwrap();
+        let mut settings = settings(root.path());
+        settings.rules.clear();
+
+        let (sent, _, received_log) = transfer(&path, &settings);
+
+        assert!(sent.is_err());
+        assert!(received_files(root.path()).is_empty());
+        let received = received_log.finish().unwrap();
+        assert_eq!(received.outcome, Outcome::Rejected);
+        assert_eq!(received.error.as_deref(), Some("Rejected by the user"));
+    }
+
+    #[test]
+    fn quarantines_flagged_files() {
+        let source = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let path = source.path().join("setup.sh");
+        fs::write(&path, "#!/bin/sh").unwrap();
+        let mut settings = settings(root.path());
+        // Exit code 1 means infected
+        settings.scanner = Some(ScannerConfig::Command {
+            command: "false".to_string(),
+            args: Vec::new(),
+            timeout_secs: 10,
+        });
+
+        let (sent, sent_log, received_log) = transfer(&path, &settings);
+
+        let Err(Error::Failed { kind, .. }) = sent else {
+            panic!("Expected a failure, got {sent:?}");
+        };
+        assert_eq!(kind, FailureKind::PeerRejected);
+        assert!(received_files(root.path()).is_empty());
+        for log in [sent_log, received_log] {
+            let record = log.finish().unwrap();
+            assert_eq!(record.outcome, Outcome::Failed);
+            assert!(record.error.unwrap().contains("quarantined"));
+        }
+    }
+
+    #[test]
+    fn reports_corrupted_files_to_the_sender() {
+        let root = tempfile::tempdir().unwrap();
+        let settings = settings(root.path());
+        let (sending, receiving) = duplex(64 * 1024);
+        let offer = FileOffer {
+            name: "notes.txt".to_string(),
+            size: 5,
+            sha256: to_hex(&Sha256::digest(b"notes")),
+            metadata: FileMetadata::default(),
+        };
+
+        let (reply, log) = rt_handle().block_on(async {
+            let peer = peer("00:00:00:00:00:01");
+            let mut log = TransferLog::new(2, Direction::Received, &peer);
+            let (reply, received) = tokio::join!(
+                async {
+                    let mut connection = Connection::new(sending);
+                    connection.write_frame(&Frame::Offer(offer)).await.unwrap();
+                    assert_eq!(connection.read_frame().await.unwrap(), Some(Frame::Accept));
+                    let data = Frame::Data(b"n0tes".to_vec());
+                    connection.write_frame(&data).await.unwrap();
+                    connection.write_frame(&Frame::Done).await.unwrap();
+                    let reply = connection.read_frame().await.unwrap();
+                    connection.close().await.unwrap();
+                    reply
+                },
+                async {
+                    let mut connection = Connection::new(receiving);
+                    receive(&mut connection, 2, &peer, &settings, &mut log).await
+                },
+            );
+            received.unwrap();
+            (reply, log)
+        });
+
+        let Some(Frame::Reject(reason)) = reply else {
+            panic!("Expected a rejection, got {reply:?}");
+        };
+        assert!(reason.contains("sha256"), "{reason}");
+        assert!(received_files(root.path()).is_empty());
+        assert_eq!(log.finish().unwrap().outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn hands_messages_over_without_storing_them() {
+        let root = tempfile::tempdir().unwrap();
+        let settings = settings(root.path());
+        let (sending, receiving) = duplex(64 * 1024);
+        let message = TextMessage::new(MessageKind::Url, "https://example.com".to_string());
+
+        let (sent, received, log) = rt_handle().block_on(async {
+            let peer = peer("00:00:00:00:00:01");
+            let mut log = TransferLog::new(2, Direction::Received, &peer);
+            let (sent, received) = tokio::join!(
+                async { send_text(&mut Connection::new(sending), message.unwrap()).await },
+                async {
+                    let mut connection = Connection::new(receiving);
+                    receive(&mut connection, 2, &peer, &settings, &mut log).await
+                },
+            );
+            (sent, received, log)
+        });
+        sent.unwrap();
+        received.unwrap();
+        assert!(received_files(root.path()).is_empty());
+        assert_eq!(log.finish(), None);
+    }
+
+    #[test]
+    fn captures_the_frames_of_a_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let capture_path = dir.path().join("transfer-1.jsonl");
+        let capture = Capture::new(File::create(&capture_path).unwrap(), 16);
+        let (sending, receiving) = duplex(64 * 1024);
+        let message = TextMessage::new(MessageKind::Text, "Hi".to_string()).unwrap();
+
+        rt_handle().block_on(async {
+            let mut connection = Connection::new(sending).with_capture(Some(capture));
+            let mut peer = Connection::new(receiving);
+            connection
+                .write_frame(&Frame::Message(message))
+                .await
+                .unwrap();
+            peer.write_frame(&Frame::Reject("No".to_string()))
+                .await
+                .unwrap();
+            assert!(connection.read_frame().await.unwrap().is_some());
+        });
+
+        let file = io::BufReader::new(File::open(&capture_path).unwrap());
+        let records = capture::read_capture(file).unwrap();
+        let frames: Vec<(capture::Direction, FrameType)> = records
+            .iter()
+            .map(|record| (record.direction, record.frame_type))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                (capture::Direction::Sent, FrameType::Message),
+                (capture::Direction::Received, FrameType::Reject),
+            ]
+        );
+    }
+
+    #[test]
+    fn connections_without_files_are_not_recorded() {
+        let log = TransferLog::new(1, Direction::Received, &peer("00:00:00:00:00:01"));
+        assert_eq!(log.finish(), None);
+    }
+}
@@ -0,0 +1,716 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::sys::jstring;
+use jni::JNIEnv;
+
+use crate::desktop::config::{app_config, update_config};
+use crate::desktop::dirs;
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::guard::jni_entry;
+
+use super::placement::free_path;
+use super::protocol::{FolderIndex, IndexEntry};
+use super::{now_millis, to_hex};
+
+static SYNC_DIR_NAME: &str = "sync";
+static INDEX_FILE_NAME: &str = "index.json";
+static PARTIAL_SUFFIX: &str = ".bft-sync";
+static STAGING_DIR_NAME: &str = "staging";
+
+/// A folder kept in sync with the same folder on a paired device
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SyncFolderConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) peer_address: String,
+}
+
+impl SyncFolderConfig {
+    /// Name the folder is known by on both devices, the name of its directory
+    pub(crate) fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Directory with the state of all sync folders (e.g. ~/.local/share/bft/sync)
+pub(crate) fn state_root() -> PathBuf {
+    dirs::data_dir().join(SYNC_DIR_NAME)
+}
+
+impl FolderIndex {
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SyncAction {
+    /// Fetch the remote version of `path`
+    Download { path: String },
+    /// The peer deleted `path`
+    Delete { path: String },
+    /// Both sides changed `path`. The newer version keeps the path, the other one gets a conflict suffix.
+    /// The local version is moved to `local_copy` if given, the remote version is stored at `remote_target`.
+    Conflict {
+        path: String,
+        local_copy: Option<String>,
+        remote_target: String,
+    },
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct SyncReport {
+    pub(crate) downloaded: usize,
+    pub(crate) deleted: usize,
+    pub(crate) conflicts: usize,
+}
+
+fn is_live(entry: Option<&IndexEntry>) -> bool {
+    entry.is_some_and(|entry| !entry.deleted)
+}
+
+fn same_content(a: Option<&IndexEntry>, b: Option<&IndexEntry>) -> bool {
+    match (is_live(a), is_live(b)) {
+        (true, true) => a.map(|a| &a.sha256) == b.map(|b| &b.sha256),
+        (false, false) => true,
+        _ => false,
+    }
+}
+
+/// `dir/name (conflict device).ext` for `dir/name.ext`
+fn conflict_name(path: &str, device: &str) -> String {
+    let device: String = device
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    let (dir, name) = path
+        .rsplit_once('/')
+        .map_or(("", path), |(dir, name)| (dir, name));
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    let name = format!("{stem} (conflict {device}){extension}");
+    if dir.is_empty() {
+        name
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Three-way comparison of both indexes with the state after the last sync with this peer.
+/// Only changes made by the peer result in actions, the peer plans the opposite direction.
+pub(crate) fn plan(
+    local: &FolderIndex,
+    remote: &FolderIndex,
+    base: &FolderIndex,
+    local_device: &str,
+    remote_device: &str,
+) -> Vec<SyncAction> {
+    let paths: BTreeSet<&String> = local.entries.keys().chain(remote.entries.keys()).collect();
+    let mut actions = Vec::new();
+
+    for path in paths {
+        let l = local.entries.get(path);
+        let r = remote.entries.get(path);
+        let b = base.entries.get(path);
+        if same_content(l, r) {
+            continue;
+        }
+        let local_changed = !same_content(l, b);
+        let remote_changed = !same_content(r, b);
+
+        let action = match (local_changed, remote_changed, is_live(l), is_live(r)) {
+            (_, false, _, _) => None,
+            (false, true, _, true) => Some(SyncAction::Download { path: path.clone() }),
+            (false, true, true, false) => Some(SyncAction::Delete { path: path.clone() }),
+            (false, true, false, false) => None,
+            // A modification wins over a deletion
+            (true, true, false, true) => Some(SyncAction::Download { path: path.clone() }),
+            (true, true, _, false) => None,
+            (true, true, true, true) => {
+                let (l, r) = (l.unwrap(), r.unwrap());
+                // Both sides have to come to the same result
+                let remote_wins = (r.mtime_nanos, &r.sha256) > (l.mtime_nanos, &l.sha256);
+                Some(if remote_wins {
+                    SyncAction::Conflict {
+                        path: path.clone(),
+                        local_copy: Some(conflict_name(path, local_device)),
+                        remote_target: path.clone(),
+                    }
+                } else {
+                    SyncAction::Conflict {
+                        path: path.clone(),
+                        local_copy: None,
+                        remote_target: conflict_name(path, remote_device),
+                    }
+                })
+            }
+        };
+        actions.extend(action);
+    }
+    actions
+}
+
+/// A synchronised folder on this device. The sync logic does not depend on the transport,
+/// files of the peer are fetched through the closure passed to [SyncFolder::apply].
+pub(crate) struct SyncFolder {
+    root: PathBuf,
+    state_dir: PathBuf,
+    device_id: String,
+}
+
+impl SyncFolder {
+    pub(crate) fn new(root: PathBuf, state_dir: PathBuf, device_id: String) -> Self {
+        Self {
+            root,
+            state_dir,
+            device_id,
+        }
+    }
+
+    /// Sync folder with its state in a subdirectory of `state_root`, see [state_root]
+    pub(crate) fn open(root: PathBuf, state_root: &Path, device_id: String) -> Self {
+        let folder_id = to_hex(&Sha256::digest(root.to_string_lossy().as_bytes()));
+        let state_dir = state_root.join(&folder_id[..16]);
+        Self::new(root, state_dir, device_id)
+    }
+
+    pub(crate) fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Where the transport keeps the files fetched from the peer until they are applied
+    pub(crate) fn staging_dir(&self) -> PathBuf {
+        self.state_dir.join(STAGING_DIR_NAME)
+    }
+
+    fn base_path(&self, peer: &str) -> PathBuf {
+        let peer: String = peer
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        self.state_dir.join(format!("base-{peer}.json"))
+    }
+
+    /// State of both folders after the last sync with `peer`
+    pub(crate) fn base(&self, peer: &str) -> Result<FolderIndex> {
+        FolderIndex::load(&self.base_path(peer))
+    }
+
+    /// Scans the folder and updates the stored index. Files missing since the last scan become tombstones,
+    /// hashes are only recalculated for files whose size or modification time changed.
+    pub(crate) fn scan(&self) -> Result<FolderIndex> {
+        let index_path = self.state_dir.join(INDEX_FILE_NAME);
+        let previous = FolderIndex::load(&index_path)?;
+        let mut index = FolderIndex::default();
+        self.scan_dir(&self.root, &previous, &mut index)?;
+
+        let deleted_at = (now_millis() as i64).saturating_mul(1_000_000);
+        for (path, entry) in previous.entries {
+            if index.entries.contains_key(&path) {
+                continue;
+            }
+            let tombstone = if entry.deleted {
+                entry
+            } else {
+                IndexEntry {
+                    size: 0,
+                    mtime_nanos: deleted_at,
+                    sha256: String::new(),
+                    deleted: true,
+                }
+            };
+            index.entries.insert(path, tombstone);
+        }
+
+        index.save(&index_path)?;
+        Ok(index)
+    }
+
+    fn scan_dir(&self, dir: &Path, previous: &FolderIndex, index: &mut FolderIndex) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            // Hidden files are skipped, this includes partial downloads
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.scan_dir(&path, previous, index)?;
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+
+            let relative = self.relative_path(&path)?;
+            let metadata = entry.metadata()?;
+            let mtime_nanos = metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .and_then(|mtime| i64::try_from(mtime.as_nanos()).ok())
+                .unwrap_or_default();
+            let sha256 = match previous.entries.get(&relative) {
+                Some(known)
+                    if !known.deleted
+                        && known.size == metadata.len()
+                        && known.mtime_nanos == mtime_nanos =>
+                {
+                    known.sha256.clone()
+                }
+                _ => hash_file(&path)?,
+            };
+            index.entries.insert(
+                relative,
+                IndexEntry {
+                    size: metadata.len(),
+                    mtime_nanos,
+                    sha256,
+                    deleted: false,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn relative_path(&self, path: &Path) -> Result<String> {
+        let relative = path
+            .strip_prefix(&self.root)
+            .map_err(|_| Error::Generic(format!("{:?} is not in {:?}", path, self.root)))?;
+        let segments: Vec<String> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        Ok(segments.join("/"))
+    }
+
+    /// Resolves a path received from the peer, which must not leave the sync folder.
+    /// Symbolic links are not followed, a link to a directory could point anywhere.
+    pub(crate) fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        let is_safe = !path.is_empty()
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !is_safe {
+            return Err(Error::Generic(format!("Invalid sync path {path:?}")));
+        }
+
+        let mut resolved = self.root.clone();
+        for component in relative.components() {
+            resolved.push(component);
+            match fs::symlink_metadata(&resolved) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    return Err(Error::Generic(format!(
+                        "Sync path {path:?} contains a symbolic link"
+                    )));
+                }
+                Ok(_) => {}
+                // Missing directories are created, so they cannot be links
+                Err(err) if err.kind() == ErrorKind::NotFound => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Applies the planned actions. `fetch` writes the remote version of a path into the given writer.
+    pub(crate) fn apply(
+        &self,
+        actions: &[SyncAction],
+        remote: &FolderIndex,
+        mut fetch: impl FnMut(&str, &mut dyn Write) -> Result<()>,
+    ) -> Result<SyncReport> {
+        // Written files get the modification time of the peer, so a cached hash could match
+        // a file which changed within the timestamp granularity. The stored index is updated instead.
+        let index_path = self.state_dir.join(INDEX_FILE_NAME);
+        let mut index = FolderIndex::load(&index_path)?;
+        let mut report = SyncReport::default();
+        let result = actions.iter().try_for_each(|action| {
+            self.apply_action(action, remote, &mut fetch, &mut index, &mut report)
+        });
+        index.save(&index_path)?;
+        result.map(|_| report)
+    }
+
+    fn apply_action(
+        &self,
+        action: &SyncAction,
+        remote: &FolderIndex,
+        fetch: &mut impl FnMut(&str, &mut dyn Write) -> Result<()>,
+        index: &mut FolderIndex,
+        report: &mut SyncReport,
+    ) -> Result<()> {
+        match action {
+            SyncAction::Download { path } => {
+                let entry = self.download(path, path, remote, fetch)?;
+                index.entries.insert(path.clone(), entry);
+                report.downloaded += 1;
+            }
+            SyncAction::Delete { path } => {
+                match fs::remove_file(self.resolve(path)?) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+                info!("Deleted {path:?}, it was deleted on the peer");
+                report.deleted += 1;
+            }
+            SyncAction::Conflict {
+                path,
+                local_copy,
+                remote_target,
+            } => {
+                if let Some(local_copy) = local_copy {
+                    let mut target = self.resolve(local_copy)?;
+                    if target.exists() {
+                        target = free_path(&target);
+                    }
+                    fs::rename(self.resolve(path)?, &target)?;
+                    if let Some(entry) = index.entries.remove(path) {
+                        index.entries.insert(self.relative_path(&target)?, entry);
+                    }
+                }
+                let entry = self.download(path, remote_target, remote, fetch)?;
+                index.entries.insert(remote_target.clone(), entry);
+                warn!("Conflicting changes of {path:?}, kept both versions");
+                report.conflicts += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn download(
+        &self,
+        path: &str,
+        target: &str,
+        remote: &FolderIndex,
+        fetch: &mut impl FnMut(&str, &mut dyn Write) -> Result<()>,
+    ) -> Result<IndexEntry> {
+        let entry = remote
+            .entries
+            .get(path)
+            .filter(|entry| !entry.deleted)
+            .ok_or_else(|| Error::Generic(format!("{path:?} is not in the remote index")))?;
+        let target = self.resolve(target)?;
+        let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(Error::Generic(format!("Invalid sync path {path:?}")));
+        };
+        fs::create_dir_all(dir)?;
+        let tmp_path = dir.join(format!(".{}{PARTIAL_SUFFIX}", name.to_string_lossy()));
+
+        let result = (|| {
+            let mut file = File::create(&tmp_path)?;
+            fetch(path, &mut file)?;
+            file.flush()?;
+            let sha256 = hash_file(&tmp_path)?;
+            if !sha256.eq_ignore_ascii_case(&entry.sha256) {
+                return Err(Error::VerificationFailed(format!(
+                    "{path:?}: expected sha256 {}, received {sha256}",
+                    entry.sha256
+                )));
+            }
+            // The peer's modification time is kept, so both indexes agree after the sync
+            let mtime = if entry.mtime_nanos >= 0 {
+                UNIX_EPOCH + Duration::from_nanos(entry.mtime_nanos as u64)
+            } else {
+                UNIX_EPOCH - Duration::from_nanos(entry.mtime_nanos.unsigned_abs())
+            };
+            file.set_modified(mtime)?;
+            fs::rename(&tmp_path, &target)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.map(|_| entry.clone())
+    }
+
+    /// Updates the common state with `peer` once both sides applied their actions. `remote` is the index
+    /// the peer sent after applying its actions, only paths that are identical on both sides are recorded.
+    /// Other paths keep their previous common state, so an interrupted round is picked up again by the next one.
+    pub(crate) fn finish(&self, peer: &str, remote: &FolderIndex) -> Result<FolderIndex> {
+        let local = self.scan()?;
+        let mut base = self.base(peer)?;
+        let paths: BTreeSet<&String> = local.entries.keys().chain(remote.entries.keys()).collect();
+        for path in paths {
+            let (l, r) = (local.entries.get(path), remote.entries.get(path));
+            if !same_content(l, r) {
+                continue;
+            }
+            match l.or(r) {
+                Some(entry) => base.entries.insert(path.clone(), entry.clone()),
+                None => base.entries.remove(path),
+            };
+        }
+        base.save(&self.base_path(peer))?;
+        Ok(base)
+    }
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_getSyncFolders<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jstring {
+    jni_entry(
+        &mut env,
+        "TransferManager::getSyncFolders",
+        ptr::null_mut(),
+        |env| {
+            info!("TransferManager::getSyncFolders()");
+
+            let json = serde_json::to_string(&app_config().lock().unwrap().sync_folders)
+                .expect("Serializing sync folders should not fail");
+            env.new_string(json)
+                .map(|json| json.into_raw())
+                .unwrap_or(std::ptr::null_mut())
+        },
+    )
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setSyncFolders<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    sync_folders: JString<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::setSyncFolders", (), |env| {
+        info!("TransferManager::setSyncFolders()");
+
+        let sync_folders: String = env
+            .get_string(&sync_folders)
+            .expect("Getting String from env should not fail")
+            .into();
+        let sync_folders: Vec<SyncFolderConfig> = match serde_json::from_str(&sync_folders) {
+            Ok(sync_folders) => sync_folders,
+            Err(err) => {
+                on_error(Error::Generic(format!("Invalid sync folders: {err}")));
+                return;
+            }
+        };
+        update_config(|config| config.sync_folders = sync_folders)
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::desktop::error::FailureKind;
+
+    use super::*;
+
+    struct Device {
+        folder: SyncFolder,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Device {
+        fn new(id: &str) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let folder = SyncFolder::new(
+                dir.path().join("folder"),
+                dir.path().join("state"),
+                id.to_string(),
+            );
+            fs::create_dir_all(&folder.root).unwrap();
+            Self { folder, _dir: dir }
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            let path = self.folder.root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn read(&self, path: &str) -> Option<String> {
+            fs::read_to_string(self.folder.root.join(path)).ok()
+        }
+
+        /// Contents by path, standing in for the transport
+        fn contents(&self, index: &FolderIndex) -> HashMap<String, Vec<u8>> {
+            index
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.deleted)
+                .map(|(path, _)| (path.clone(), fs::read(self.folder.root.join(path)).unwrap()))
+                .collect()
+        }
+    }
+
+    fn sync(a: &Device, b: &Device) -> (SyncReport, SyncReport) {
+        let (index_a, index_b) = (a.folder.scan().unwrap(), b.folder.scan().unwrap());
+        let (contents_a, contents_b) = (a.contents(&index_a), b.contents(&index_b));
+        let plan_a = plan(&index_a, &index_b, &a.folder.base("b").unwrap(), "a", "b");
+        let plan_b = plan(&index_b, &index_a, &b.folder.base("a").unwrap(), "b", "a");
+
+        let report_a = a
+            .folder
+            .apply(&plan_a, &index_b, |path, out| {
+                Ok(out.write_all(&contents_b[path])?)
+            })
+            .unwrap();
+        let report_b = b
+            .folder
+            .apply(&plan_b, &index_a, |path, out| {
+                Ok(out.write_all(&contents_a[path])?)
+            })
+            .unwrap();
+        finish(a, b);
+        (report_a, report_b)
+    }
+
+    /// Both sides confirm their state after applying the actions
+    fn finish(a: &Device, b: &Device) {
+        let (index_a, index_b) = (a.folder.scan().unwrap(), b.folder.scan().unwrap());
+        a.folder.finish("b", &index_b).unwrap();
+        b.folder.finish("a", &index_a).unwrap();
+    }
+
+    fn report(downloaded: usize, deleted: usize, conflicts: usize) -> SyncReport {
+        SyncReport {
+            downloaded,
+            deleted,
+            conflicts,
+        }
+    }
+
+    #[test]
+    fn propagates_changes_and_deletions() {
+        let (a, b) = (Device::new("a"), Device::new("b"));
+        a.write("notes.txt", "a");
+        a.write("photos/1.jpg", "jpg");
+        b.write("todo.txt", "b");
+
+        assert_eq!(sync(&a, &b), (report(1, 0, 0), report(2, 0, 0)));
+        assert_eq!(b.read("photos/1.jpg").as_deref(), Some("jpg"));
+        assert_eq!(a.read("todo.txt").as_deref(), Some("b"));
+        assert_eq!(sync(&a, &b), (report(0, 0, 0), report(0, 0, 0)));
+
+        fs::remove_file(b.folder.root.join("notes.txt")).unwrap();
+        b.write("todo.txt", "b2");
+        assert_eq!(sync(&a, &b), (report(1, 1, 0), report(0, 0, 0)));
+        assert_eq!(a.read("notes.txt"), None);
+        assert_eq!(a.read("todo.txt").as_deref(), Some("b2"));
+
+        // A modification wins over a deletion
+        fs::remove_file(a.folder.root.join("todo.txt")).unwrap();
+        b.write("todo.txt", "b3");
+        assert_eq!(sync(&a, &b), (report(1, 0, 0), report(0, 0, 0)));
+        assert_eq!(a.read("todo.txt").as_deref(), Some("b3"));
+    }
+
+    #[test]
+    fn keeps_both_versions_on_conflict() {
+        let (a, b) = (Device::new("a"), Device::new("b"));
+        a.write("doc.txt", "base");
+        sync(&a, &b);
+
+        a.write("doc.txt", "from a");
+        b.write("doc.txt", "from b");
+        assert_eq!(sync(&a, &b), (report(0, 0, 1), report(0, 0, 1)));
+
+        let index_a = a.folder.scan().unwrap();
+        let index_b = b.folder.scan().unwrap();
+        assert_eq!(index_a.entries.len(), 2);
+        assert!(index_a
+            .entries
+            .iter()
+            .all(|(path, entry)| index_b.entries[path].sha256 == entry.sha256));
+        let mut versions = vec![a.read("doc.txt").unwrap(), {
+            let copy = index_a
+                .entries
+                .keys()
+                .find(|path| path.contains("conflict"));
+            a.read(copy.unwrap()).unwrap()
+        }];
+        versions.sort();
+        assert_eq!(versions, vec!["from a", "from b"]);
+        assert_eq!(sync(&a, &b), (report(0, 0, 0), report(0, 0, 0)));
+    }
+
+    #[test]
+    fn interrupted_round_loses_no_files() {
+        let (a, b) = (Device::new("a"), Device::new("b"));
+        a.write("keep.txt", "a");
+        a.write("photo.jpg", "jpg");
+
+        // The connection drops while b fetches the files of a
+        let (index_a, index_b) = (a.folder.scan().unwrap(), b.folder.scan().unwrap());
+        let plan_b = plan(&index_b, &index_a, &b.folder.base("a").unwrap(), "b", "a");
+        let contents_a = a.contents(&index_a);
+        let mut fetched = 0;
+        let result = b.folder.apply(&plan_b, &index_a, |path, out| {
+            fetched += 1;
+            if fetched > 1 {
+                return Err(Error::failed(FailureKind::Io, "Connection reset"));
+            }
+            Ok(out.write_all(&contents_a[path])?)
+        });
+        assert!(result.is_err());
+        finish(&a, &b);
+        let base = a.folder.base("b").unwrap();
+        assert_eq!(base.entries.keys().collect::<Vec<_>>(), vec!["keep.txt"]);
+
+        // The missing file is not mistaken for a deletion on b
+        assert_eq!(sync(&a, &b), (report(0, 0, 0), report(1, 0, 0)));
+        assert_eq!(a.read("photo.jpg").as_deref(), Some("jpg"));
+        assert_eq!(b.read("photo.jpg").as_deref(), Some("jpg"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_folder() {
+        let a = Device::new("a");
+        assert!(a.folder.resolve("../etc/passwd").is_err());
+        assert!(a.folder.resolve("/etc/passwd").is_err());
+        assert!(a.folder.resolve("dir/file.txt").is_ok());
+
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), a.folder.root.join("link")).unwrap();
+        assert!(a.folder.resolve("link/file.txt").is_err());
+        assert!(a.folder.resolve("link").is_err());
+        assert_eq!(
+            conflict_name("dir/a.txt", "AA:BB"),
+            "dir/a (conflict AA-BB).txt"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::desktop::error::{Error, FailureKind, Result};
+
+use super::protocol::{FetchRequest, FolderIndex, Frame, ProtocolError, SyncIndex};
+use super::session::{blocking, closed, Connection, CHUNK_LEN};
+use super::sync::{plan, SyncAction, SyncFolder, SyncFolderConfig, SyncReport};
+use super::throttle::ThrottleGuard;
+use super::TransferId;
+
+lazy_static! {
+    /// Roots of the folders a round is running for, one round per folder at a time
+    static ref SYNCING: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/// Marks a folder as syncing until it is dropped
+struct SyncGuard(PathBuf);
+
+impl SyncGuard {
+    fn acquire(root: &Path) -> Option<Self> {
+        let mut syncing = SYNCING.lock().unwrap();
+        syncing
+            .insert(root.to_path_buf())
+            .then(|| Self(root.to_path_buf()))
+    }
+}
+
+impl Drop for SyncGuard {
+    fn drop(&mut self) {
+        SYNCING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Where the folders of both devices are, the device ids are the Bluetooth addresses
+pub(crate) struct SyncContext<'a> {
+    /// Id of the connection the round runs on, file data is throttled under it
+    pub(crate) transfer_id: TransferId,
+    pub(crate) state_root: &'a Path,
+    pub(crate) local_device: &'a str,
+    pub(crate) peer_device: &'a str,
+}
+
+/// Syncs `config` with the device on the other end of `connection`, which runs [answer_sync].
+///
+/// Both devices plan with the indexes they exchange at the start. The device that started the round
+/// fetches and applies its changes first, then serves the fetches of the peer. Each side sends its
+/// index again once its changes are applied, both record the paths that agree as their common state.
+pub(crate) async fn start_sync<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    config: &SyncFolderConfig,
+    context: &SyncContext<'_>,
+) -> Result<SyncReport> {
+    let Some(_guard) = SyncGuard::acquire(&config.path) else {
+        return Err(Error::failed(
+            FailureKind::InProgress,
+            format!("{:?} is already syncing", config.name()),
+        ));
+    };
+    let mut round = Round::new(connection, config, context);
+    let local = round.scan().await?;
+    round.send_index(&local).await?;
+    let remote = round.receive_index().await?;
+    let actions = round.plan(&local, &remote).await?;
+    let staged = round.fetch(&actions, &remote).await?;
+    let report = round.apply(actions, remote, staged).await?;
+
+    let local = round.scan().await?;
+    round.send_index(&local).await?;
+    let remote = round.serve(&local).await?;
+    round.finish(remote).await?;
+    round.connection.close().await?;
+    Ok(report)
+}
+
+/// Runs the round the peer started with `request`, the first part of its index, see [start_sync].
+/// The peer gets a `Reject` if none of `configs` is the folder of that name synced with it,
+/// or a round for the folder is already running.
+pub(crate) async fn answer_sync<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    request: SyncIndex,
+    configs: &[SyncFolderConfig],
+    context: &SyncContext<'_>,
+) -> Result<Option<SyncReport>> {
+    let request = complete_index(connection, request).await?;
+    let config = configs.iter().find(|config| {
+        config.name() == request.folder
+            && config
+                .peer_address
+                .eq_ignore_ascii_case(context.peer_device)
+    });
+    let Some(config) = config else {
+        let reason = format!("{:?} is not synced with this device", request.folder);
+        connection.write_frame(&Frame::Reject(reason)).await?;
+        return Ok(None);
+    };
+    let Some(_guard) = SyncGuard::acquire(&config.path) else {
+        let reason = format!("{:?} is already syncing", request.folder);
+        connection.write_frame(&Frame::Reject(reason)).await?;
+        return Ok(None);
+    };
+    let mut round = Round::new(connection, config, context);
+    let local = round.scan().await?;
+    round.send_index(&local).await?;
+    let actions = round.plan(&local, &request.index).await?;
+    let remote = round.serve(&local).await?;
+
+    let staged = round.fetch(&actions, &request.index).await?;
+    let report = round.apply(actions, request.index, staged).await?;
+    let local = round.scan().await?;
+    round.send_index(&local).await?;
+    round.finish(remote).await?;
+    Ok(Some(report))
+}
+
+/// One device's side of a sync round
+struct Round<'a, S> {
+    connection: &'a mut Connection<S>,
+    id: TransferId,
+    folder: Arc<SyncFolder>,
+    name: String,
+    peer: String,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Round<'a, S> {
+    fn new(
+        connection: &'a mut Connection<S>,
+        config: &SyncFolderConfig,
+        context: &SyncContext<'_>,
+    ) -> Self {
+        let folder = SyncFolder::open(
+            config.path.clone(),
+            context.state_root,
+            context.local_device.to_string(),
+        );
+        Self {
+            connection,
+            id: context.transfer_id,
+            folder: Arc::new(folder),
+            name: config.name(),
+            peer: context.peer_device.to_string(),
+        }
+    }
+
+    async fn scan(&self) -> Result<FolderIndex> {
+        let folder = self.folder.clone();
+        blocking(move || folder.scan()).await?
+    }
+
+    async fn plan(&self, local: &FolderIndex, remote: &FolderIndex) -> Result<Vec<SyncAction>> {
+        let (folder, peer) = (self.folder.clone(), self.peer.clone());
+        let base = blocking(move || folder.base(&peer)).await??;
+        Ok(plan(
+            local,
+            remote,
+            &base,
+            self.folder.device_id(),
+            &self.peer,
+        ))
+    }
+
+    async fn send_index(&mut self, index: &FolderIndex) -> Result<()> {
+        for part in SyncIndex::parts(&self.name, index) {
+            self.connection.write_frame(&Frame::SyncIndex(part)).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive_index(&mut self) -> Result<FolderIndex> {
+        match self.connection.read_frame().await? {
+            Some(Frame::SyncIndex(part)) if part.folder == self.name => {
+                Ok(complete_index(self.connection, part).await?.index)
+            }
+            Some(frame) => Err(unexpected(frame)),
+            None => Err(closed("during the sync round")),
+        }
+    }
+
+    /// Answers the fetches of the peer until it sends its index after applying its actions
+    async fn serve(&mut self, local: &FolderIndex) -> Result<FolderIndex> {
+        loop {
+            match self.connection.read_frame().await? {
+                Some(Frame::Fetch(request)) => self.serve_fetch(request, local).await?,
+                Some(Frame::SyncIndex(part)) if part.folder == self.name => {
+                    return Ok(complete_index(self.connection, part).await?.index)
+                }
+                Some(frame) => return Err(unexpected(frame)),
+                None => return Err(closed("during the sync round")),
+            }
+        }
+    }
+
+    async fn serve_fetch(&mut self, request: FetchRequest, local: &FolderIndex) -> Result<()> {
+        let has_content = |path: &String| {
+            local
+                .entries
+                .get(path)
+                .is_some_and(|entry| !entry.deleted && entry.sha256 == request.sha256)
+        };
+        // A conflicting version was moved aside when this device applied its actions
+        let path = Some(&request.path)
+            .filter(|path| has_content(path))
+            .or_else(|| local.entries.keys().find(|path| has_content(path)))
+            .cloned();
+        let folder = self.folder.clone();
+        let file = blocking(move || {
+            let path = path.ok_or_else(|| Error::Generic("The content changed".to_string()))?;
+            Ok::<_, Error>(File::open(folder.resolve(&path)?)?)
+        })
+        .await?;
+        let mut file = match file {
+            Ok(file) => tokio::fs::File::from_std(file),
+            Err(err) => {
+                let reason = format!("{:?} is not available: {err}", request.path);
+                return self.connection.write_frame(&Frame::Reject(reason)).await;
+            }
+        };
+
+        let throttle = ThrottleGuard::register(self.id);
+        let mut chunk = vec![0; CHUNK_LEN];
+        loop {
+            let len = file.read(&mut chunk).await?;
+            if len == 0 {
+                break;
+            }
+            throttle.acquire(len as u64).await;
+            let frame = Frame::Data(chunk[..len].to_vec());
+            self.connection.write_frame(&frame).await?;
+        }
+        self.connection.write_frame(&Frame::Done).await
+    }
+
+    /// Fetches the remote versions the actions need into the staging directory, by path
+    async fn fetch(
+        &mut self,
+        actions: &[SyncAction],
+        remote: &FolderIndex,
+    ) -> Result<HashMap<String, PathBuf>> {
+        let staging = self.folder.staging_dir();
+        // Left over from an interrupted round
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        tokio::fs::create_dir_all(&staging).await?;
+
+        let mut staged = HashMap::new();
+        for action in actions {
+            let path = match action {
+                SyncAction::Download { path } | SyncAction::Conflict { path, .. } => path,
+                SyncAction::Delete { .. } => continue,
+            };
+            // Applying the action reports the missing entry
+            let Some(entry) = remote.entries.get(path) else {
+                continue;
+            };
+            let request = FetchRequest {
+                path: path.clone(),
+                sha256: entry.sha256.clone(),
+            };
+            self.connection.write_frame(&Frame::Fetch(request)).await?;
+
+            let target = staging.join(staged.len().to_string());
+            let mut file = tokio::fs::File::create(&target).await?;
+            // Not reading while throttled makes the peer wait as well
+            let throttle = ThrottleGuard::register(self.id);
+            loop {
+                match self.connection.read_frame().await? {
+                    Some(Frame::Data(data)) => {
+                        throttle.acquire(data.len() as u64).await;
+                        file.write_all(&data).await?
+                    }
+                    Some(Frame::Done) => break,
+                    Some(frame) => return Err(unexpected(frame)),
+                    None => return Err(closed("during the sync round")),
+                }
+            }
+            file.flush().await?;
+            staged.insert(path.clone(), target);
+        }
+        Ok(staged)
+    }
+
+    async fn apply(
+        &self,
+        actions: Vec<SyncAction>,
+        remote: FolderIndex,
+        staged: HashMap<String, PathBuf>,
+    ) -> Result<SyncReport> {
+        let folder = self.folder.clone();
+        blocking(move || {
+            let report = folder.apply(&actions, &remote, |path, out| {
+                let staged = staged
+                    .get(path)
+                    .ok_or_else(|| Error::Generic(format!("{path:?} was not fetched")))?;
+                io::copy(&mut File::open(staged)?, out)?;
+                Ok(())
+            });
+            let _ = fs::remove_dir_all(folder.staging_dir());
+            report
+        })
+        .await?
+    }
+
+    async fn finish(&self, remote: FolderIndex) -> Result<()> {
+        let (folder, peer) = (self.folder.clone(), self.peer.clone());
+        blocking(move || folder.finish(&peer, &remote)).await??;
+        Ok(())
+    }
+}
+
+/// Reads the remaining parts of an index whose `first` part was received
+async fn complete_index<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    first: SyncIndex,
+) -> Result<SyncIndex> {
+    let mut index = first;
+    while index.more {
+        match connection.read_frame().await? {
+            Some(Frame::SyncIndex(part)) if part.folder == index.folder => {
+                index.index.entries.extend(part.index.entries);
+                index.more = part.more;
+            }
+            Some(frame) => return Err(unexpected(frame)),
+            None => return Err(closed("while receiving the index")),
+        }
+    }
+    Ok(index)
+}
+
+fn unexpected(frame: Frame) -> Error {
+    match frame {
+        Frame::Reject(reason) => Error::failed(FailureKind::PeerRejected, reason),
+        frame => ProtocolError::UnexpectedFrame {
+            state: "Syncing",
+            frame: frame.frame_type(),
+        }
+        .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use crate::desktop::rt_handle;
+    use crate::desktop::transfer::history::Direction;
+    use crate::desktop::transfer::session::{receive, Peer, ReceiveSettings, TransferLog};
+
+    use super::*;
+
+    const A: &str = "00:00:00:00:00:0A";
+    const B: &str = "00:00:00:00:00:0B";
+
+    /// Device with a sync folder named "Shared" synced with the other device
+    struct Device {
+        config: SyncFolderConfig,
+        state_root: PathBuf,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Device {
+        fn new(peer: &str) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let config = SyncFolderConfig {
+                path: dir.path().join("Shared"),
+                peer_address: peer.to_string(),
+            };
+            fs::create_dir_all(&config.path).unwrap();
+            Self {
+                config,
+                state_root: dir.path().join("state"),
+                _dir: dir,
+            }
+        }
+
+        fn write(&self, path: &str, content: &str) {
+            fs::write(self.config.path.join(path), content).unwrap();
+        }
+
+        fn contents(&self) -> Vec<(String, String)> {
+            let mut contents: Vec<(String, String)> = fs::read_dir(&self.config.path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .map(|path| {
+                    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                    (name, fs::read_to_string(path).unwrap())
+                })
+                .collect();
+            contents.sort();
+            contents
+        }
+    }
+
+    /// Round started by `a`, `b` answers it from its receive session
+    fn sync(a: &Device, b: &Device) -> Result<SyncReport> {
+        let (starting, answering) = duplex(64 * 1024);
+        rt_handle().block_on(async {
+            let settings = ReceiveSettings {
+                sync_folders: vec![b.config.clone()],
+                sync_state_root: b.state_root.clone(),
+                local_device: B.to_string(),
+                ..Default::default()
+            };
+            let peer = Peer {
+                address: A.to_string(),
+                name: None,
+            };
+            let mut log = TransferLog::new(2, Direction::Received, &peer);
+            let (report, answered) = tokio::join!(
+                async {
+                    let context = SyncContext {
+                        transfer_id: 1,
+                        state_root: &a.state_root,
+                        local_device: A,
+                        peer_device: B,
+                    };
+                    let mut connection = Connection::new(starting);
+                    start_sync(&mut connection, &a.config, &context).await
+                },
+                async {
+                    let mut connection = Connection::new(answering);
+                    receive(&mut connection, 2, &peer, &settings, &mut log).await
+                },
+            );
+            answered.unwrap();
+            assert!(log.finish().is_none());
+            report
+        })
+    }
+
+    #[test]
+    fn syncs_both_folders_in_one_round() {
+        let (a, b) = (Device::new(B), Device::new(A));
+        a.write("notes.txt", "from a");
+        b.write("photo.jpg", "from b");
+        b.write("doc.txt", "base");
+        sync(&a, &b).unwrap();
+        assert_eq!(a.contents(), b.contents());
+        assert_eq!(a.contents().len(), 3);
+
+        a.write("doc.txt", "changed on a");
+        b.write("doc.txt", "changed on b");
+        fs::remove_file(b.config.path.join("photo.jpg")).unwrap();
+        let report = sync(&a, &b).unwrap();
+        assert_eq!(report.deleted, 1);
+        assert_eq!(report.conflicts, 1);
+
+        let contents = a.contents();
+        assert_eq!(contents, b.contents());
+        let mut versions: Vec<&str> = contents
+            .iter()
+            .filter(|(name, _)| name.starts_with("doc"))
+            .map(|(_, content)| content.as_str())
+            .collect();
+        versions.sort();
+        assert_eq!(versions, vec!["changed on a", "changed on b"]);
+        assert_eq!(sync(&a, &b).unwrap(), SyncReport::default());
+    }
+
+    #[test]
+    fn exchanges_indexes_larger_than_a_frame() {
+        let (a, b) = (Device::new(B), Device::new(A));
+        // About 300 bytes of index per file
+        for n in 0..5000 {
+            let name = format!("{n:04}-{}.txt", "x".repeat(200));
+            a.write(&name, "same");
+            b.write(&name, "same");
+        }
+        a.write("notes.txt", "from a");
+
+        let report = sync(&a, &b).unwrap();
+        assert_eq!(report.downloaded, 0);
+        assert_eq!(
+            fs::read_to_string(b.config.path.join("notes.txt")).unwrap(),
+            "from a"
+        );
+        assert_eq!(sync(&a, &b).unwrap(), SyncReport::default());
+    }
+
+    #[test]
+    fn rejects_folders_not_synced_with_the_peer() {
+        let (a, b) = (Device::new(B), Device::new("00:00:00:00:00:0C"));
+        a.write("notes.txt", "from a");
+
+        let err = sync(&a, &b).unwrap_err();
+        assert!(err.to_string().contains("not synced"), "{err}");
+        assert!(b.contents().is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lazy_static::lazy_static;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info};
+
+use jni::objects::JClass;
+use jni::sys::jlong;
+use jni::JNIEnv;
+
+use crate::desktop::guard::jni_entry;
+
+use super::TransferId;
+
+/// Rate in bytes per second, `0` means unlimited
+pub(crate) type Rate = u64;
+
+/// Token bucket which may go into debt: a request is granted as soon as the bucket is not empty,
+/// and the following requests have to wait until the debt is paid off.
+/// This allows chunks larger than the burst size without ever blocking forever.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    pub(crate) fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    pub(crate) fn set_rate(&mut self, rate: Rate, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.last_refill = now;
+        // Allow bursts of at most one second worth of data
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+    }
+
+    /// Time until the bucket can grant the next request
+    pub(crate) fn wait_time(&mut self, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+
+    pub(crate) fn take(&mut self, bytes: u64) {
+        if self.rate != 0 {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    bucket: TokenBucket,
+    cap: Option<Rate>,
+}
+
+/// Bandwidth limiter shared by all active transfers.
+/// Every transfer is limited by its own cap and by a fair share of the global cap,
+/// while the global bucket makes sure the sum of all transfers never exceeds the global cap.
+#[derive(Debug)]
+pub(crate) struct BandwidthLimiter {
+    global: TokenBucket,
+    per_transfer: Rate,
+    sessions: HashMap<TransferId, Session>,
+}
+
+impl BandwidthLimiter {
+    pub(crate) fn new(global: Rate, per_transfer: Rate) -> Self {
+        Self {
+            global: TokenBucket::new(global, Instant::now()),
+            per_transfer,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn set_limits(&mut self, global: Rate, per_transfer: Rate) {
+        self.global.set_rate(global, Instant::now());
+        self.per_transfer = per_transfer;
+        self.rebalance();
+    }
+
+    /// Overrides the per-transfer cap of a single transfer, `None` restores the default cap
+    pub(crate) fn set_transfer_limit(&mut self, id: TransferId, cap: Option<Rate>) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.cap = cap;
+            self.rebalance();
+        }
+    }
+
+    pub(crate) fn register(&mut self, id: TransferId) {
+        self.sessions.insert(
+            id,
+            Session {
+                bucket: TokenBucket::new(0, Instant::now()),
+                cap: None,
+            },
+        );
+        self.rebalance();
+    }
+
+    pub(crate) fn unregister(&mut self, id: TransferId) {
+        if self.sessions.remove(&id).is_some() {
+            self.rebalance();
+        }
+    }
+
+    /// Effective rates of all transfers. The global cap is split fairly, but bandwidth a transfer cannot use
+    /// because of its own cap goes to the other transfers.
+    pub(crate) fn session_rates(&self) -> Vec<(TransferId, Rate)> {
+        let mut caps: Vec<(TransferId, Rate)> = self
+            .sessions
+            .iter()
+            .map(|(id, session)| (*id, session.cap.unwrap_or(self.per_transfer)))
+            .collect();
+        let global = self.global.rate();
+        if global == 0 {
+            return caps;
+        }
+
+        // Transfers with the lowest caps are served first, the rest shares what remains
+        caps.sort_by_key(|(id, cap)| (*cap == 0, *cap, *id));
+        let mut remaining = global;
+        let mut rates = Vec::with_capacity(caps.len());
+        for (index, (id, cap)) in caps.iter().enumerate() {
+            let share = remaining / (caps.len() - index) as u64;
+            let rate = if *cap == 0 { share } else { share.min(*cap) };
+            remaining -= rate;
+            rates.push((*id, rate));
+        }
+        rates
+    }
+
+    fn rebalance(&mut self) {
+        let now = Instant::now();
+        for (id, rate) in self.session_rates() {
+            if let Some(session) = self.sessions.get_mut(&id) {
+                session.bucket.set_rate(rate, now);
+            }
+        }
+        debug!(
+            "Rebalanced bandwidth of {} transfers (global: {} B/s)",
+            self.sessions.len(),
+            self.global.rate()
+        );
+    }
+
+    /// Grants `bytes` to the transfer if possible, otherwise returns how long to wait before retrying
+    fn try_acquire(&mut self, id: TransferId, bytes: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let session_wait = self
+            .sessions
+            .get_mut(&id)
+            .map_or(Duration::ZERO, |session| session.bucket.wait_time(now));
+        let wait = session_wait.max(self.global.wait_time(now));
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+
+        self.global.take(bytes);
+        if let Some(session) = self.sessions.get_mut(&id) {
+            session.bucket.take(bytes);
+        }
+        None
+    }
+}
+
+lazy_static! {
+    static ref BANDWIDTH_LIMITER: Mutex<BandwidthLimiter> = Mutex::new(BandwidthLimiter::new(0, 0));
+}
+
+/// Registers a transfer with the global limiter for as long as the guard lives
+pub(crate) struct ThrottleGuard {
+    id: TransferId,
+}
+
+impl ThrottleGuard {
+    pub(crate) fn register(id: TransferId) -> Self {
+        BANDWIDTH_LIMITER.lock().unwrap().register(id);
+        debug!(transfer_id = id, "Registered with the bandwidth limiter");
+        Self { id }
+    }
+
+    /// Waits until the transfer is allowed to send or receive another `bytes` bytes
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        loop {
+            let wait = BANDWIDTH_LIMITER
+                .lock()
+                .unwrap()
+                .try_acquire(self.id, bytes);
+            match wait {
+                Some(wait) => sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+}
+
+impl Drop for ThrottleGuard {
+    fn drop(&mut self) {
+        BANDWIDTH_LIMITER.lock().unwrap().unregister(self.id);
+        debug!(
+            transfer_id = self.id,
+            "Unregistered from the bandwidth limiter"
+        );
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setBandwidthLimit<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    global_bytes_per_sec: jlong,
+    per_transfer_bytes_per_sec: jlong,
+) {
+    jni_entry(&mut env, "TransferManager::setBandwidthLimit", (), |_env| {
+        info!(
+            "TransferManager::setBandwidthLimit({global_bytes_per_sec}, {per_transfer_bytes_per_sec})"
+        );
+
+        BANDWIDTH_LIMITER.lock().unwrap().set_limits(
+            global_bytes_per_sec.max(0) as Rate,
+            per_transfer_bytes_per_sec.max(0) as Rate,
+        );
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setTransferBandwidthLimit<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    transfer_id: jlong,
+    bytes_per_sec: jlong,
+) {
+    jni_entry(
+        &mut env,
+        "TransferManager::setTransferBandwidthLimit",
+        (),
+        |_env| {
+            info!("TransferManager::setTransferBandwidthLimit({transfer_id}, {bytes_per_sec})");
+
+            let cap = (bytes_per_sec >= 0).then_some(bytes_per_sec as Rate);
+            BANDWIDTH_LIMITER
+                .lock()
+                .unwrap()
+                .set_transfer_limit(transfer_id as TransferId, cap);
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_goes_into_debt_and_recovers() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+
+        assert_eq!(bucket.wait_time(start), Duration::ZERO);
+        bucket.take(3000);
+        assert_eq!(bucket.wait_time(start), Duration::from_secs(2));
+        assert_eq!(
+            bucket.wait_time(start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn global_cap_is_shared_fairly() {
+        let rates = |limiter: &BandwidthLimiter| {
+            let mut rates = limiter.session_rates();
+            rates.sort();
+            rates
+        };
+        let mut limiter = BandwidthLimiter::new(1000, 0);
+        limiter.register(1);
+        assert_eq!(rates(&limiter), vec![(1, 1000)]);
+
+        limiter.register(2);
+        assert_eq!(rates(&limiter), vec![(1, 500), (2, 500)]);
+
+        // Bandwidth a capped transfer cannot use goes to the others
+        limiter.register(3);
+        limiter.set_transfer_limit(1, Some(100));
+        assert_eq!(rates(&limiter), vec![(1, 100), (2, 450), (3, 450)]);
+        limiter.set_transfer_limit(2, Some(600));
+        assert_eq!(rates(&limiter), vec![(1, 100), (2, 450), (3, 450)]);
+        limiter.set_transfer_limit(3, Some(200));
+        assert_eq!(rates(&limiter), vec![(1, 100), (2, 600), (3, 200)]);
+
+        limiter.unregister(1);
+        limiter.set_limits(0, 300);
+        limiter.set_transfer_limit(2, None);
+        assert_eq!(rates(&limiter), vec![(2, 300), (3, 200)]);
+        limiter.set_transfer_limit(3, Some(0));
+        assert_eq!(rates(&limiter), vec![(2, 300), (3, 0)]);
+    }
+}
@@ -0,0 +1,598 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use bluer::Address;
+use inotify::{EventMask, Inotify, WatchMask};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+use jni::objects::{JClass, JString};
+use jni::JNIEnv;
+
+use crate::desktop::config::{app_config, update_config};
+use crate::desktop::error::{on_error, Error, Result};
+use crate::desktop::events::{self, Event};
+use crate::desktop::guard::{jni_entry, spawn_guarded};
+
+use super::placement::free_path;
+use super::rfcomm;
+use super::session::blocking;
+
+static SENT_FOLDER: &str = "sent";
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Delay before a file whose transfer failed is sent again, doubled with every further failure
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+
+/// Folder whose new files are sent to `target_address` automatically
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct WatchFolderConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) target_address: String,
+    /// Time without filesystem events before a file is looked at
+    #[serde(default = "default_debounce_ms")]
+    pub(crate) debounce_ms: u64,
+    /// Time size and modification time of a file have to stay unchanged before it is queued
+    #[serde(default = "default_stable_ms")]
+    pub(crate) stable_ms: u64,
+}
+
+fn default_debounce_ms() -> u64 {
+    500
+}
+
+fn default_stable_ms() -> u64 {
+    2000
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WatchStatus {
+    Detected,
+    Queued,
+    Sent,
+    Failed,
+}
+
+impl WatchStatus {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Detected => "Detected",
+            Self::Queued => "Queued",
+            Self::Sent => "Sent",
+            Self::Failed => "Failed",
+        }
+    }
+}
+
+type Snapshot = (u64, SystemTime);
+
+struct Candidate {
+    last_event: Instant,
+    snapshot: Option<Snapshot>,
+    stable_since: Instant,
+    /// Files being retried are not looked at before
+    not_before: Instant,
+}
+
+/// Debounces filesystem events and reports files once they stopped changing
+pub(crate) struct StabilityTracker {
+    debounce: Duration,
+    stable: Duration,
+    candidates: HashMap<PathBuf, Candidate>,
+}
+
+impl StabilityTracker {
+    pub(crate) fn new(debounce: Duration, stable: Duration) -> Self {
+        Self {
+            debounce,
+            stable,
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Records an event for `path`, returns whether the file was not tracked before
+    pub(crate) fn touch(&mut self, path: PathBuf, now: Instant) -> bool {
+        match self.candidates.get_mut(&path) {
+            Some(candidate) => {
+                candidate.last_event = now;
+                false
+            }
+            None => {
+                self.candidates.insert(
+                    path,
+                    Candidate {
+                        last_event: now,
+                        snapshot: None,
+                        stable_since: now,
+                        not_before: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Tracks `path` again after sending it failed, it is not reported before `delay` has passed
+    pub(crate) fn retry(&mut self, path: PathBuf, delay: Duration, now: Instant) {
+        self.candidates.insert(
+            path,
+            Candidate {
+                last_event: now,
+                snapshot: None,
+                stable_since: now,
+                not_before: now + delay,
+            },
+        );
+    }
+
+    /// Returns the files which are stable now. `stat` returns size and modification time
+    /// of a regular file, files for which it returns `None` are dropped.
+    pub(crate) fn poll(
+        &mut self,
+        now: Instant,
+        stat: impl Fn(&Path) -> Option<Snapshot>,
+    ) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        self.candidates.retain(|path, candidate| {
+            if now < candidate.not_before
+                || now.duration_since(candidate.last_event) < self.debounce
+            {
+                return true;
+            }
+            let Some(snapshot) = stat(path) else {
+                return false;
+            };
+            if candidate.snapshot != Some(snapshot) {
+                candidate.snapshot = Some(snapshot);
+                candidate.stable_since = now;
+                return true;
+            }
+            if now.duration_since(candidate.stable_since) < self.stable {
+                return true;
+            }
+            ready.push(path.clone());
+            false
+        });
+        ready.sort();
+        ready
+    }
+}
+
+fn stat_file(path: &Path) -> Option<Snapshot> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OutgoingFile {
+    pub(crate) path: PathBuf,
+    pub(crate) target_address: String,
+}
+
+/// Files from the watch folder waiting to be sent
+#[derive(Default)]
+pub(crate) struct SendQueue {
+    files: VecDeque<OutgoingFile>,
+    /// Files which are queued or currently being sent
+    pending: HashSet<PathBuf>,
+    /// Failed transfers by file, reset once the file is sent
+    failures: HashMap<PathBuf, u32>,
+    /// Failed files the watcher tracks again after the delay
+    retries: Vec<(PathBuf, Duration)>,
+}
+
+impl SendQueue {
+    fn push(&mut self, file: OutgoingFile) -> bool {
+        if !self.pending.insert(file.path.clone()) {
+            return false;
+        }
+        self.files.push_back(file);
+        true
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.pending.contains(path)
+    }
+
+    /// Next file to send. It stays pending until [complete] is called.
+    pub(crate) fn pop(&mut self) -> Option<OutgoingFile> {
+        self.files.pop_front()
+    }
+
+    /// Schedules a retry of a file whose transfer failed and returns the delay before it is sent again
+    fn retry(&mut self, path: &Path) -> Duration {
+        let failures = self.failures.entry(path.to_path_buf()).or_default();
+        *failures += 1;
+        let delay = RETRY_DELAY
+            .saturating_mul(1 << (*failures - 1).min(16))
+            .min(MAX_RETRY_DELAY);
+        self.retries.push((path.to_path_buf(), delay));
+        delay
+    }
+
+    fn take_retries(&mut self) -> Vec<(PathBuf, Duration)> {
+        std::mem::take(&mut self.retries)
+    }
+
+    fn clear(&mut self) {
+        self.files.clear();
+        self.pending.clear();
+        self.failures.clear();
+        self.retries.clear();
+    }
+}
+
+lazy_static! {
+    static ref SEND_QUEUE: Mutex<SendQueue> = Mutex::new(SendQueue::default());
+    static ref FOLDER_WATCHER: Mutex<Option<FolderWatcher>> = Mutex::new(None);
+}
+
+pub(crate) fn send_queue() -> &'static Mutex<SendQueue> {
+    &SEND_QUEUE
+}
+
+/// Called by [send_queued] once a file from the watch folder has been transferred or failed.
+/// Sent files are moved to the `sent` subfolder, failed files stay where they are and are sent again
+/// after a delay growing with every failure.
+pub(crate) fn complete(file: &OutgoingFile, result: Result<()>) {
+    let mut queue = send_queue().lock().unwrap();
+    queue.pending.remove(&file.path);
+
+    match result.and_then(|_| move_to_sent(&file.path)) {
+        Ok(sent_path) => {
+            queue.failures.remove(&file.path);
+            drop(queue);
+            info!(device = %file.target_address, "Sent {:?}", sent_path);
+            report_status(&file.path, WatchStatus::Sent, None);
+        }
+        Err(err) if !file.path.exists() => {
+            queue.failures.remove(&file.path);
+            drop(queue);
+            warn!(device = %file.target_address, "Sending {:?} failed: {err}, the file is gone", file.path);
+            report_status(&file.path, WatchStatus::Failed, Some(&err.to_string()));
+        }
+        Err(err) => {
+            let delay = queue.retry(&file.path);
+            drop(queue);
+            warn!(
+                device = %file.target_address,
+                "Sending {:?} failed: {err}, retrying in {}s",
+                file.path,
+                delay.as_secs()
+            );
+            report_status(&file.path, WatchStatus::Failed, Some(&err.to_string()));
+        }
+    }
+}
+
+fn move_to_sent(path: &Path) -> Result<PathBuf> {
+    let (Some(folder), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(Error::Generic(format!(
+            "Invalid watch folder file {:?}",
+            path
+        )));
+    };
+    let sent_folder = folder.join(SENT_FOLDER);
+    fs::create_dir_all(&sent_folder)?;
+    let mut target = sent_folder.join(name);
+    if target.exists() {
+        target = free_path(&target);
+    }
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Watches a single folder, not recursive, and sends the files it queues. Stopped when dropped.
+struct FolderWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    sender: tokio::task::JoinHandle<()>,
+}
+
+impl FolderWatcher {
+    fn start(config: WatchFolderConfig) -> Result<Self> {
+        fs::create_dir_all(config.path.join(SENT_FOLDER))?;
+        let inotify = Inotify::init()?;
+        inotify.watches().add(
+            &config.path,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::MODIFY,
+        )?;
+        info!(
+            "Watching {:?}, new files are sent to {}",
+            config.path, config.target_address
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let queued = Arc::new(Notify::new());
+        let thread = thread::Builder::new()
+            .name("bft-watch-folder".to_string())
+            .spawn({
+                let (stop, queued) = (stop.clone(), queued.clone());
+                move || watch(inotify, config, &stop, &queued)
+            })?;
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            sender: spawn_guarded(send_queued(queued)),
+        })
+    }
+}
+
+impl Drop for FolderWatcher {
+    fn drop(&mut self) {
+        self.sender.abort();
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(mut inotify: Inotify, config: WatchFolderConfig, stop: &AtomicBool, queued: &Notify) {
+    let mut tracker = StabilityTracker::new(
+        Duration::from_millis(config.debounce_ms),
+        Duration::from_millis(config.stable_ms),
+    );
+    let mut buffer = [0u8; 4096];
+
+    // Files dropped while the app was not running
+    scan(&mut tracker, &config.path);
+
+    while !stop.load(Ordering::Relaxed) {
+        match inotify.read_events(&mut buffer) {
+            Ok(events) => {
+                let mut overflow = false;
+                for event in events {
+                    if event.mask.contains(EventMask::Q_OVERFLOW) {
+                        overflow = true;
+                        continue;
+                    }
+                    if event.mask.contains(EventMask::ISDIR) {
+                        continue;
+                    }
+                    let Some(name) = event.name else {
+                        continue;
+                    };
+                    if is_hidden(&name.to_string_lossy()) {
+                        continue;
+                    }
+                    touch(&mut tracker, config.path.join(name));
+                }
+                if overflow {
+                    warn!(
+                        "Missed events in watch folder {:?}, rescanning",
+                        config.path
+                    );
+                    scan(&mut tracker, &config.path);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => {
+                error!("Watching {:?} failed: {err}", config.path);
+                break;
+            }
+        }
+
+        let retries = send_queue().lock().unwrap().take_retries();
+        for (path, delay) in retries {
+            tracker.retry(path, delay, Instant::now());
+        }
+
+        for path in tracker.poll(Instant::now(), stat_file) {
+            let file = OutgoingFile {
+                path: path.clone(),
+                target_address: config.target_address.clone(),
+            };
+            if send_queue().lock().unwrap().push(file) {
+                info!("Queued {:?} for {}", path, config.target_address);
+                report_status(&path, WatchStatus::Queued, None);
+                queued.notify_one();
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    info!("Stopped watching {:?}", config.path);
+}
+
+/// Sends the queued files one after the other until the watcher is stopped
+async fn send_queued(queued: Arc<Notify>) {
+    loop {
+        let next = send_queue().lock().unwrap().pop();
+        let Some(file) = next else {
+            queued.notified().await;
+            continue;
+        };
+        let result = send(&file).await;
+        if let Err(err) = blocking(move || complete(&file, result)).await {
+            warn!("Completing a watch folder file failed: {err}");
+        }
+    }
+}
+
+async fn send(file: &OutgoingFile) -> Result<()> {
+    let address = Address::from_str(&file.target_address)
+        .map_err(|_| Error::Generic(format!("Invalid device address: {}", file.target_address)))?;
+    rfcomm::send_file(address, file.path.clone()).await
+}
+
+fn touch(tracker: &mut StabilityTracker, path: PathBuf) {
+    if send_queue().lock().unwrap().contains(&path) {
+        return;
+    }
+    if tracker.touch(path.clone(), Instant::now()) {
+        report_status(&path, WatchStatus::Detected, None);
+    }
+}
+
+fn scan(tracker: &mut StabilityTracker, folder: &Path) {
+    match fs::read_dir(folder) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .filter(|entry| !is_hidden(&entry.file_name().to_string_lossy()))
+            .for_each(|entry| touch(tracker, entry.path())),
+        Err(err) => warn!("Could not read watch folder {:?}: {err}", folder),
+    }
+}
+
+/// Hidden files are skipped, many programs use them for partial downloads
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// (Re)starts the watcher with the watch folder from the app config
+pub(crate) fn restart_watcher() -> Result<()> {
+    let mut watcher = FOLDER_WATCHER.lock().unwrap();
+    // Stop the old watcher before anything of the new one is queued
+    watcher.take();
+    send_queue().lock().unwrap().clear();
+
+    let Some(config) = app_config().lock().unwrap().watch_folder.clone() else {
+        return Ok(());
+    };
+    *watcher = Some(FolderWatcher::start(config)?);
+    Ok(())
+}
+
+/// Stops watching and forgets all queued files, [restart_watcher] picks them up again
+pub(crate) fn stop_watcher() {
+    FOLDER_WATCHER.lock().unwrap().take();
+    send_queue().lock().unwrap().clear();
+}
+
+fn report_status(path: &Path, status: WatchStatus, message: Option<&str>) {
+    events::emit(Event::WatchFolderStatus {
+        path: path.to_string_lossy().into_owned(),
+        status,
+        message: message.map(ToString::to_string),
+    });
+}
+
+#[no_mangle]
+pub extern "system" fn Java_de_schweizer_bft_TransferManager_setWatchFolder<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    target_address: JString<'local>,
+) {
+    jni_entry(&mut env, "TransferManager::setWatchFolder", (), |env| {
+        info!("TransferManager::setWatchFolder()");
+
+        let watch_folder = if path.is_null() || target_address.is_null() {
+            None
+        } else {
+            let path: String = env
+                .get_string(&path)
+                .expect("Getting String from env should not fail")
+                .into();
+            let target_address: String = env
+                .get_string(&target_address)
+                .expect("Getting String from env should not fail")
+                .into();
+            Some(WatchFolderConfig {
+                path: PathBuf::from(path),
+                target_address,
+                debounce_ms: default_debounce_ms(),
+                stable_ms: default_stable_ms(),
+            })
+        };
+        update_config(|config| config.watch_folder = watch_folder)
+            .and_then(|_| restart_watcher())
+            .map_err(on_error)
+            .ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_files_once_they_are_stable() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let size = Mutex::new(10u64);
+        let stat = |_: &Path| Some((*size.lock().unwrap(), SystemTime::UNIX_EPOCH));
+        let mut tracker =
+            StabilityTracker::new(Duration::from_millis(100), Duration::from_millis(500));
+        let path = PathBuf::from("/watch/a.txt");
+
+        assert!(tracker.touch(path.clone(), at(0)));
+        assert!(!tracker.touch(path.clone(), at(50)));
+        // Still within the debounce time
+        assert!(tracker.poll(at(100), stat).is_empty());
+        // First snapshot
+        assert!(tracker.poll(at(200), stat).is_empty());
+        // Still growing
+        *size.lock().unwrap() = 20;
+        assert!(tracker.poll(at(600), stat).is_empty());
+        assert!(tracker.poll(at(1000), stat).is_empty());
+        assert_eq!(tracker.poll(at(1100), stat), vec![path.clone()]);
+        assert!(tracker.poll(at(2000), stat).is_empty());
+
+        // Deleted files are dropped
+        tracker.touch(path, at(3000));
+        assert!(tracker.poll(at(3200), |_| None).is_empty());
+        assert!(tracker.poll(at(4000), stat).is_empty());
+    }
+
+    #[test]
+    fn retries_failed_files_after_a_delay() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let stat = |_: &Path| Some((10, SystemTime::UNIX_EPOCH));
+        let mut tracker =
+            StabilityTracker::new(Duration::from_millis(100), Duration::from_millis(500));
+        let path = PathBuf::from("/watch/a.txt");
+
+        tracker.retry(path.clone(), Duration::from_secs(30), at(0));
+        assert!(tracker.poll(at(1000), stat).is_empty());
+        assert!(tracker.poll(at(30_000), stat).is_empty());
+        assert_eq!(tracker.poll(at(30_500), stat), vec![path.clone()]);
+
+        let mut queue = SendQueue::default();
+        let delays: Vec<Duration> = (0..9).map(|_| queue.retry(&path)).collect();
+        assert_eq!(delays[0], RETRY_DELAY);
+        assert_eq!(delays[1], RETRY_DELAY * 2);
+        assert_eq!(delays[8], MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn moves_sent_files_to_sent_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, b"a").unwrap();
+        fs::create_dir_all(dir.path().join(SENT_FOLDER)).unwrap();
+        fs::write(dir.path().join(SENT_FOLDER).join("a.txt"), b"old").unwrap();
+
+        let file = OutgoingFile {
+            path: path.clone(),
+            target_address: "AA:BB:CC:DD:EE:FF".to_string(),
+        };
+        complete(&file, Err(Error::Generic("peer rejected".to_string())));
+        assert!(path.exists());
+        assert_eq!(
+            send_queue().lock().unwrap().take_retries(),
+            vec![(path.clone(), RETRY_DELAY)]
+        );
+
+        complete(&file, Ok(()));
+        assert!(!path.exists());
+        assert_eq!(
+            fs::read(dir.path().join(SENT_FOLDER).join("a (1).txt")).unwrap(),
+            b"a"
+        );
+    }
+}
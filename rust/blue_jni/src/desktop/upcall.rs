@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use tracing::{info, warn};
+
+use jni::errors::{Error as JniError, Result as JniResult};
+use jni::objects::{
+    GlobalRef, JMethodID, JObject, JStaticFieldID, JStaticMethodID, JValue, JValueOwned,
+};
+use jni::signature::{JavaType, Primitive, ReturnType, TypeSignature};
+use jni::sys::{jint, jlong, jvalue};
+use jni::{Executor, JNIEnv};
+
+use crate::desktop::error::{blue_error_object, Error, FailureKind};
+use crate::desktop::transfer::TransferId;
+
+use super::GLOBAL_JVM;
+
+const BLUE_MANAGER: &str = "de/schweizer/bft/BlueManager";
+const TRANSFER_MANAGER: &str = "de/schweizer/bft/TransferManager";
+const NATIVE_LOGGER: &str = "de/schweizer/bft/NativeLogger";
+pub(crate) const BLUE_ERROR: &str = "de/schweizer/bft/BlueError";
+/// Completes the `CompletableFuture` of a failed native async operation
+pub(crate) const BLUE_EXCEPTION: &str = "de/schweizer/bft/BlueException";
+/// Thrown into the awaiting coroutine when the `BlueException` itself could not be created
+pub(crate) const FALLBACK_EXCEPTION: &str = "java/lang/IllegalStateException";
+const COMPLETABLE_FUTURE: &str = "java/util/concurrent/CompletableFuture";
+
+const MESSAGE_CONSTRUCTOR: &str = "(Ljava/lang/String;)V";
+/// Constructor of the `BlueError` subclass of every [`FailureKind`], taking operation, device and message
+const FAILURE_CONSTRUCTOR: &str = "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V";
+
+/// How native code gets an instance of a class
+#[derive(Clone, Copy, Debug)]
+enum Creation {
+    /// Calls the constructor with this signature
+    Constructor(&'static str),
+    /// Kotlin `object`, its `INSTANCE` is used
+    Instance,
+}
+
+/// Subclasses of `BlueError` that native errors are converted into, besides one for every [`FailureKind`]
+const BLUE_ERROR_VARIANTS: [(&str, Creation); 8] = [
+    ("Generic", Creation::Constructor(MESSAGE_CONSTRUCTOR)),
+    ("DiscoveryNotPossible", Creation::Instance),
+    ("AdapterNotAvailable", Creation::Instance),
+    ("FileTooLarge", Creation::Constructor("(JJ)V")),
+    ("InsufficientSpace", Creation::Constructor("(JJ)V")),
+    (
+        "VerificationFailed",
+        Creation::Constructor(MESSAGE_CONSTRUCTOR),
+    ),
+    (
+        "FileQuarantined",
+        Creation::Constructor("(Ljava/lang/String;Ljava/lang/String;)V"),
+    ),
+    ("Internal", Creation::Constructor(MESSAGE_CONSTRUCTOR)),
+];
+
+/// Classes native code creates instances of
+fn creations() -> impl Iterator<Item = (String, Creation)> {
+    let variants = BLUE_ERROR_VARIANTS
+        .into_iter()
+        .chain(
+            FailureKind::ALL.map(|kind| (kind.name(), Creation::Constructor(FAILURE_CONSTRUCTOR))),
+        )
+        .map(|(variant, creation)| (format!("{BLUE_ERROR}${variant}"), creation));
+    let exceptions = [
+        (
+            BLUE_EXCEPTION,
+            Creation::Constructor("(Lde/schweizer/bft/BlueError;)V"),
+        ),
+        (
+            FALLBACK_EXCEPTION,
+            Creation::Constructor(MESSAGE_CONSTRUCTOR),
+        ),
+    ]
+    .map(|(class, creation)| (class.to_string(), creation));
+    variants.chain(exceptions)
+}
+
+/// Kotlin method called from native code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Method {
+    OnDeviceDiscovered,
+    OnDeviceUpdated,
+    OnDeviceLost,
+    OnDiscoveryStopped,
+    OnError,
+    UpdateBluetoothState,
+    OnDiscoverableCountdown,
+    OnDiscoverableEnded,
+    OnTextReceived,
+    OnWatchFolderStatus,
+    OnTransferProgress,
+    OnTransferOffered,
+    OnLog,
+}
+
+impl Method {
+    const ALL: [Self; 13] = [
+        Self::OnDeviceDiscovered,
+        Self::OnDeviceUpdated,
+        Self::OnDeviceLost,
+        Self::OnDiscoveryStopped,
+        Self::OnError,
+        Self::UpdateBluetoothState,
+        Self::OnDiscoverableCountdown,
+        Self::OnDiscoverableEnded,
+        Self::OnTextReceived,
+        Self::OnWatchFolderStatus,
+        Self::OnTransferProgress,
+        Self::OnTransferOffered,
+        Self::OnLog,
+    ];
+
+    /// Class, name and signature of the `@JvmStatic` method
+    fn descriptor(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::OnDeviceDiscovered => (
+                BLUE_MANAGER,
+                "onDeviceDiscovered",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+            ),
+            Self::OnDeviceUpdated => (
+                BLUE_MANAGER,
+                "onDeviceUpdated",
+                "(Ljava/lang/String;Ljava/lang/String;I)V",
+            ),
+            Self::OnDeviceLost => (BLUE_MANAGER, "onDeviceLost", "(Ljava/lang/String;)V"),
+            Self::OnDiscoveryStopped => (BLUE_MANAGER, "onDiscoveryStopped", "()V"),
+            Self::OnError => (BLUE_MANAGER, "onError", "(Lde/schweizer/bft/BlueError;)V"),
+            Self::UpdateBluetoothState => (
+                BLUE_MANAGER,
+                "updateBluetoothState",
+                "(Ljava/lang/String;)V",
+            ),
+            Self::OnDiscoverableCountdown => (BLUE_MANAGER, "onDiscoverableCountdown", "(I)V"),
+            Self::OnDiscoverableEnded => (BLUE_MANAGER, "onDiscoverableEnded", "()V"),
+            Self::OnTextReceived => (
+                TRANSFER_MANAGER,
+                "onTextReceived",
+                "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V",
+            ),
+            Self::OnWatchFolderStatus => (
+                TRANSFER_MANAGER,
+                "onWatchFolderStatus",
+                "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V",
+            ),
+            Self::OnTransferProgress => (TRANSFER_MANAGER, "onTransferProgress", "(JJJ)V"),
+            Self::OnTransferOffered => (
+                TRANSFER_MANAGER,
+                "onTransferOffered",
+                "(JLjava/lang/String;Ljava/lang/String;Ljava/lang/String;J)V",
+            ),
+            Self::OnLog => (
+                NATIVE_LOGGER,
+                "onLog",
+                "(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V",
+            ),
+        }
+    }
+}
+
+/// Call of a Kotlin method together with its arguments
+#[derive(Debug)]
+pub(crate) enum Upcall<'a> {
+    DeviceDiscovered {
+        name: &'a str,
+        address: &'a str,
+    },
+    /// A missing RSSI is passed as `Int.MIN_VALUE`
+    DeviceUpdated {
+        address: &'a str,
+        name: Option<&'a str>,
+        rssi: Option<i16>,
+    },
+    DeviceLost {
+        address: &'a str,
+    },
+    DiscoveryStopped,
+    Error(Error),
+    BluetoothState(&'a str),
+    DiscoverableCountdown(u32),
+    DiscoverableEnded,
+    TextReceived {
+        sender: &'a str,
+        text: &'a str,
+        kind: &'a str,
+    },
+    WatchFolderStatus {
+        path: &'a str,
+        status: &'a str,
+        message: Option<&'a str>,
+    },
+    TransferProgress {
+        transfer_id: TransferId,
+        transferred: u64,
+        total: u64,
+    },
+    /// The user has to accept or reject an offer through `respondToOffer`
+    TransferOffered {
+        transfer_id: TransferId,
+        sender: &'a str,
+        sender_name: Option<&'a str>,
+        file_name: &'a str,
+        size: u64,
+    },
+    /// `location` is `file:line` of the log statement
+    Log {
+        level: &'a str,
+        target: &'a str,
+        message: &'a str,
+        location: Option<&'a str>,
+    },
+}
+
+impl Upcall<'_> {
+    fn method(&self) -> Method {
+        match self {
+            Self::DeviceDiscovered { .. } => Method::OnDeviceDiscovered,
+            Self::DeviceUpdated { .. } => Method::OnDeviceUpdated,
+            Self::DeviceLost { .. } => Method::OnDeviceLost,
+            Self::DiscoveryStopped => Method::OnDiscoveryStopped,
+            Self::Error(_) => Method::OnError,
+            Self::BluetoothState(_) => Method::UpdateBluetoothState,
+            Self::DiscoverableCountdown(_) => Method::OnDiscoverableCountdown,
+            Self::DiscoverableEnded => Method::OnDiscoverableEnded,
+            Self::TextReceived { .. } => Method::OnTextReceived,
+            Self::WatchFolderStatus { .. } => Method::OnWatchFolderStatus,
+            Self::TransferProgress { .. } => Method::OnTransferProgress,
+            Self::TransferOffered { .. } => Method::OnTransferOffered,
+            Self::Log { .. } => Method::OnLog,
+        }
+    }
+
+    /// Converts the arguments in the order of the method signature
+    fn arguments<'local>(self, env: &mut JNIEnv<'local>) -> JniResult<Vec<JValueOwned<'local>>> {
+        let arguments = match self {
+            Self::DeviceDiscovered { name, address } => {
+                vec![string(env, name)?, string(env, address)?]
+            }
+            Self::DeviceUpdated {
+                address,
+                name,
+                rssi,
+            } => vec![
+                string(env, address)?,
+                optional_string(env, name)?,
+                JValueOwned::from(rssi.map_or(jint::MIN, jint::from)),
+            ],
+            Self::DeviceLost { address } => vec![string(env, address)?],
+            Self::DiscoveryStopped | Self::DiscoverableEnded => vec![],
+            Self::Error(error) => vec![blue_error_object(env, error)?.into()],
+            Self::BluetoothState(state) => vec![string(env, state)?],
+            Self::DiscoverableCountdown(remaining_secs) => {
+                vec![JValueOwned::from(remaining_secs as jint)]
+            }
+            Self::TextReceived { sender, text, kind } => {
+                vec![string(env, sender)?, string(env, text)?, string(env, kind)?]
+            }
+            Self::WatchFolderStatus {
+                path,
+                status,
+                message,
+            } => {
+                let message = optional_string(env, message)?;
+                vec![string(env, path)?, string(env, status)?, message]
+            }
+            Self::TransferProgress {
+                transfer_id,
+                transferred,
+                total,
+            } => vec![
+                JValueOwned::from(transfer_id as jlong),
+                JValueOwned::from(transferred as jlong),
+                JValueOwned::from(total as jlong),
+            ],
+            Self::TransferOffered {
+                transfer_id,
+                sender,
+                sender_name,
+                file_name,
+                size,
+            } => vec![
+                JValueOwned::from(transfer_id as jlong),
+                string(env, sender)?,
+                optional_string(env, sender_name)?,
+                string(env, file_name)?,
+                JValueOwned::from(size as jlong),
+            ],
+            Self::Log {
+                level,
+                target,
+                message,
+                location,
+            } => {
+                let location = optional_string(env, location)?;
+                vec![
+                    string(env, level)?,
+                    string(env, target)?,
+                    string(env, message)?,
+                    location,
+                ]
+            }
+        };
+        Ok(arguments)
+    }
+}
+
+fn string<'local>(env: &mut JNIEnv<'local>, value: &str) -> JniResult<JValueOwned<'local>> {
+    Ok(JObject::from(env.new_string(value)?).into())
+}
+
+fn optional_string<'local>(
+    env: &mut JNIEnv<'local>,
+    value: Option<&str>,
+) -> JniResult<JValueOwned<'local>> {
+    match value {
+        Some(value) => string(env, value),
+        None => Ok(JObject::null().into()),
+    }
+}
+
+/// Constructor resolved by [`init`] with the argument types of its signature
+struct Constructor {
+    id: JMethodID,
+    arguments: Vec<JavaType>,
+}
+
+/// Classes as global references and method IDs, resolved once by [`init`]
+struct Cache {
+    classes: HashMap<String, GlobalRef>,
+    methods: HashMap<Method, JStaticMethodID>,
+    constructors: HashMap<String, Constructor>,
+    instances: HashMap<String, JStaticFieldID>,
+    /// `CompletableFuture.complete`
+    complete: JMethodID,
+    /// `CompletableFuture.completeExceptionally`
+    complete_exceptionally: JMethodID,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+/// Resolves all classes and methods used by upcalls. Native threads only see the system class loader,
+/// so this has to run on a thread called from Kotlin.
+pub(crate) fn init(env: &mut JNIEnv) -> JniResult<()> {
+    if CACHE.get().is_some() {
+        return Ok(());
+    }
+
+    let class_names = [
+        BLUE_MANAGER,
+        TRANSFER_MANAGER,
+        NATIVE_LOGGER,
+        COMPLETABLE_FUTURE,
+    ]
+    .map(str::to_string)
+    .into_iter()
+    .chain(creations().map(|(name, _)| name));
+    let mut classes = HashMap::new();
+    for name in class_names {
+        let class = env.find_class(&name)?;
+        classes.insert(name, env.new_global_ref(class)?);
+    }
+
+    let mut methods = HashMap::new();
+    for method in Method::ALL {
+        let (class, name, signature) = method.descriptor();
+        let id = env.get_static_method_id(&classes[class], name, signature)?;
+        methods.insert(method, id);
+    }
+
+    let mut constructors = HashMap::new();
+    let mut instances = HashMap::new();
+    for (name, creation) in creations() {
+        match creation {
+            Creation::Constructor(signature) => {
+                let id = env.get_method_id(&classes[&name], "<init>", signature)?;
+                let arguments = TypeSignature::from_str(signature)?.args;
+                constructors.insert(name, Constructor { id, arguments });
+            }
+            Creation::Instance => {
+                let signature = format!("L{name};");
+                let id = env.get_static_field_id(&classes[&name], "INSTANCE", signature)?;
+                instances.insert(name, id);
+            }
+        }
+    }
+
+    let future = &classes[COMPLETABLE_FUTURE];
+    let complete = env.get_method_id(future, "complete", "(Ljava/lang/Object;)Z")?;
+    let complete_exceptionally =
+        env.get_method_id(future, "completeExceptionally", "(Ljava/lang/Throwable;)Z")?;
+
+    info!(
+        "Resolved {} classes, {} methods and {} constructors for upcalls",
+        classes.len(),
+        methods.len() + 2,
+        constructors.len()
+    );
+    let _ = CACHE.set(Cache {
+        classes,
+        methods,
+        constructors,
+        instances,
+        complete,
+        complete_exceptionally,
+    });
+    Ok(())
+}
+
+fn cache() -> JniResult<&'static Cache> {
+    CACHE
+        .get()
+        .ok_or(JniError::NullPtr("Upcalls are not initialized"))
+}
+
+/// Creates an object of a class resolved by [`init`] with its cached constructor.
+/// Arguments that do not match the constructor signature are refused.
+pub(crate) fn new_object<'local>(
+    env: &mut JNIEnv<'local>,
+    class: &str,
+    arguments: &[JValue],
+) -> JniResult<JObject<'local>> {
+    let cache = cache()?;
+    let (Some(class_ref), Some(constructor)) =
+        (cache.classes.get(class), cache.constructors.get(class))
+    else {
+        return Err(JniError::NullPtr(
+            "Constructor was not resolved for upcalls",
+        ));
+    };
+    let matches = |(argument, java_type): (&JValue, &JavaType)| match (
+        argument.primitive_type(),
+        java_type,
+    ) {
+        (None, JavaType::Object(_) | JavaType::Array(_)) => true,
+        (Some(primitive), JavaType::Primitive(expected)) => primitive == *expected,
+        _ => false,
+    };
+    if arguments.len() != constructor.arguments.len()
+        || !arguments.iter().zip(&constructor.arguments).all(matches)
+    {
+        return Err(JniError::InvalidArgList(TypeSignature {
+            args: constructor.arguments.clone(),
+            ret: ReturnType::Primitive(Primitive::Void),
+        }));
+    }
+    let arguments: Vec<jvalue> = arguments.iter().map(JValue::as_jni).collect();
+    // SAFETY: The constructor was resolved from this class and the arguments were checked against its signature
+    unsafe { env.new_object_unchecked(class_ref, constructor.id, &arguments) }
+}
+
+/// `INSTANCE` of a Kotlin `object` resolved by [`init`]
+pub(crate) fn instance<'local>(
+    env: &mut JNIEnv<'local>,
+    class: &str,
+) -> JniResult<JObject<'local>> {
+    let cache = cache()?;
+    let (Some(class_ref), Some(field)) = (cache.classes.get(class), cache.instances.get(class))
+    else {
+        return Err(JniError::NullPtr("Instance was not resolved for upcalls"));
+    };
+    env.get_static_field_unchecked(class_ref, *field, JavaType::Object(class.to_string()))?
+        .l()
+}
+
+/// Completes a `CompletableFuture` with `value`, returns whether this completed it
+pub(crate) fn complete_future(
+    env: &mut JNIEnv,
+    future: &JObject,
+    value: &JObject,
+) -> JniResult<bool> {
+    let cache = cache()?;
+    // SAFETY: `complete` was resolved from `CompletableFuture` and takes a single object
+    unsafe {
+        env.call_method_unchecked(
+            future,
+            cache.complete,
+            ReturnType::Primitive(Primitive::Boolean),
+            &[JValue::from(value).as_jni()],
+        )
+    }?
+    .z()
+}
+
+/// Completes a `CompletableFuture` exceptionally with `exception`, returns whether this completed it
+pub(crate) fn complete_future_exceptionally(
+    env: &mut JNIEnv,
+    future: &JObject,
+    exception: &JObject,
+) -> JniResult<bool> {
+    let cache = cache()?;
+    // SAFETY: `completeExceptionally` was resolved from `CompletableFuture` and takes a single throwable
+    unsafe {
+        env.call_method_unchecked(
+            future,
+            cache.complete_exceptionally,
+            ReturnType::Primitive(Primitive::Boolean),
+            &[JValue::from(exception).as_jni()],
+        )
+    }?
+    .z()
+}
+
+/// Whether upcalls reach Kotlin, which is the case after [`init`]
+pub(crate) fn is_ready() -> bool {
+    GLOBAL_JVM.get().is_some() && CACHE.get().is_some()
+}
+
+/// Calls the Kotlin method of the upcall. Does nothing before [`init`], e.g. in tests.
+pub(crate) fn call(upcall: Upcall) {
+    call_all([upcall]);
+}
+
+/// Calls the Kotlin methods of several upcalls in order, attaching the thread only once
+pub(crate) fn call_all<'a>(upcalls: impl IntoIterator<Item = Upcall<'a>>) {
+    let (Some(jvm), Some(cache)) = (GLOBAL_JVM.get(), CACHE.get()) else {
+        return;
+    };
+
+    let exec = Executor::new(jvm.clone());
+    let result = exec.with_attached(|env| -> JniResult<()> {
+        for upcall in upcalls {
+            let method = upcall.method();
+            let (class, _, _) = method.descriptor();
+            // Every upcall gets its own local frame, so a large batch does not exhaust local references
+            let result = env.with_local_frame(4, |env| -> JniResult<()> {
+                let arguments = upcall.arguments(env)?;
+                let arguments: Vec<jvalue> =
+                    arguments.iter().map(|argument| argument.as_jni()).collect();
+                // SAFETY: The method ID was resolved from this class and the arguments follow its signature
+                let result = unsafe {
+                    env.call_static_method_unchecked(
+                        &cache.classes[class],
+                        cache.methods[&method],
+                        ReturnType::Primitive(Primitive::Void),
+                        &arguments,
+                    )
+                };
+                // An exception thrown by Kotlin must not stay pending on a native thread
+                if result.is_err() && env.exception_check()? {
+                    env.exception_describe()?;
+                    env.exception_clear()?;
+                }
+                result.map(|_| ())
+            });
+            if let Err(err) = result {
+                warn!("Calling {method:?} failed: {err}");
+            }
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        warn!("Attaching to the JVM for upcalls failed: {err}");
+    }
+}
@@ -1,8 +1,11 @@
 use std::env;
 use std::fs::File;
-use std::io::{ErrorKind, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fs, io};
 
 pub fn current_dir() -> PathBuf {
@@ -15,14 +18,14 @@ pub fn current_dir() -> PathBuf {
 }
 
 pub fn create_dir(path: &Path) {
-    fs::create_dir_all(&path).unwrap_or_else(|error| match error.kind() {
+    fs::create_dir_all(path).unwrap_or_else(|error| match error.kind() {
         ErrorKind::AlreadyExists => {}
         _ => panic!("Could not create directory: {}", error),
     });
 }
 
 pub fn create_file(file: &Path) -> File {
-    File::create(&file).expect("Could not create file")
+    File::create(file).expect("Could not create file")
 }
 
 pub fn copy_file(from: &Path, to: &Path) {
@@ -42,7 +45,7 @@ pub struct CommandConfig<'a> {
     pub args: Vec<&'a str>,
 }
 
-pub fn run_command(command: &CommandConfig) {
+pub fn run_command(command: &CommandConfig) -> ExitStatus {
     let mut command_with_args = Command::new(command.command);
     for arg in &command.args {
         command_with_args.arg(arg);
@@ -53,14 +56,131 @@ pub fn run_command(command: &CommandConfig) {
         .expect("Failed to execute command");
     io::stdout().write_all(&output.stdout).unwrap();
     io::stderr().write_all(&output.stderr).unwrap();
+    output.status
+}
+
+pub struct CommandOutput {
+    /// `None` if the command was killed because it exceeded its timeout
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn timed_out(&self) -> bool {
+        self.status.is_none()
+    }
+}
+
+/// Runs a command with additional environment variables and captures its output instead of echoing it.
+/// The command runs in its own process group, which is killed if the command or anything it started
+/// still runs or holds its output open after `timeout`.
+pub fn run_command_with_timeout(
+    command: &CommandConfig,
+    envs: &[(&str, &str)],
+    timeout: Duration,
+) -> io::Result<CommandOutput> {
+    let mut child = Command::new(command.command)
+        .args(&command.args)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    fn read_to_string(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).into_owned()
+        })
+    }
+    let stdout = read_to_string(child.stdout.take().expect("stdout should be piped"));
+    let stderr = read_to_string(child.stderr.take().expect("stderr should be piped"));
+
+    let deadline = Instant::now() + timeout;
+    let mut exit_status = None;
+    let status = loop {
+        if exit_status.is_none() {
+            exit_status = child.try_wait()?;
+        }
+        // Background processes of the command keep the pipes open
+        if exit_status.is_some() && stdout.is_finished() && stderr.is_finished() {
+            break exit_status;
+        }
+        if Instant::now() >= deadline {
+            // SAFETY: kill has no memory safety requirements, the negative pid addresses the process group
+            // the command leads, the group exists until the command is reaped
+            if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err);
+                }
+            }
+            if exit_status.is_none() {
+                child.wait()?;
+            }
+            break None;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    Ok(CommandOutput {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
 
     #[test]
     fn it_works() {
         assert_eq!(true, true);
     }
+
+    #[test]
+    fn run_command_with_timeout_captures_output() {
+        let command = CommandConfig {
+            command: "sh",
+            args: vec!["-c", "echo $GREETING; echo oops >&2; exit 3"],
+        };
+        let output =
+            run_command_with_timeout(&command, &[("GREETING", "hello")], Duration::from_secs(5))
+                .unwrap();
+        assert_eq!(output.status.and_then(|status| status.code()), Some(3));
+        assert_eq!(output.stdout, "hello\n");
+        assert_eq!(output.stderr, "oops\n");
+
+        let command = CommandConfig {
+            command: "sleep",
+            args: vec!["5"],
+        };
+        let output = run_command_with_timeout(&command, &[], Duration::from_millis(100)).unwrap();
+        assert!(output.timed_out());
+    }
+
+    #[test]
+    fn run_command_with_timeout_kills_child_processes() {
+        let start = Instant::now();
+        let command = CommandConfig {
+            command: "sh",
+            args: vec!["-c", "sleep 60; echo done"],
+        };
+        let output = run_command_with_timeout(&command, &[], Duration::from_millis(200)).unwrap();
+        assert!(output.timed_out());
+
+        // Exits right away, but the background sleep holds on to stdout
+        let command = CommandConfig {
+            command: "sh",
+            args: vec!["-c", "sleep 60 & echo started"],
+        };
+        let output = run_command_with_timeout(&command, &[], Duration::from_millis(200)).unwrap();
+        assert!(output.timed_out());
+        assert_eq!(output.stdout, "started\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
 }